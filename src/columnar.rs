@@ -0,0 +1,354 @@
+//! Columnar export of database tables (Apache Parquet and Arrow IPC)
+//!
+//! Tables are read from `ScanDatabase` in batches and written with typed
+//! columns: `INTEGER` columns become 64-bit integers (flag columns such as
+//! `is_partial_hash` become booleans) and `TEXT` columns UTF-8 strings.
+//! Times keep their database units (Unix seconds, or nanoseconds for the
+//! `*_ns` columns).
+//!
+//! Batches are converted to Arrow record batches and written with the
+//! `parquet` crate's `ArrowWriter` (one uncompressed row group per batch) or
+//! the `arrow-ipc` crate's `FileWriter` (the Feather v2 format, one record
+//! batch per batch).
+
+use arrow_array::{ArrayRef, BooleanArray, Int64Array, RecordBatch, StringArray};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef};
+use parquet::arrow::ArrowWriter;
+use parquet::file::properties::WriterProperties;
+use rusqlite::types::Value as SqlValue;
+use std::fmt::Display;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::db::{ScanDatabase, TableColumn, EXPORT_TABLES};
+use crate::error::ScanError;
+
+/// Default number of rows per Parquet row group / Arrow record batch
+pub const DEFAULT_BATCH_ROWS: usize = 65536;
+
+/// `INTEGER` columns holding 0/1 flags, exported as booleans
+const BOOL_COLUMNS: [&str; 4] = [
+    "is_partial_hash",
+    "is_broken_link",
+    "incremental",
+    "cancelled",
+];
+
+/// Columnar file format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnarFormat {
+    /// Apache Parquet
+    Parquet,
+    /// Arrow IPC file format (Feather v2)
+    Arrow,
+}
+
+impl ColumnarFormat {
+    /// Get string representation
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ColumnarFormat::Parquet => "parquet",
+            ColumnarFormat::Arrow => "arrow",
+        }
+    }
+
+    /// File extension of exported tables
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ColumnarFormat::Parquet => "parquet",
+            ColumnarFormat::Arrow => "arrow",
+        }
+    }
+}
+
+impl std::str::FromStr for ColumnarFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "parquet" => Ok(ColumnarFormat::Parquet),
+            "arrow" | "ipc" | "feather" => Ok(ColumnarFormat::Arrow),
+            _ => Err(format!(
+                "invalid export format {:?} (expected parquet or arrow)",
+                s
+            )),
+        }
+    }
+}
+
+/// Arrow schema of a database table
+///
+/// `INTEGER` columns become `Int64` (or `Boolean` for the flag columns) and
+/// everything else `Utf8`; nullability follows the table definition.
+pub fn table_schema(columns: &[TableColumn]) -> Schema {
+    let fields: Vec<Field> = columns
+        .iter()
+        .map(|column| {
+            let ty = if BOOL_COLUMNS.contains(&column.name.as_str()) {
+                DataType::Boolean
+            } else if column.decl_type.contains("INT") {
+                DataType::Int64
+            } else {
+                DataType::Utf8
+            };
+            Field::new(&column.name, ty, column.nullable)
+        })
+        .collect();
+    Schema::new(fields)
+}
+
+/// Convert database rows to a record batch of `schema`
+///
+/// Values that do not fit the column type (which SQLite allows) become null;
+/// a null in a non-nullable column is an error.
+pub fn to_record_batch(
+    schema: &SchemaRef,
+    rows: &[Vec<SqlValue>],
+) -> Result<RecordBatch, ArrowError> {
+    let columns = schema
+        .fields()
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            let values = rows.iter().map(|row| &row[i]);
+            let column: ArrayRef = match field.data_type() {
+                DataType::Boolean => Arc::new(
+                    values
+                        .map(|v| sql_int(v).map(|n| n != 0))
+                        .collect::<BooleanArray>(),
+                ),
+                DataType::Int64 => Arc::new(values.map(sql_int).collect::<Int64Array>()),
+                _ => Arc::new(values.map(sql_text).collect::<StringArray>()),
+            };
+            column
+        })
+        .collect();
+    RecordBatch::try_new(Arc::clone(schema), columns)
+}
+
+fn sql_int(value: &SqlValue) -> Option<i64> {
+    match value {
+        SqlValue::Integer(n) => Some(*n),
+        SqlValue::Real(f) => Some(*f as i64),
+        SqlValue::Text(s) => s.trim().parse().ok(),
+        SqlValue::Null | SqlValue::Blob(_) => None,
+    }
+}
+
+fn sql_text(value: &SqlValue) -> Option<String> {
+    match value {
+        SqlValue::Text(s) => Some(s.clone()),
+        SqlValue::Integer(n) => Some(n.to_string()),
+        SqlValue::Real(f) => Some(f.to_string()),
+        SqlValue::Blob(b) => Some(String::from_utf8_lossy(b).into_owned()),
+        SqlValue::Null => None,
+    }
+}
+
+/// A table written to a file by `export_database`
+#[derive(Debug, Clone)]
+pub struct ExportedTable {
+    pub table: String,
+    pub path: PathBuf,
+    pub rows: u64,
+}
+
+/// Write `tables` (all `EXPORT_TABLES` when empty) to `dir`, one file per table
+pub fn export_database(
+    db: &ScanDatabase,
+    dir: &Path,
+    format: ColumnarFormat,
+    tables: &[String],
+    batch_rows: usize,
+) -> Result<Vec<ExportedTable>, ScanError> {
+    let tables: Vec<String> = if tables.is_empty() {
+        EXPORT_TABLES.iter().map(|t| t.to_string()).collect()
+    } else {
+        tables.to_vec()
+    };
+    if let Some(unknown) = tables.iter().find(|t| !EXPORT_TABLES.contains(&t.as_str())) {
+        return Err(ScanError::config_error(
+            None,
+            format!(
+                "unknown table {:?} (expected {})",
+                unknown,
+                EXPORT_TABLES.join(", ")
+            ),
+        ));
+    }
+    std::fs::create_dir_all(dir)
+        .map_err(|e| ScanError::io_error(Some(dir.to_path_buf()), e.to_string()))?;
+
+    tables
+        .iter()
+        .map(|table| {
+            let path = dir.join(format!("{}.{}", table, format.extension()));
+            let rows = export_table(db, table, &path, format, batch_rows)?;
+            Ok(ExportedTable {
+                table: table.clone(),
+                path,
+                rows,
+            })
+        })
+        .collect()
+}
+
+/// Write one table to `path`, returning the number of rows written
+pub fn export_table(
+    db: &ScanDatabase,
+    table: &str,
+    path: &Path,
+    format: ColumnarFormat,
+    batch_rows: usize,
+) -> Result<u64, ScanError> {
+    let schema: SchemaRef = Arc::new(table_schema(&db.table_columns(table)?));
+    let file = File::create(path).map_err(write_error(path))?;
+
+    let rows = match format {
+        ColumnarFormat::Parquet => {
+            let props = WriterProperties::builder()
+                .set_max_row_group_size(batch_rows.max(1))
+                .build();
+            let mut writer = ArrowWriter::try_new(file, Arc::clone(&schema), Some(props))
+                .map_err(write_error(path))?;
+            let rows = db.read_table(table, batch_rows, |rows| {
+                let batch = to_record_batch(&schema, &rows).map_err(write_error(path))?;
+                writer.write(&batch).map_err(write_error(path))?;
+                // Close the row group so each batch gets its own
+                writer.flush().map_err(write_error(path))
+            })?;
+            writer.close().map_err(write_error(path))?;
+            rows
+        }
+        ColumnarFormat::Arrow => {
+            let mut writer =
+                FileWriter::try_new_buffered(file, &schema).map_err(write_error(path))?;
+            let rows = db.read_table(table, batch_rows, |rows| {
+                let batch = to_record_batch(&schema, &rows).map_err(write_error(path))?;
+                writer.write(&batch).map_err(write_error(path))
+            })?;
+            writer.finish().map_err(write_error(path))?;
+            rows
+        }
+    };
+    Ok(rows)
+}
+
+/// Map a writer error to an I/O error on `path`
+fn write_error<E: Display>(path: &Path) -> impl Fn(E) -> ScanError + '_ {
+    move |e| ScanError::io_error(Some(path.to_path_buf()), e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::ScanRecord;
+    use crate::models::ScanResult;
+    use arrow_array::Array;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    fn columns() -> Vec<TableColumn> {
+        let column = |name: &str, decl_type: &str, nullable| TableColumn {
+            name: name.to_string(),
+            decl_type: decl_type.to_string(),
+            nullable,
+            primary_key: false,
+        };
+        vec![
+            column("id", "INTEGER", false),
+            column("name", "TEXT", true),
+            column("cancelled", "INTEGER", true),
+        ]
+    }
+
+    #[test]
+    fn test_to_record_batch() {
+        let schema = Arc::new(table_schema(&columns()));
+        let types: Vec<&DataType> = schema.fields().iter().map(|f| f.data_type()).collect();
+        assert_eq!(
+            types,
+            [&DataType::Int64, &DataType::Utf8, &DataType::Boolean]
+        );
+
+        let rows = vec![
+            vec![
+                SqlValue::Integer(1),
+                SqlValue::Text("x".into()),
+                SqlValue::Integer(1),
+            ],
+            vec![
+                SqlValue::Integer(2),
+                SqlValue::Null,
+                SqlValue::Text("no".into()),
+            ],
+        ];
+        let batch = to_record_batch(&schema, &rows).unwrap();
+        assert_eq!(batch.num_rows(), 2);
+        let names = batch
+            .column(1)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(names.value(0), "x");
+        assert!(names.is_null(1));
+        let flags = batch
+            .column(2)
+            .as_any()
+            .downcast_ref::<BooleanArray>()
+            .unwrap();
+        assert!(flags.value(0));
+        assert!(flags.is_null(1));
+
+        let rows = vec![vec![SqlValue::Null, SqlValue::Null, SqlValue::Null]];
+        assert!(to_record_batch(&schema, &rows).is_err());
+    }
+
+    #[test]
+    fn test_columnar_format_from_str() {
+        assert_eq!("Feather".parse(), Ok(ColumnarFormat::Arrow));
+        assert!("orc".parse::<ColumnarFormat>().is_err());
+    }
+
+    #[test]
+    fn test_export_database() {
+        let mut db = ScanDatabase::open_memory().unwrap();
+        for _ in 0..3 {
+            let record = ScanRecord::from_result(&ScanResult::default(), &[], true);
+            db.record_scan(&record).unwrap();
+        }
+        let dir = tempfile::tempdir().unwrap();
+
+        let tables = export_database(&db, dir.path(), ColumnarFormat::Parquet, &[], 2).unwrap();
+        assert_eq!(tables.len(), EXPORT_TABLES.len());
+        let scans = tables.iter().find(|t| t.table == "scans").unwrap();
+        assert_eq!(scans.rows, 3);
+        let reader =
+            ParquetRecordBatchReaderBuilder::try_new(File::open(&scans.path).unwrap()).unwrap();
+        assert_eq!(reader.metadata().num_row_groups(), 2);
+        let batches: Vec<RecordBatch> = reader.build().unwrap().map(Result::unwrap).collect();
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 3);
+        let schema = batches[0].schema();
+        let incremental = schema.field_with_name("incremental").unwrap();
+        assert_eq!(incremental.data_type(), &DataType::Boolean);
+
+        let tables = ["scans".to_string()];
+        let exported = export_database(&db, dir.path(), ColumnarFormat::Arrow, &tables, 2).unwrap();
+        let reader =
+            arrow_ipc::reader::FileReader::try_new(File::open(&exported[0].path).unwrap(), None)
+                .unwrap();
+        assert_eq!(reader.num_batches(), 2);
+        let rows: usize = reader.map(|b| b.unwrap().num_rows()).sum();
+        assert_eq!(rows, 3);
+
+        assert!(export_database(
+            &db,
+            dir.path(),
+            ColumnarFormat::Arrow,
+            &["nope".to_string()],
+            16
+        )
+        .is_err());
+    }
+}
@@ -1,8 +1,10 @@
 //! Configuration for the media scanner
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
 use std::collections::HashSet;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+use crate::error::ScanError;
 
 /// Default large file threshold (100 MB)
 pub const DEFAULT_LARGE_FILE_THRESHOLD: u64 = 100 * 1024 * 1024;
@@ -19,19 +21,414 @@ pub const DEFAULT_MAX_DEPTH: usize = 3;
 /// Default progress reporting interval in milliseconds
 pub const DEFAULT_PROGRESS_INTERVAL_MS: u64 = 200;
 
+/// Default full verification interval for the directory shortcut (7 days)
+pub const DEFAULT_DIR_VERIFY_INTERVAL_SECS: u64 = 7 * 24 * 60 * 60;
+
+/// Name of the per-directory ignore file read during scans
+pub const IGNORE_FILE_NAME: &str = ".mediaignore";
+
+/// Include/exclude patterns matched against paths relative to a scan root
+///
+/// Patterns use gitignore syntax (`*.part`, `**/Samples/**`, `!keep.mp4`).
+/// A pattern prefixed with `re:` is a regular expression matched against
+/// the relative path with forward slashes.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PathRules {
+    /// Only files matching at least one of these are scanned (empty = all)
+    pub include: Vec<String>,
+    /// Files and directories matching any of these are skipped
+    pub exclude: Vec<String>,
+}
+
+impl PathRules {
+    /// Check if no patterns are configured
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Append the patterns of another rule set
+    pub fn extend(&mut self, other: &PathRules) {
+        self.include.extend(other.include.iter().cloned());
+        self.exclude.extend(other.exclude.iter().cloned());
+    }
+}
+
+/// Rules that only apply below one root directory
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RootRules {
+    /// Root directory the rules belong to (must match an entry in `roots`)
+    pub root: PathBuf,
+    /// Include/exclude patterns for this root
+    #[serde(flatten)]
+    pub rules: PathRules,
+}
+
+/// Format of a configuration file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    /// TOML document
+    Toml,
+    /// JSON document
+    Json,
+}
+
+impl ConfigFormat {
+    /// Infer the format from a file extension (`.toml` or `.json`)
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase())
+            .as_deref()
+        {
+            Some("toml") => Some(ConfigFormat::Toml),
+            Some("json") => Some(ConfigFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Policy for entries whose name starts with `.`
+///
+/// Never applies to the configured roots themselves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HiddenPolicy {
+    /// Skip hidden directories, keep hidden files
+    #[default]
+    Dirs,
+    /// Skip hidden files, descend into hidden directories
+    Files,
+    /// Skip both hidden directories and hidden files
+    All,
+    /// Treat hidden entries like any other
+    None,
+}
+
+impl HiddenPolicy {
+    /// Get string representation
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HiddenPolicy::Dirs => "dirs",
+            HiddenPolicy::Files => "files",
+            HiddenPolicy::All => "all",
+            HiddenPolicy::None => "none",
+        }
+    }
+
+    /// Check if hidden directories are skipped
+    pub fn skips_dirs(&self) -> bool {
+        matches!(self, HiddenPolicy::Dirs | HiddenPolicy::All)
+    }
+
+    /// Check if hidden files are skipped
+    pub fn skips_files(&self) -> bool {
+        matches!(self, HiddenPolicy::Files | HiddenPolicy::All)
+    }
+}
+
+impl std::fmt::Display for HiddenPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl std::str::FromStr for HiddenPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "dirs" => Ok(HiddenPolicy::Dirs),
+            "files" => Ok(HiddenPolicy::Files),
+            "all" => Ok(HiddenPolicy::All),
+            "none" => Ok(HiddenPolicy::None),
+            _ => Err(format!(
+                "invalid hidden policy {:?} (expected dirs, files, all or none)",
+                s
+            )),
+        }
+    }
+}
+
+/// How incremental scans decide whether a file has changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeDetection {
+    /// Compare size and mtime seconds
+    #[default]
+    Fast,
+    /// Also compare nanosecond mtime, inode change time and inode number
+    Strict,
+}
+
+impl ChangeDetection {
+    /// Get string representation
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeDetection::Fast => "fast",
+            ChangeDetection::Strict => "strict",
+        }
+    }
+}
+
+impl std::fmt::Display for ChangeDetection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl std::str::FromStr for ChangeDetection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "fast" => Ok(ChangeDetection::Fast),
+            "strict" => Ok(ChangeDetection::Strict),
+            _ => Err(format!(
+                "invalid change detection {:?} (expected fast or strict)",
+                s
+            )),
+        }
+    }
+}
+
+/// How progress messages are rendered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProgressFormat {
+    /// A live status line when writing to a terminal, JSON otherwise
+    #[default]
+    Auto,
+    /// JSON lines (the machine-readable protocol)
+    Json,
+    /// A live status line
+    Tty,
+}
+
+impl ProgressFormat {
+    /// Get string representation
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProgressFormat::Auto => "auto",
+            ProgressFormat::Json => "json",
+            ProgressFormat::Tty => "tty",
+        }
+    }
+}
+
+impl std::fmt::Display for ProgressFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl std::str::FromStr for ProgressFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(ProgressFormat::Auto),
+            "json" => Ok(ProgressFormat::Json),
+            "tty" => Ok(ProgressFormat::Tty),
+            _ => Err(format!(
+                "invalid progress format {:?} (expected auto, json or tty)",
+                s
+            )),
+        }
+    }
+}
+
+/// Order of files (and directories) in scan results
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputOrder {
+    /// By directory, then file name
+    #[default]
+    Path,
+    /// By size, smallest first, then path
+    Size,
+    /// By modification time, oldest first, then path
+    Mtime,
+    /// Walk order (filesystem dependent)
+    None,
+}
+
+impl OutputOrder {
+    /// Get string representation
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutputOrder::Path => "path",
+            OutputOrder::Size => "size",
+            OutputOrder::Mtime => "mtime",
+            OutputOrder::None => "none",
+        }
+    }
+}
+
+impl std::fmt::Display for OutputOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl std::str::FromStr for OutputOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "path" => Ok(OutputOrder::Path),
+            "size" => Ok(OutputOrder::Size),
+            "mtime" => Ok(OutputOrder::Mtime),
+            "none" => Ok(OutputOrder::None),
+            _ => Err(format!(
+                "invalid output order {:?} (expected path, size, mtime or none)",
+                s
+            )),
+        }
+    }
+}
+
+/// A modification time limit, either absolute or relative to the scan start
+///
+/// Parsed from and written as a string:
+/// - `"10m"`, `"2h"`, `"7d"`, `"30s"`, `"1w"`: an age relative to now
+/// - `"2024-01-31"` or an RFC 3339 timestamp: an absolute point in time
+/// - `"1706659200"`: an absolute Unix timestamp
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum TimeBound {
+    /// Unix timestamp in seconds
+    Absolute(i64),
+    /// Age in seconds before the scan starts
+    Age(u64),
+}
+
+impl TimeBound {
+    /// Resolve to a Unix timestamp, using `now` for relative ages
+    pub fn resolve(&self, now: i64) -> i64 {
+        match *self {
+            TimeBound::Absolute(ts) => ts,
+            TimeBound::Age(secs) => now.saturating_sub(i64::try_from(secs).unwrap_or(i64::MAX)),
+        }
+    }
+}
+
+impl std::str::FromStr for TimeBound {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(ts) = s.parse::<i64>() {
+            return Ok(TimeBound::Absolute(ts));
+        }
+        if let Ok(dt) = chrono::DateTime::parse_from_rfc3339(s) {
+            return Ok(TimeBound::Absolute(dt.timestamp()));
+        }
+        if let Ok(date) = chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+            let ts = date
+                .and_hms_opt(0, 0, 0)
+                .map(|dt| dt.and_utc().timestamp())
+                .unwrap_or(0);
+            return Ok(TimeBound::Absolute(ts));
+        }
+
+        parse_duration(s).map(TimeBound::Age).map_err(|_| {
+            format!(
+                "invalid time {:?} (expected e.g. 10m, 2h, 7d, 2024-01-31 or a Unix timestamp)",
+                s
+            )
+        })
+    }
+}
+
+impl TryFrom<String> for TimeBound {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<TimeBound> for String {
+    fn from(bound: TimeBound) -> Self {
+        match bound {
+            TimeBound::Absolute(ts) => ts.to_string(),
+            TimeBound::Age(secs) => format!("{}s", secs),
+        }
+    }
+}
+
+/// Parse a duration such as `"30s"`, `"10m"`, `"2h"`, `"7d"` or `"1w"` into seconds
+pub fn parse_duration(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let value: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration {:?}", s))?;
+    let multiplier = match unit.trim() {
+        "" | "s" => 1,
+        "m" | "min" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "invalid duration {:?} (expected e.g. 30s, 10m, 2h, 7d or 1w)",
+                s
+            ))
+        }
+    };
+    value
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("duration {:?} is too large", s))
+}
+
+/// Parse a size such as `"500"`, `"10K"`, `"5MB"` or `"1.5G"` into bytes (binary units)
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let value: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size {:?}", s))?;
+    let multiplier: u64 = match unit.trim().to_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1024,
+        "M" | "MB" | "MIB" => 1024 * 1024,
+        "G" | "GB" | "GIB" => 1024 * 1024 * 1024,
+        "T" | "TB" | "TIB" => 1024 * 1024 * 1024 * 1024,
+        _ => return Err(format!("invalid size unit in {:?}", s)),
+    };
+    Ok((value * multiplier as f64) as u64)
+}
+
 /// Configuration for the scanner
+///
+/// Missing fields take their default values when deserialized, so a config
+/// file only needs to list the settings it changes.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ScanConfig {
     /// Root directories to scan
     pub roots: Vec<PathBuf>,
 
     /// File extensions to include (whitelist)
     /// If empty, all media extensions are included
+    #[serde(serialize_with = "serialize_sorted")]
     pub extensions: HashSet<String>,
 
     /// Directory names to ignore
+    #[serde(serialize_with = "serialize_sorted")]
     pub ignore_dirs: HashSet<String>,
 
+    /// How hidden (dot-prefixed) directories and files are treated
+    pub hidden: HiddenPolicy,
+
     /// Whether to compute file hashes
     pub compute_hash: bool,
 
@@ -65,6 +462,80 @@ pub struct ScanConfig {
 
     /// Progress reporting interval in milliseconds
     pub progress_interval_ms: u64,
+
+    /// File descriptor to write progress messages to instead of stderr (Unix only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress_fd: Option<i32>,
+
+    /// File or named pipe to write progress messages to instead of stderr
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress_file: Option<PathBuf>,
+
+    /// How progress messages on stderr are rendered
+    pub progress_format: ProgressFormat,
+
+    /// Order of files and deleted paths in scan results
+    pub output_order: OutputOrder,
+
+    /// Include/exclude patterns applied to every root
+    pub rules: PathRules,
+
+    /// Additional include/exclude patterns for specific roots
+    pub root_rules: Vec<RootRules>,
+
+    /// Whether to honour `.mediaignore` files found in scanned directories
+    pub use_ignore_files: bool,
+
+    /// Skip files smaller than this many bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_size: Option<u64>,
+
+    /// Skip files larger than this many bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u64>,
+
+    /// Skip files modified before this time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_mtime: Option<TimeBound>,
+
+    /// Skip files modified after this time
+    /// A relative value such as `"10m"` skips files that may still be written
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_mtime: Option<TimeBound>,
+
+    /// File name glob patterns to skip (e.g. `"*.part"`, `"._*"`)
+    pub exclude_names: Vec<String>,
+
+    /// Whether to follow symbolic links to files and directories
+    /// Directories reached more than once (including loops) are only walked once
+    pub follow_symlinks: bool,
+
+    /// Whether to record symbolic links as entries with their target path,
+    /// including broken links
+    pub record_symlinks: bool,
+
+    /// Whether to stay on the file system of each root (do not cross mount points)
+    pub one_file_system: bool,
+
+    /// How incremental scans detect changed files
+    pub change_detection: ChangeDetection,
+
+    /// Whether incremental scans skip directories whose mtime is unchanged,
+    /// trusting the stored file records instead of listing and stating them
+    pub dir_shortcut: bool,
+
+    /// Seconds after which a skipped directory is listed in full again,
+    /// catching in-place content changes that do not touch the directory mtime
+    pub dir_verify_interval_secs: u64,
+
+    /// Keep deleted file records for this many days after each scan
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_deleted_days: Option<u64>,
+
+    /// Keep deleted file records of the last N scans after each scan
+    /// A record is purged only when neither retention rule keeps it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_deleted_runs: Option<u64>,
 }
 
 impl Default for ScanConfig {
@@ -73,6 +544,7 @@ impl Default for ScanConfig {
             roots: Vec::new(),
             extensions: Self::default_extensions(),
             ignore_dirs: Self::default_ignore_dirs(),
+            hidden: HiddenPolicy::default(),
             compute_hash: false,
             large_file_threshold: DEFAULT_LARGE_FILE_THRESHOLD,
             num_threads: 0,
@@ -83,6 +555,26 @@ impl Default for ScanConfig {
             max_depth: DEFAULT_MAX_DEPTH,
             show_progress: false,
             progress_interval_ms: DEFAULT_PROGRESS_INTERVAL_MS,
+            progress_fd: None,
+            progress_file: None,
+            progress_format: ProgressFormat::default(),
+            output_order: OutputOrder::default(),
+            rules: PathRules::default(),
+            root_rules: Vec::new(),
+            use_ignore_files: true,
+            min_size: None,
+            max_size: None,
+            min_mtime: None,
+            max_mtime: None,
+            exclude_names: Vec::new(),
+            follow_symlinks: false,
+            record_symlinks: false,
+            one_file_system: false,
+            change_detection: ChangeDetection::default(),
+            dir_shortcut: false,
+            dir_verify_interval_secs: DEFAULT_DIR_VERIFY_INTERVAL_SECS,
+            keep_deleted_days: None,
+            keep_deleted_runs: None,
         }
     }
 }
@@ -101,6 +593,47 @@ impl ScanConfig {
         ScanConfigBuilder::new()
     }
 
+    /// Load a config from a TOML or JSON file
+    ///
+    /// The format is chosen by file extension; files without a known
+    /// extension are tried as JSON first, then as TOML.
+    pub fn from_file(path: &Path) -> Result<Self, ScanError> {
+        let content = std::fs::read_to_string(path).map_err(|e| {
+            ScanError::config_error(
+                Some(path.to_path_buf()),
+                format!("Cannot read config: {}", e),
+            )
+        })?;
+
+        let parsed = match ConfigFormat::from_path(path) {
+            Some(format) => Self::from_str_with_format(&content, format),
+            None => Self::from_str_with_format(&content, ConfigFormat::Json)
+                .or_else(|_| Self::from_str_with_format(&content, ConfigFormat::Toml)),
+        };
+
+        parsed.map_err(|e| ScanError::config_error(Some(path.to_path_buf()), e.message))
+    }
+
+    /// Parse a config from a string in the given format
+    pub fn from_str_with_format(content: &str, format: ConfigFormat) -> Result<Self, ScanError> {
+        match format {
+            ConfigFormat::Toml => toml::from_str(content)
+                .map_err(|e| ScanError::config_error(None, format!("Invalid TOML config: {}", e))),
+            ConfigFormat::Json => serde_json::from_str(content)
+                .map_err(|e| ScanError::config_error(None, format!("Invalid JSON config: {}", e))),
+        }
+    }
+
+    /// Serialize the config in the given format
+    pub fn to_string_with_format(&self, format: ConfigFormat) -> Result<String, ScanError> {
+        match format {
+            ConfigFormat::Toml => toml::to_string_pretty(self)
+                .map_err(|e| ScanError::config_error(None, e.to_string())),
+            ConfigFormat::Json => serde_json::to_string_pretty(self)
+                .map_err(|e| ScanError::config_error(None, e.to_string())),
+        }
+    }
+
     /// Get the default video extensions
     pub fn default_video_extensions() -> HashSet<String> {
         [
@@ -162,16 +695,29 @@ impl ScanConfig {
         self.extensions.contains(&ext.to_lowercase())
     }
 
-    /// Check if a directory should be ignored
+    /// Check if a directory below a root should be ignored
     pub fn should_ignore_dir(&self, name: &str) -> bool {
-        // Ignore hidden directories (starting with .)
-        if name.starts_with('.') {
+        if self.hidden.skips_dirs() && name.starts_with('.') {
             return true;
         }
         // Ignore configured directories
         self.ignore_dirs.contains(name)
     }
 
+    /// Check if a file should be ignored by name
+    pub fn should_ignore_file(&self, name: &str) -> bool {
+        self.hidden.skips_files() && name.starts_with('.')
+    }
+
+    /// Get the combined rules for a root (global rules followed by root-specific ones)
+    pub fn rules_for_root(&self, root: &Path) -> PathRules {
+        let mut rules = self.rules.clone();
+        for root_rules in self.root_rules.iter().filter(|r| r.root == root) {
+            rules.extend(&root_rules.rules);
+        }
+        rules
+    }
+
     /// Get the effective number of threads
     pub fn effective_threads(&self) -> usize {
         if self.num_threads == 0 {
@@ -195,6 +741,16 @@ impl ScanConfig {
     }
 }
 
+/// Serialize a set as a sorted list so dumped configs are stable
+fn serialize_sorted<S: Serializer>(
+    set: &HashSet<String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut items: Vec<&String> = set.iter().collect();
+    items.sort();
+    serializer.collect_seq(items)
+}
+
 /// Builder for ScanConfig
 #[derive(Debug, Default)]
 pub struct ScanConfigBuilder {
@@ -237,6 +793,12 @@ impl ScanConfigBuilder {
         self
     }
 
+    /// Set the hidden entry policy
+    pub fn hidden(mut self, policy: HiddenPolicy) -> Self {
+        self.config.hidden = policy;
+        self
+    }
+
     /// Set the directories to ignore
     pub fn ignore_dirs(mut self, dirs: HashSet<String>) -> Self {
         self.config.ignore_dirs = dirs;
@@ -310,6 +872,132 @@ impl ScanConfigBuilder {
         self
     }
 
+    /// Write progress messages to an open file descriptor instead of stderr
+    pub fn progress_fd(mut self, fd: i32) -> Self {
+        self.config.progress_fd = Some(fd);
+        self
+    }
+
+    /// Write progress messages to a file or named pipe instead of stderr
+    pub fn progress_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.progress_file = Some(path.into());
+        self
+    }
+
+    /// Set how progress messages on stderr are rendered
+    pub fn progress_format(mut self, format: ProgressFormat) -> Self {
+        self.config.progress_format = format;
+        self
+    }
+
+    /// Set the order of files in scan results
+    pub fn output_order(mut self, order: OutputOrder) -> Self {
+        self.config.output_order = order;
+        self
+    }
+
+    /// Add an include pattern for all roots
+    pub fn include(mut self, pattern: impl Into<String>) -> Self {
+        self.config.rules.include.push(pattern.into());
+        self
+    }
+
+    /// Add an exclude pattern for all roots
+    pub fn exclude(mut self, pattern: impl Into<String>) -> Self {
+        self.config.rules.exclude.push(pattern.into());
+        self
+    }
+
+    /// Add include/exclude patterns for a single root
+    pub fn root_rules(mut self, root: PathBuf, rules: PathRules) -> Self {
+        self.config.root_rules.push(RootRules { root, rules });
+        self
+    }
+
+    /// Enable or disable reading `.mediaignore` files
+    pub fn use_ignore_files(mut self, enabled: bool) -> Self {
+        self.config.use_ignore_files = enabled;
+        self
+    }
+
+    /// Set the minimum file size in bytes
+    pub fn min_size(mut self, size: u64) -> Self {
+        self.config.min_size = Some(size);
+        self
+    }
+
+    /// Set the maximum file size in bytes
+    pub fn max_size(mut self, size: u64) -> Self {
+        self.config.max_size = Some(size);
+        self
+    }
+
+    /// Skip files modified before this time
+    pub fn min_mtime(mut self, bound: TimeBound) -> Self {
+        self.config.min_mtime = Some(bound);
+        self
+    }
+
+    /// Skip files modified after this time
+    pub fn max_mtime(mut self, bound: TimeBound) -> Self {
+        self.config.max_mtime = Some(bound);
+        self
+    }
+
+    /// Add a file name glob pattern to skip
+    pub fn exclude_name(mut self, pattern: impl Into<String>) -> Self {
+        self.config.exclude_names.push(pattern.into());
+        self
+    }
+
+    /// Enable or disable following symbolic links
+    pub fn follow_symlinks(mut self, enabled: bool) -> Self {
+        self.config.follow_symlinks = enabled;
+        self
+    }
+
+    /// Enable or disable recording symbolic links as entries
+    pub fn record_symlinks(mut self, enabled: bool) -> Self {
+        self.config.record_symlinks = enabled;
+        self
+    }
+
+    /// Enable or disable staying on the root's file system
+    pub fn one_file_system(mut self, enabled: bool) -> Self {
+        self.config.one_file_system = enabled;
+        self
+    }
+
+    /// Set the change detection policy for incremental scans
+    pub fn change_detection(mut self, policy: ChangeDetection) -> Self {
+        self.config.change_detection = policy;
+        self
+    }
+
+    /// Enable or disable the directory mtime shortcut for incremental scans
+    pub fn dir_shortcut(mut self, enabled: bool) -> Self {
+        self.config.dir_shortcut = enabled;
+        self
+    }
+
+    /// Set the full verification interval for the directory shortcut (seconds)
+    pub fn dir_verify_interval_secs(mut self, secs: u64) -> Self {
+        self.config.dir_verify_interval_secs = secs;
+        self
+    }
+
+    /// Keep deleted file records for this many days
+    pub fn keep_deleted_days(mut self, days: u64) -> Self {
+        self.config.keep_deleted_days = Some(days);
+        self
+    }
+
+    /// Keep deleted file records of the last N scans
+    pub fn keep_deleted_runs(mut self, runs: u64) -> Self {
+        self.config.keep_deleted_runs = Some(runs);
+        self
+    }
+
     /// Build the config
     pub fn build(self) -> ScanConfig {
         self.config
@@ -380,6 +1068,73 @@ mod tests {
         assert!(!config.should_ignore_dir("Photos"));
     }
 
+    #[test]
+    fn test_hidden_policy() {
+        // The default skips hidden directories but keeps hidden files
+        assert!(!ScanConfig::default().should_ignore_file(".poster.jpg"));
+
+        let config = ScanConfig::builder().hidden(HiddenPolicy::None).build();
+        assert!(!config.should_ignore_dir(".media"));
+        assert!(!config.should_ignore_file(".poster.jpg"));
+        // Configured names are still ignored
+        assert!(config.should_ignore_dir(".git"));
+
+        let config = ScanConfig::builder().hidden(HiddenPolicy::Files).build();
+        assert!(!config.should_ignore_dir(".media"));
+        assert!(config.should_ignore_file(".poster.jpg"));
+
+        let config = ScanConfig::builder().hidden(HiddenPolicy::All).build();
+        assert!(config.should_ignore_dir(".media"));
+        assert!(config.should_ignore_file(".poster.jpg"));
+
+        assert_eq!("none".parse::<HiddenPolicy>(), Ok(HiddenPolicy::None));
+        assert!("some".parse::<HiddenPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_change_detection_from_toml() {
+        let config =
+            ScanConfig::from_str_with_format("change_detection = \"strict\"", ConfigFormat::Toml)
+                .unwrap();
+        assert_eq!(config.change_detection, ChangeDetection::Strict);
+        assert_eq!(
+            ScanConfig::default().change_detection,
+            ChangeDetection::Fast
+        );
+        assert!("exact".parse::<ChangeDetection>().is_err());
+    }
+
+    #[test]
+    fn test_progress_format() {
+        let config =
+            ScanConfig::from_str_with_format("progress_format = \"json\"", ConfigFormat::Toml)
+                .unwrap();
+        assert_eq!(config.progress_format, ProgressFormat::Json);
+        assert_eq!(ScanConfig::default().progress_format, ProgressFormat::Auto);
+        assert_eq!("TTY".parse::<ProgressFormat>(), Ok(ProgressFormat::Tty));
+        assert!("fancy".parse::<ProgressFormat>().is_err());
+    }
+
+    #[test]
+    fn test_output_order() {
+        let config =
+            ScanConfig::from_str_with_format("output_order = \"mtime\"", ConfigFormat::Toml)
+                .unwrap();
+        assert_eq!(config.output_order, OutputOrder::Mtime);
+        assert_eq!(ScanConfig::default().output_order, OutputOrder::Path);
+        assert_eq!("None".parse::<OutputOrder>(), Ok(OutputOrder::None));
+        assert!("name".parse::<OutputOrder>().is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30s"), Ok(30));
+        assert_eq!(parse_duration("7d"), Ok(7 * 24 * 60 * 60));
+        assert_eq!(parse_duration("0"), Ok(0));
+        assert!(parse_duration("3x").is_err());
+        assert!(parse_duration("999999999999999999w").is_err());
+    }
+
     #[test]
     fn test_config_builder() {
         let config = ScanConfig::builder()
@@ -407,6 +1162,110 @@ mod tests {
         assert!(auto_config.effective_threads() > 0);
     }
 
+    #[test]
+    fn test_partial_toml_config_uses_defaults() {
+        let config = ScanConfig::from_str_with_format(
+            "roots = [\"/media\"]\ncompute_hash = true\nmax_depth = 7\n",
+            ConfigFormat::Toml,
+        )
+        .unwrap();
+        assert_eq!(config.roots, vec![PathBuf::from("/media")]);
+        assert!(config.compute_hash);
+        assert_eq!(config.max_depth, 7);
+        assert_eq!(config.batch_size, DEFAULT_BATCH_SIZE);
+        assert!(config.extensions.contains("mp4"));
+    }
+
+    #[test]
+    fn test_config_round_trip() {
+        let config = ScanConfig::builder()
+            .add_root(PathBuf::from("/videos"))
+            .video_only()
+            .num_threads(2)
+            .build();
+        for format in [ConfigFormat::Toml, ConfigFormat::Json] {
+            let text = config.to_string_with_format(format).unwrap();
+            let parsed = ScanConfig::from_str_with_format(&text, format).unwrap();
+            assert_eq!(parsed.roots, config.roots);
+            assert_eq!(parsed.extensions, config.extensions);
+            assert_eq!(parsed.num_threads, 2);
+        }
+    }
+
+    #[test]
+    fn test_root_rules_from_toml() {
+        let config = ScanConfig::from_str_with_format(
+            r#"
+            roots = ["/a", "/b"]
+
+            [rules]
+            exclude = ["*.part"]
+
+            [[root_rules]]
+            root = "/a"
+            exclude = ["**/Samples/**"]
+            "#,
+            ConfigFormat::Toml,
+        )
+        .unwrap();
+
+        let a = config.rules_for_root(Path::new("/a"));
+        assert_eq!(a.exclude, vec!["*.part", "**/Samples/**"]);
+        let b = config.rules_for_root(Path::new("/b"));
+        assert_eq!(b.exclude, vec!["*.part"]);
+
+        // Nested tables must survive a TOML round trip
+        let text = config.to_string_with_format(ConfigFormat::Toml).unwrap();
+        let parsed = ScanConfig::from_str_with_format(&text, ConfigFormat::Toml).unwrap();
+        assert_eq!(parsed.root_rules, config.root_rules);
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("500"), Ok(500));
+        assert_eq!(parse_size("10K"), Ok(10 * 1024));
+        assert_eq!(parse_size("5MB"), Ok(5 * 1024 * 1024));
+        assert_eq!(parse_size("1.5g"), Ok(1536 * 1024 * 1024));
+        assert!(parse_size("ten").is_err());
+        assert!(parse_size("5X").is_err());
+    }
+
+    #[test]
+    fn test_time_bound_parse() {
+        assert_eq!("10m".parse(), Ok(TimeBound::Age(600)));
+        assert_eq!("2h".parse(), Ok(TimeBound::Age(7200)));
+        assert_eq!("1706659200".parse(), Ok(TimeBound::Absolute(1706659200)));
+        assert_eq!("2024-01-31".parse(), Ok(TimeBound::Absolute(1706659200)));
+        assert_eq!(
+            "2024-01-31T00:00:00+00:00".parse(),
+            Ok(TimeBound::Absolute(1706659200))
+        );
+        assert!("soon".parse::<TimeBound>().is_err());
+
+        assert_eq!(TimeBound::Age(600).resolve(1000), 400);
+        assert_eq!(TimeBound::Age(u64::MAX).resolve(1000), 1000 - i64::MAX);
+        assert_eq!(TimeBound::Absolute(5).resolve(1000), 5);
+    }
+
+    #[test]
+    fn test_file_filters_from_toml() {
+        let config = ScanConfig::from_str_with_format(
+            "min_size = 4096\nmax_mtime = \"10m\"\nexclude_names = [\"*.part\"]\n",
+            ConfigFormat::Toml,
+        )
+        .unwrap();
+        assert_eq!(config.min_size, Some(4096));
+        assert_eq!(config.max_mtime, Some(TimeBound::Age(600)));
+        assert_eq!(config.exclude_names, vec!["*.part"]);
+    }
+
+    #[test]
+    fn test_invalid_config_is_config_error() {
+        let err = ScanConfig::from_str_with_format("max_depth = \"deep\"", ConfigFormat::Toml)
+            .unwrap_err();
+        assert_eq!(err.kind, crate::error::ScanErrorKind::ConfigError);
+    }
+
     #[test]
     fn test_progress_interval_builder() {
         let config = ScanConfig::builder()
@@ -1,10 +1,16 @@
 //! Database module for persistent storage and incremental scanning
 
-use rusqlite::{params, Connection, Result as SqliteResult};
-use std::collections::HashMap;
-use std::path::Path;
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params, params_from_iter, Connection, Result as SqliteResult};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use crate::models::{FileStatus, ScannedFile};
+use crate::config::OutputOrder;
+use crate::models::{FileStatus, MediaType, ScanResult, ScannedFile};
+use crate::scanner::unix_now;
+use crate::verify::VerifyOrder;
 
 /// File record stored in database (minimal for fast comparison)
 #[derive(Debug, Clone)]
@@ -14,11 +20,54 @@ pub struct FileRecord {
     pub size: u64,
     pub mtime: i64,
     pub hash: Option<String>,
+    /// Whether `hash` covers only the first and last 1MB
+    pub is_partial_hash: bool,
     pub status: String,
+    /// Modification time in nanoseconds (None for rows written before it was tracked)
+    pub mtime_ns: Option<i64>,
+    /// Inode change time (st_ctime) in nanoseconds
+    pub change_ns: Option<i64>,
+    pub dev: Option<u64>,
+    pub inode: Option<u64>,
+}
+
+/// Columns selected for a FileRecord, in `file_record_from_row` order
+const FILE_RECORD_COLUMNS: &str =
+    "path, name, size, mtime, hash, status, mtime_ns, change_ns, dev, inode, is_partial_hash";
+
+/// Build a FileRecord from a row selected with FILE_RECORD_COLUMNS
+fn file_record_from_row(row: &rusqlite::Row) -> SqliteResult<FileRecord> {
+    Ok(FileRecord {
+        path: row.get(0)?,
+        name: row.get(1)?,
+        size: row.get::<_, i64>(2)? as u64,
+        mtime: row.get(3)?,
+        hash: row.get(4)?,
+        status: row
+            .get::<_, String>(5)
+            .unwrap_or_else(|_| "unchanged".to_string()),
+        mtime_ns: row.get(6)?,
+        change_ns: row.get(7)?,
+        dev: row.get::<_, Option<i64>>(8)?.map(|d| d as u64),
+        inode: row.get::<_, Option<i64>>(9)?.map(|i| i as u64),
+        is_partial_hash: row.get::<_, Option<i32>>(10)?.unwrap_or(0) != 0,
+    })
+}
+
+/// Directory record used by the directory mtime shortcut
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectoryRecord {
+    pub path: String,
+    /// Directory modification time in nanoseconds when it was last listed
+    pub mtime_ns: i64,
+    /// Number of entries seen when it was last listed
+    pub entry_count: u64,
+    /// Unix timestamp of the last full listing
+    pub verified_at: i64,
 }
 
 /// Deleted file record
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct DeletedFileRecord {
     pub path: String,
     pub name: String,
@@ -27,6 +76,186 @@ pub struct DeletedFileRecord {
     pub deleted_at: i64,
 }
 
+/// Group of distinct files sharing the same hash
+///
+/// Hard links (same device and inode) count as one file: only the first
+/// path is listed in `paths`, the others are counted in `hard_links`.
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateGroup {
+    pub hash: String,
+    pub size: u64,
+    pub paths: Vec<String>,
+    pub hard_links: u64,
+}
+
+/// Filter for `ScanDatabase::query_files` (all fields optional)
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct FileQuery {
+    pub status: Option<FileStatus>,
+    pub media_type: Option<MediaType>,
+    /// Only paths starting with this prefix
+    pub path_prefix: Option<String>,
+    /// Substring of the file name
+    pub name: Option<String>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub limit: Option<u64>,
+    pub offset: Option<u64>,
+}
+
+/// Rows of `deleted_files` to purge (all given conditions must match)
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct DeletedFilter {
+    /// Only rows deleted before this Unix timestamp
+    pub deleted_before: Option<i64>,
+    /// Only paths starting with this prefix
+    pub path_prefix: Option<String>,
+    /// Only rows deleted before the last N recorded scans
+    pub keep_runs: Option<u64>,
+}
+
+/// Summary of the database contents
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DbStats {
+    pub total_files: u64,
+    pub total_size: u64,
+    pub by_media_type: BTreeMap<String, u64>,
+    pub by_status: BTreeMap<String, u64>,
+    pub deleted_files: u64,
+    pub directories: u64,
+}
+
+/// Summary of a finished scan, kept as scan history
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScanRecord {
+    pub id: i64,
+    /// Unix timestamp when the scan finished
+    pub finished_at: i64,
+    pub incremental: bool,
+    pub roots: Vec<String>,
+    pub total_files: u64,
+    pub new_files: u64,
+    pub modified_files: u64,
+    pub deleted_files: u64,
+    pub error_count: u64,
+    pub duration_ms: u64,
+    pub cancelled: bool,
+}
+
+impl ScanRecord {
+    /// Build a history record for a scan result
+    pub fn from_result(result: &ScanResult, roots: &[PathBuf], incremental: bool) -> Self {
+        Self {
+            id: 0,
+            finished_at: chrono::Utc::now().timestamp(),
+            incremental,
+            roots: roots
+                .iter()
+                .map(|r| r.to_string_lossy().replace('\\', "/"))
+                .collect(),
+            total_files: result.total_files,
+            new_files: result.new_files,
+            modified_files: result.modified_files,
+            deleted_files: result.deleted_files,
+            error_count: result.errors.len() as u64,
+            duration_ms: result.duration_ms,
+            cancelled: result.cancelled,
+        }
+    }
+}
+
+/// Version of the schema created by `init_schema` and `migrate_schema`,
+/// stored as `PRAGMA user_version` (0 in databases opened by older versions)
+pub const SCHEMA_VERSION: i64 = 1;
+
+/// Overview of the database, for `db info`
+#[derive(Debug, Clone, Serialize)]
+pub struct DbInfo {
+    pub schema_version: i64,
+    /// Size of the database in bytes
+    pub size_bytes: u64,
+    /// Bytes in free pages, reclaimed by `vacuum`
+    pub free_bytes: u64,
+    /// Row count of each of the `EXPORT_TABLES`
+    pub tables: BTreeMap<String, u64>,
+    pub last_scan: Option<ScanRecord>,
+}
+
+/// Result of `ScanDatabase::check`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DbCheck {
+    /// Problems reported by `PRAGMA integrity_check` (empty when intact)
+    pub integrity_errors: Vec<String>,
+    /// Roots the rows were checked against (none: not checked)
+    pub roots: Vec<String>,
+    pub files_outside_roots: u64,
+    pub deleted_outside_roots: u64,
+    pub directories_outside_roots: u64,
+    /// Some of the file paths outside every root
+    pub outside_samples: Vec<String>,
+    /// Files whose status is not a known `FileStatus`
+    pub invalid_status: u64,
+}
+
+impl DbCheck {
+    /// Whether no problem was found
+    pub fn is_ok(&self) -> bool {
+        self.integrity_errors.is_empty()
+            && self.files_outside_roots == 0
+            && self.deleted_outside_roots == 0
+            && self.directories_outside_roots == 0
+            && self.invalid_status == 0
+    }
+}
+
+/// Rows removed by `ScanDatabase::prune_root`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PruneCounts {
+    pub files: u64,
+    pub deleted_files: u64,
+    pub directories: u64,
+}
+
+/// Tables that can be read with `ScanDatabase::read_table`
+pub const EXPORT_TABLES: [&str; 4] = ["files", "deleted_files", "directories", "scans"];
+
+/// A column of a database table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableColumn {
+    pub name: String,
+    /// Declared SQL type (e.g. `INTEGER`, `TEXT`)
+    pub decl_type: String,
+    pub nullable: bool,
+    pub primary_key: bool,
+}
+
+/// Check that `table` is one of `EXPORT_TABLES` (names are interpolated into SQL)
+fn export_table(table: &str) -> SqliteResult<&str> {
+    EXPORT_TABLES
+        .iter()
+        .find(|t| **t == table)
+        .copied()
+        .ok_or_else(|| rusqlite::Error::InvalidParameterName(format!("unknown table {}", table)))
+}
+
+/// SQL condition matching paths outside every root, with its parameters
+fn outside_roots(roots: &[String]) -> (String, Vec<String>) {
+    let mut values = Vec::new();
+    let conditions: Vec<&str> = roots
+        .iter()
+        .map(|root| {
+            let root = root.trim_end_matches('/');
+            values.push(root.to_string());
+            values.push(format!("{}/", root));
+            values.push(format!("{}/", root));
+            "path = ? OR substr(path, 1, length(?)) = ?"
+        })
+        .collect();
+    (format!("NOT ({})", conditions.join(" OR ")), values)
+}
+
 /// Database manager for scan results
 pub struct ScanDatabase {
     conn: Connection,
@@ -36,6 +265,8 @@ impl ScanDatabase {
     /// Open or create database
     pub fn open(path: &Path) -> SqliteResult<Self> {
         let conn = Connection::open(path)?;
+        // Other processes (watch/serve) may hold the write lock briefly
+        conn.busy_timeout(Duration::from_secs(5))?;
         let db = Self { conn };
         db.init_schema()?;
         db.migrate_schema()?;
@@ -47,6 +278,7 @@ impl ScanDatabase {
         let conn = Connection::open_in_memory()?;
         let db = Self { conn };
         db.init_schema()?;
+        db.migrate_schema()?;
         Ok(db)
     }
 
@@ -65,7 +297,14 @@ impl ScanDatabase {
                 hash TEXT,
                 is_partial_hash INTEGER DEFAULT 0,
                 status TEXT DEFAULT 'new',
-                old_path TEXT
+                old_path TEXT,
+                link_target TEXT,
+                is_broken_link INTEGER DEFAULT 0,
+                dev INTEGER,
+                inode INTEGER,
+                mtime_ns INTEGER,
+                change_ns INTEGER,
+                last_verified INTEGER
             );
             CREATE INDEX IF NOT EXISTS idx_files_mtime ON files(mtime);
             CREATE INDEX IF NOT EXISTS idx_files_size ON files(size);
@@ -86,6 +325,27 @@ impl ScanDatabase {
             );
             CREATE INDEX IF NOT EXISTS idx_deleted_files_hash ON deleted_files(hash);
             CREATE INDEX IF NOT EXISTS idx_deleted_files_deleted_at ON deleted_files(deleted_at);
+
+            CREATE TABLE IF NOT EXISTS directories (
+                path TEXT PRIMARY KEY,
+                mtime_ns INTEGER NOT NULL,
+                entry_count INTEGER NOT NULL,
+                verified_at INTEGER NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS scans (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                finished_at INTEGER NOT NULL,
+                incremental INTEGER NOT NULL,
+                roots TEXT NOT NULL,
+                total_files INTEGER NOT NULL,
+                new_files INTEGER NOT NULL,
+                modified_files INTEGER NOT NULL,
+                deleted_files INTEGER NOT NULL,
+                error_count INTEGER NOT NULL,
+                duration_ms INTEGER NOT NULL,
+                cancelled INTEGER NOT NULL
+            );
             ",
         )?;
         Ok(())
@@ -110,6 +370,52 @@ impl ScanDatabase {
                 .execute("ALTER TABLE files ADD COLUMN old_path TEXT", [])?;
         }
 
+        self.add_column_if_missing("files", "link_target", "TEXT")?;
+        self.add_column_if_missing("files", "is_broken_link", "INTEGER DEFAULT 0")?;
+        self.add_column_if_missing("files", "dev", "INTEGER")?;
+        self.add_column_if_missing("files", "inode", "INTEGER")?;
+        self.add_column_if_missing("files", "mtime_ns", "INTEGER")?;
+        self.add_column_if_missing("files", "change_ns", "INTEGER")?;
+        self.add_column_if_missing("files", "last_verified", "INTEGER")?;
+        self.conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_files_inode ON files(dev, inode)",
+            [],
+        )?;
+        self.conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_files_last_verified ON files(last_verified)",
+            [],
+        )?;
+
+        if self.schema_version()? != SCHEMA_VERSION {
+            self.conn
+                .pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        }
+        Ok(())
+    }
+
+    /// Get the schema version stored in the database
+    pub fn schema_version(&self) -> SqliteResult<i64> {
+        self.conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+    }
+
+    /// Add a column to a table unless it already exists
+    fn add_column_if_missing(&self, table: &str, column: &str, decl: &str) -> SqliteResult<()> {
+        let exists: bool = self.conn.query_row(
+            &format!(
+                "SELECT COUNT(*) > 0 FROM pragma_table_info('{}') WHERE name = ?1",
+                table
+            ),
+            [column],
+            |row| row.get(0),
+        )?;
+
+        if !exists {
+            self.conn.execute(
+                &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl),
+                [],
+            )?;
+        }
         Ok(())
     }
 
@@ -117,18 +423,9 @@ impl ScanDatabase {
     pub fn load_file_index(&self) -> SqliteResult<HashMap<String, FileRecord>> {
         let mut stmt = self
             .conn
-            .prepare("SELECT path, name, size, mtime, hash, status FROM files")?;
+            .prepare(&format!("SELECT {} FROM files", FILE_RECORD_COLUMNS))?;
 
-        let rows = stmt.query_map([], |row| {
-            Ok(FileRecord {
-                path: row.get(0)?,
-                name: row.get(1)?,
-                size: row.get::<_, i64>(2)? as u64,
-                mtime: row.get(3)?,
-                hash: row.get(4)?,
-                status: row.get::<_, String>(5).unwrap_or_else(|_| "unchanged".to_string()),
-            })
-        })?;
+        let rows = stmt.query_map([], file_record_from_row)?;
 
         let mut index = HashMap::new();
         for row in rows {
@@ -140,20 +437,12 @@ impl ScanDatabase {
 
     /// Load hash index for move detection
     pub fn load_hash_index(&self) -> SqliteResult<HashMap<String, FileRecord>> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT path, name, size, mtime, hash, status FROM files WHERE hash IS NOT NULL")?;
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM files WHERE hash IS NOT NULL",
+            FILE_RECORD_COLUMNS
+        ))?;
 
-        let rows = stmt.query_map([], |row| {
-            Ok(FileRecord {
-                path: row.get(0)?,
-                name: row.get(1)?,
-                size: row.get::<_, i64>(2)? as u64,
-                mtime: row.get(3)?,
-                hash: row.get(4)?,
-                status: row.get::<_, String>(5).unwrap_or_else(|_| "unchanged".to_string()),
-            })
-        })?;
+        let rows = stmt.query_map([], file_record_from_row)?;
 
         let mut index = HashMap::new();
         for row in rows {
@@ -180,8 +469,9 @@ impl ScanDatabase {
         {
             let mut stmt = tx.prepare(
                 "INSERT OR REPLACE INTO files 
-                 (path, name, size, mtime, ctime, extension, media_type, hash, is_partial_hash, status, old_path)
-                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                 (path, name, size, mtime, ctime, extension, media_type, hash, is_partial_hash, status, old_path,
+                  link_target, is_broken_link, dev, inode, mtime_ns, change_ns)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17)",
             )?;
 
             for file in files {
@@ -204,6 +494,12 @@ impl ScanDatabase {
                     file.is_partial_hash as i32,
                     file.status.as_str(),
                     file.old_path,
+                    file.link_target,
+                    file.broken_link as i32,
+                    file.dev.map(|d| d as i64),
+                    file.inode.map(|i| i as i64),
+                    file.mtime_ns,
+                    file.change_ns,
                 ])?;
             }
         }
@@ -211,16 +507,172 @@ impl ScanDatabase {
         Ok(())
     }
 
+    /// Get a single file record by path
+    pub fn get_file(&self, path: &str) -> SqliteResult<Option<FileRecord>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM files WHERE path = ?1",
+            FILE_RECORD_COLUMNS
+        ))?;
+        let mut rows = stmt.query_map([path], file_record_from_row)?;
+        rows.next().transpose()
+    }
+
+    /// Get the paths of all files below a directory
+    pub fn paths_under(&self, dir: &str) -> SqliteResult<Vec<String>> {
+        let prefix = format!("{}/", dir.trim_end_matches('/'));
+        let mut stmt = self
+            .conn
+            .prepare("SELECT path FROM files WHERE substr(path, 1, length(?1)) = ?1")?;
+        let rows = stmt.query_map([&prefix], |row| row.get(0))?;
+        rows.collect()
+    }
+
+    /// Get the records of all files below a directory, sorted by path
+    pub fn files_under(&self, dir: &str) -> SqliteResult<Vec<FileRecord>> {
+        let prefix = format!("{}/", dir.trim_end_matches('/'));
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM files WHERE substr(path, 1, length(?1)) = ?1 ORDER BY path",
+            FILE_RECORD_COLUMNS
+        ))?;
+        let rows = stmt.query_map([&prefix], file_record_from_row)?;
+        rows.collect()
+    }
+
+    /// Record a file as moved to a new path
+    ///
+    /// A file already indexed at `new_path` was replaced by the rename and is
+    /// moved to deleted_files first.
+    pub fn rename_file(&mut self, old_path: &str, new_path: &str) -> SqliteResult<bool> {
+        let name = new_path.rsplit('/').next().unwrap_or(new_path);
+        let tx = self.conn.transaction()?;
+        if old_path != new_path {
+            tx.execute(
+                "INSERT INTO deleted_files (path, name, size, mtime, ctime, extension, media_type, hash, deleted_at)
+                 SELECT path, name, size, mtime, ctime, extension, media_type, hash, ?2
+                 FROM files WHERE path = ?1",
+                params![new_path, unix_now()],
+            )?;
+            tx.execute("DELETE FROM files WHERE path = ?1", [new_path])?;
+        }
+        let updated = tx.execute(
+            "UPDATE files SET path = ?2, name = ?3, status = 'moved', old_path = ?1 WHERE path = ?1",
+            params![old_path, new_path, name],
+        )?;
+        tx.commit()?;
+        Ok(updated > 0)
+    }
+
+    /// Record all files and directories below a directory as moved to a new directory
+    ///
+    /// Files still indexed below `new_dir` were replaced and are moved to
+    /// deleted_files first. Returns the number of files moved.
+    pub fn rename_dir(&mut self, old_dir: &str, new_dir: &str) -> SqliteResult<usize> {
+        let old_dir = old_dir.trim_end_matches('/');
+        let new_dir = new_dir.trim_end_matches('/');
+        let old_prefix = format!("{}/", old_dir);
+        let new_prefix = format!("{}/", new_dir);
+        if old_prefix == new_prefix {
+            return Ok(0);
+        }
+
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "INSERT INTO deleted_files (path, name, size, mtime, ctime, extension, media_type, hash, deleted_at)
+             SELECT path, name, size, mtime, ctime, extension, media_type, hash, ?2
+             FROM files WHERE substr(path, 1, length(?1)) = ?1",
+            params![new_prefix, unix_now()],
+        )?;
+        tx.execute(
+            "DELETE FROM files WHERE substr(path, 1, length(?1)) = ?1",
+            [&new_prefix],
+        )?;
+        let moved = tx.execute(
+            "UPDATE files SET path = ?2 || substr(path, length(?1) + 1), status = 'moved', old_path = path
+             WHERE substr(path, 1, length(?1)) = ?1",
+            params![old_prefix, new_prefix],
+        )?;
+
+        tx.execute(
+            "DELETE FROM directories WHERE path = ?1 OR substr(path, 1, length(?2)) = ?2",
+            params![new_dir, new_prefix],
+        )?;
+        tx.execute(
+            "UPDATE directories SET path = ?2 WHERE path = ?1",
+            params![old_dir, new_dir],
+        )?;
+        tx.execute(
+            "UPDATE directories SET path = ?2 || substr(path, length(?1) + 1)
+             WHERE substr(path, 1, length(?1)) = ?1",
+            params![old_prefix, new_prefix],
+        )?;
+        tx.commit()?;
+        Ok(moved)
+    }
+
+    /// Load all directory records keyed by path
+    pub fn load_directory_index(&self) -> SqliteResult<HashMap<String, DirectoryRecord>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT path, mtime_ns, entry_count, verified_at FROM directories")?;
+
+        let rows = stmt.query_map([], |row| {
+            Ok(DirectoryRecord {
+                path: row.get(0)?,
+                mtime_ns: row.get(1)?,
+                entry_count: row.get::<_, i64>(2)? as u64,
+                verified_at: row.get(3)?,
+            })
+        })?;
+
+        let mut index = HashMap::new();
+        for row in rows {
+            let record = row?;
+            index.insert(record.path.clone(), record);
+        }
+        Ok(index)
+    }
+
+    /// Batch insert/update directory records
+    pub fn upsert_directories(&mut self, dirs: &[DirectoryRecord]) -> SqliteResult<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT OR REPLACE INTO directories (path, mtime_ns, entry_count, verified_at)
+                 VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for dir in dirs {
+                stmt.execute(params![
+                    dir.path,
+                    dir.mtime_ns,
+                    dir.entry_count as i64,
+                    dir.verified_at
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Remove directory records by path
+    pub fn delete_directories(&mut self, paths: &[String]) -> SqliteResult<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut stmt = tx.prepare("DELETE FROM directories WHERE path = ?1")?;
+            for path in paths {
+                stmt.execute([path])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
     /// Move files to deleted_files table and remove from files table
     pub fn move_to_deleted(&mut self, paths: &[String]) -> SqliteResult<()> {
         if paths.is_empty() {
             return Ok(());
         }
 
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .map(|d| d.as_secs() as i64)
-            .unwrap_or(0);
+        let now = unix_now();
 
         let tx = self.conn.transaction()?;
         {
@@ -262,20 +714,12 @@ impl ScanDatabase {
 
     /// Get files by status
     pub fn get_files_by_status(&self, status: FileStatus) -> SqliteResult<Vec<FileRecord>> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT path, name, size, mtime, hash, status FROM files WHERE status = ?1")?;
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM files WHERE status = ?1",
+            FILE_RECORD_COLUMNS
+        ))?;
 
-        let rows = stmt.query_map([status.as_str()], |row| {
-            Ok(FileRecord {
-                path: row.get(0)?,
-                name: row.get(1)?,
-                size: row.get::<_, i64>(2)? as u64,
-                mtime: row.get(3)?,
-                hash: row.get(4)?,
-                status: row.get(5)?,
-            })
-        })?;
+        let rows = stmt.query_map([status.as_str()], file_record_from_row)?;
 
         let mut files = Vec::new();
         for row in rows {
@@ -325,6 +769,219 @@ impl ScanDatabase {
         Ok(files)
     }
 
+    /// Get groups of files with identical hashes, counting hard links once
+    pub fn get_duplicates(&self) -> SqliteResult<Vec<DuplicateGroup>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT hash, size, path, dev, inode FROM files
+             WHERE hash IN (SELECT hash FROM files WHERE hash IS NOT NULL
+                            GROUP BY hash HAVING COUNT(*) > 1)
+             ORDER BY hash, path",
+        )?;
+
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i64>(1)? as u64,
+                row.get::<_, String>(2)?,
+                row.get::<_, Option<i64>>(3)?,
+                row.get::<_, Option<i64>>(4)?,
+            ))
+        })?;
+
+        let mut groups: Vec<DuplicateGroup> = Vec::new();
+        let mut seen_ids: HashSet<(i64, i64)> = HashSet::new();
+        for row in rows {
+            let (hash, size, path, dev, inode) = row?;
+            if groups.last().map(|g| g.hash != hash).unwrap_or(true) {
+                seen_ids.clear();
+                groups.push(DuplicateGroup {
+                    hash,
+                    size,
+                    paths: Vec::new(),
+                    hard_links: 0,
+                });
+            }
+            let group = groups.last_mut().expect("group pushed above");
+            let is_extra_link = match (dev, inode) {
+                (Some(dev), Some(inode)) => !seen_ids.insert((dev, inode)),
+                _ => false,
+            };
+            if is_extra_link {
+                group.hard_links += 1;
+            } else {
+                group.paths.push(path);
+            }
+        }
+
+        // Groups made up only of hard links to one file are not duplicates
+        groups.retain(|g| g.paths.len() > 1);
+        Ok(groups)
+    }
+
+    /// Query files matching a filter, ordered by path
+    pub fn query_files(&self, query: &FileQuery) -> SqliteResult<Vec<ScannedFile>> {
+        let mut conditions = Vec::new();
+        let mut values: Vec<rusqlite::types::Value> = Vec::new();
+
+        if let Some(status) = query.status {
+            conditions.push("status = ?");
+            values.push(status.as_str().to_string().into());
+        }
+        if let Some(media_type) = query.media_type {
+            conditions.push("media_type = ?");
+            values.push(media_type.as_str().to_string().into());
+        }
+        if let Some(ref prefix) = query.path_prefix {
+            conditions.push("substr(path, 1, length(?)) = ?");
+            values.push(prefix.clone().into());
+            values.push(prefix.clone().into());
+        }
+        if let Some(ref name) = query.name {
+            conditions.push("instr(name, ?) > 0");
+            values.push(name.clone().into());
+        }
+        if let Some(min_size) = query.min_size {
+            conditions.push("size >= ?");
+            values.push((min_size as i64).into());
+        }
+        if let Some(max_size) = query.max_size {
+            conditions.push("size <= ?");
+            values.push((max_size as i64).into());
+        }
+
+        let mut sql = String::from(
+            "SELECT path, name, size, mtime, ctime, extension, media_type, hash, is_partial_hash,
+                    status, old_path, link_target, is_broken_link, dev, inode, mtime_ns, change_ns
+             FROM files",
+        );
+        if !conditions.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&conditions.join(" AND "));
+        }
+        sql.push_str(" ORDER BY path");
+        if query.limit.is_some() || query.offset.is_some() {
+            sql.push_str(" LIMIT ? OFFSET ?");
+            values.push(query.limit.map_or(-1, |l| l as i64).into());
+            values.push((query.offset.unwrap_or(0) as i64).into());
+        }
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt.query_map(params_from_iter(values), |row| {
+            let mut file = ScannedFile::new(
+                PathBuf::from(row.get::<_, String>(0)?),
+                row.get(1)?,
+                row.get::<_, i64>(2)? as u64,
+                row.get(3)?,
+                row.get(4)?,
+                row.get(5)?,
+            );
+            if let Ok(media_type) = row.get::<_, String>(6)?.parse() {
+                file.media_type = media_type;
+            }
+            file.hash = row.get(7)?;
+            file.is_partial_hash = row.get::<_, Option<i32>>(8)?.unwrap_or(0) != 0;
+            file.status = row
+                .get::<_, Option<String>>(9)?
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(FileStatus::Unchanged);
+            file.old_path = row.get(10)?;
+            file.link_target = row.get(11)?;
+            file.broken_link = row.get::<_, Option<i32>>(12)?.unwrap_or(0) != 0;
+            file.dev = row.get::<_, Option<i64>>(13)?.map(|d| d as u64);
+            file.inode = row.get::<_, Option<i64>>(14)?.map(|i| i as u64);
+            file.mtime_ns = row.get(15)?;
+            file.change_ns = row.get(16)?;
+            Ok(file)
+        })?;
+        rows.collect()
+    }
+
+    /// Get summary statistics for the database
+    pub fn get_stats(&self) -> SqliteResult<DbStats> {
+        let (total_files, total_size): (i64, i64) = self.conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(size), 0) FROM files",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+
+        let mut by_media_type = BTreeMap::new();
+        let mut stmt = self
+            .conn
+            .prepare("SELECT media_type, COUNT(*) FROM files GROUP BY media_type")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)? as u64))
+        })?;
+        for row in rows {
+            let (media_type, count) = row?;
+            by_media_type.insert(media_type, count);
+        }
+
+        let directories: i64 =
+            self.conn
+                .query_row("SELECT COUNT(*) FROM directories", [], |row| row.get(0))?;
+
+        Ok(DbStats {
+            total_files: total_files as u64,
+            total_size: total_size as u64,
+            by_media_type,
+            by_status: self.get_status_counts()?.into_iter().collect(),
+            deleted_files: self.deleted_files_count()?,
+            directories: directories as u64,
+        })
+    }
+
+    /// Add a finished scan to the scan history, returning its id
+    pub fn record_scan(&mut self, record: &ScanRecord) -> SqliteResult<i64> {
+        self.conn.execute(
+            "INSERT INTO scans (finished_at, incremental, roots, total_files, new_files, modified_files,
+                                deleted_files, error_count, duration_ms, cancelled)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                record.finished_at,
+                record.incremental as i32,
+                record.roots.join("\n"),
+                record.total_files as i64,
+                record.new_files as i64,
+                record.modified_files as i64,
+                record.deleted_files as i64,
+                record.error_count as i64,
+                record.duration_ms as i64,
+                record.cancelled as i32,
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Get the most recent scans, newest first
+    pub fn get_scans(&self, limit: u64) -> SqliteResult<Vec<ScanRecord>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, finished_at, incremental, roots, total_files, new_files, modified_files,
+                    deleted_files, error_count, duration_ms, cancelled
+             FROM scans ORDER BY id DESC LIMIT ?1",
+        )?;
+        let rows = stmt.query_map([limit as i64], |row| {
+            Ok(ScanRecord {
+                id: row.get(0)?,
+                finished_at: row.get(1)?,
+                incremental: row.get::<_, i32>(2)? != 0,
+                roots: row
+                    .get::<_, String>(3)?
+                    .split('\n')
+                    .filter(|r| !r.is_empty())
+                    .map(String::from)
+                    .collect(),
+                total_files: row.get::<_, i64>(4)? as u64,
+                new_files: row.get::<_, i64>(5)? as u64,
+                modified_files: row.get::<_, i64>(6)? as u64,
+                deleted_files: row.get::<_, i64>(7)? as u64,
+                error_count: row.get::<_, i64>(8)? as u64,
+                duration_ms: row.get::<_, i64>(9)? as u64,
+                cancelled: row.get::<_, i32>(10)? != 0,
+            })
+        })?;
+        rows.collect()
+    }
+
     /// Get file count
     pub fn file_count(&self) -> SqliteResult<u64> {
         let count: i64 = self
@@ -360,6 +1017,115 @@ impl ScanDatabase {
         Ok(count as u64)
     }
 
+    /// Delete the `deleted_files` rows matching `filter`, returning how many
+    ///
+    /// An empty filter matches every row, like `clear_deleted_files`.
+    pub fn purge_deleted_files(&mut self, filter: &DeletedFilter) -> SqliteResult<u64> {
+        let mut conditions = Vec::new();
+        let mut values: Vec<SqlValue> = Vec::new();
+
+        if let Some(before) = filter.deleted_before {
+            conditions.push("deleted_at < ?");
+            values.push(before.into());
+        }
+        if let Some(ref prefix) = filter.path_prefix {
+            conditions.push("substr(path, 1, length(?)) = ?");
+            values.push(prefix.clone().into());
+            values.push(prefix.clone().into());
+        }
+        if let Some(runs) = filter.keep_runs {
+            // Rows deleted after the scan before the last N were deleted by
+            // those N scans; no such scan means nothing is old enough. A row
+            // from the second that scan finished in is kept (times are seconds).
+            conditions.push(
+                "deleted_at < (SELECT finished_at FROM scans ORDER BY id DESC LIMIT 1 OFFSET ?)",
+            );
+            // Beyond i64::MAX the offset matches no scan, keeping every row
+            values.push(i64::try_from(runs).unwrap_or(i64::MAX).into());
+        }
+
+        let mut sql = String::from("DELETE FROM deleted_files");
+        if !conditions.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&conditions.join(" AND "));
+        }
+        let count = self.conn.execute(&sql, params_from_iter(values))?;
+        Ok(count as u64)
+    }
+
+    /// Apply a retention policy to `deleted_files`, returning the rows purged
+    ///
+    /// Rows are kept while either rule keeps them: deleted within the last
+    /// `keep_days` days, or by one of the last `keep_runs` recorded scans.
+    /// Nothing is purged when both are None, or when `keep_days` is too large
+    /// to subtract from the current time (it keeps every row).
+    pub fn apply_deleted_retention(
+        &mut self,
+        keep_days: Option<u64>,
+        keep_runs: Option<u64>,
+    ) -> SqliteResult<u64> {
+        if keep_days.is_none() && keep_runs.is_none() {
+            return Ok(0);
+        }
+        let deleted_before = match keep_days {
+            Some(days) => {
+                let Some(secs) = i64::try_from(days).ok().and_then(|d| d.checked_mul(86400)) else {
+                    return Ok(0);
+                };
+                Some(chrono::Utc::now().timestamp().saturating_sub(secs))
+            }
+            None => None,
+        };
+        self.purge_deleted_files(&DeletedFilter {
+            deleted_before,
+            path_prefix: None,
+            keep_runs,
+        })
+    }
+
+    /// Deleted files with a hash, keyed by (hash, size), most recently deleted last
+    pub fn load_deleted_hash_index(&self) -> SqliteResult<HashMap<(String, u64), Vec<String>>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT hash, size, path FROM deleted_files WHERE hash IS NOT NULL
+             ORDER BY deleted_at, id",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i64>(1)? as u64,
+                row.get::<_, String>(2)?,
+            ))
+        })?;
+        let mut index: HashMap<(String, u64), Vec<String>> = HashMap::new();
+        for row in rows {
+            let (hash, size, path) = row?;
+            index.entry((hash, size)).or_default().push(path);
+        }
+        Ok(index)
+    }
+
+    /// Remove the `deleted_files` rows of restored files
+    ///
+    /// For every file with status `restored`, the most recent row for its
+    /// `old_path`, hash and size is removed: the file is back in `files`.
+    pub fn remove_restored(&mut self, files: &[ScannedFile]) -> SqliteResult<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "DELETE FROM deleted_files WHERE id = (
+                     SELECT id FROM deleted_files WHERE path = ?1 AND hash = ?2 AND size = ?3
+                     ORDER BY deleted_at DESC, id DESC LIMIT 1
+                 )",
+            )?;
+            for file in files.iter().filter(|f| f.status == FileStatus::Restored) {
+                if let (Some(old_path), Some(hash)) = (&file.old_path, &file.hash) {
+                    stmt.execute(params![old_path, hash, file.size as i64])?;
+                }
+            }
+        }
+        tx.commit()
+    }
+
     /// Get deleted files count
     pub fn deleted_files_count(&self) -> SqliteResult<u64> {
         let count: i64 = self
@@ -367,4 +1133,673 @@ impl ScanDatabase {
             .query_row("SELECT COUNT(*) FROM deleted_files", [], |row| row.get(0))?;
         Ok(count as u64)
     }
+
+    /// Get the schema version, size, row counts and last scan
+    pub fn info(&self) -> SqliteResult<DbInfo> {
+        let pragma = |name: &str| -> SqliteResult<u64> {
+            let value: i64 = self
+                .conn
+                .query_row(&format!("PRAGMA {}", name), [], |row| row.get(0))?;
+            Ok(value as u64)
+        };
+        let page_size = pragma("page_size")?;
+
+        let mut tables = BTreeMap::new();
+        for table in EXPORT_TABLES {
+            let count: i64 =
+                self.conn
+                    .query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+                        row.get(0)
+                    })?;
+            tables.insert(table.to_string(), count as u64);
+        }
+
+        Ok(DbInfo {
+            schema_version: self.schema_version()?,
+            size_bytes: pragma("page_count")? * page_size,
+            free_bytes: pragma("freelist_count")? * page_size,
+            tables,
+            last_scan: self.get_scans(1)?.pop(),
+        })
+    }
+
+    /// Rebuild the database file, reclaiming free pages
+    pub fn vacuum(&self) -> SqliteResult<()> {
+        self.conn.execute_batch("VACUUM")
+    }
+
+    /// Roots of all recorded scans, sorted
+    pub fn known_roots(&self) -> SqliteResult<Vec<String>> {
+        let mut stmt = self.conn.prepare("SELECT DISTINCT roots FROM scans")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        let mut roots = BTreeSet::new();
+        for row in rows {
+            roots.extend(row?.split('\n').filter(|r| !r.is_empty()).map(String::from));
+        }
+        Ok(roots.into_iter().collect())
+    }
+
+    /// Run `PRAGMA integrity_check` and consistency checks
+    ///
+    /// Rows are checked against `roots`, or the roots of the recorded scans
+    /// when `roots` is empty; with neither, that check is skipped.
+    pub fn check(&self, roots: &[String]) -> SqliteResult<DbCheck> {
+        let mut check = DbCheck::default();
+
+        let mut stmt = self.conn.prepare("PRAGMA integrity_check")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        for row in rows {
+            let message = row?;
+            if message != "ok" {
+                check.integrity_errors.push(message);
+            }
+        }
+
+        check.roots = if roots.is_empty() {
+            self.known_roots()?
+        } else {
+            roots.to_vec()
+        };
+        if !check.roots.is_empty() {
+            let (condition, values) = outside_roots(&check.roots);
+            let count = |table: &str| -> SqliteResult<u64> {
+                let count: i64 = self.conn.query_row(
+                    &format!("SELECT COUNT(*) FROM {} WHERE {}", table, condition),
+                    params_from_iter(&values),
+                    |row| row.get(0),
+                )?;
+                Ok(count as u64)
+            };
+            check.files_outside_roots = count("files")?;
+            check.deleted_outside_roots = count("deleted_files")?;
+            check.directories_outside_roots = count("directories")?;
+
+            let mut stmt = self.conn.prepare(&format!(
+                "SELECT path FROM files WHERE {} ORDER BY path LIMIT 10",
+                condition
+            ))?;
+            let rows = stmt.query_map(params_from_iter(&values), |row| row.get(0))?;
+            check.outside_samples = rows.collect::<SqliteResult<_>>()?;
+        }
+
+        check.invalid_status = self
+            .get_status_counts()?
+            .into_iter()
+            .filter(|(status, _)| status.parse::<FileStatus>().is_err())
+            .map(|(_, count)| count)
+            .sum();
+        Ok(check)
+    }
+
+    /// Drop every file, deleted file and directory record below `root`
+    pub fn prune_root(&mut self, root: &str) -> SqliteResult<PruneCounts> {
+        let root = root.trim_end_matches('/');
+        let prefix = format!("{}/", root);
+        let tx = self.conn.transaction()?;
+        let delete = |table: &str| -> SqliteResult<u64> {
+            let count = tx.execute(
+                &format!(
+                    "DELETE FROM {} WHERE path = ?1 OR substr(path, 1, length(?2)) = ?2",
+                    table
+                ),
+                params![root, prefix],
+            )?;
+            Ok(count as u64)
+        };
+        let counts = PruneCounts {
+            files: delete("files")?,
+            deleted_files: delete("deleted_files")?,
+            directories: delete("directories")?,
+        };
+        tx.commit()?;
+        Ok(counts)
+    }
+
+    /// Columns of one of the `EXPORT_TABLES`, in table order
+    pub fn table_columns(&self, table: &str) -> SqliteResult<Vec<TableColumn>> {
+        let table = export_table(table)?;
+        let mut stmt = self.conn.prepare(
+            "SELECT name, type, \"notnull\", pk FROM pragma_table_info(?1) ORDER BY cid",
+        )?;
+        let rows = stmt.query_map([table], |row| {
+            let not_null: bool = row.get(2)?;
+            let primary_key = row.get::<_, i64>(3)? > 0;
+            Ok(TableColumn {
+                name: row.get(0)?,
+                decl_type: row.get::<_, String>(1)?.to_uppercase(),
+                nullable: !not_null && !primary_key,
+                primary_key,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Read all rows of one of the `EXPORT_TABLES` in primary key order
+    ///
+    /// Rows are passed to `on_batch` in batches of at most `batch_size`, with
+    /// values in `table_columns` order, so large tables are never held in
+    /// memory at once. Returns the number of rows read.
+    pub fn read_table<E: From<rusqlite::Error>>(
+        &self,
+        table: &str,
+        batch_size: usize,
+        mut on_batch: impl FnMut(Vec<Vec<SqlValue>>) -> Result<(), E>,
+    ) -> Result<u64, E> {
+        let columns = self.table_columns(table)?;
+        let order = columns
+            .iter()
+            .find(|c| c.primary_key)
+            .map_or("rowid", |c| c.name.as_str());
+        let names: Vec<String> = columns.iter().map(|c| format!("\"{}\"", c.name)).collect();
+        let sql = format!(
+            "SELECT {} FROM {} ORDER BY \"{}\"",
+            names.join(", "),
+            export_table(table)?,
+            order
+        );
+        let mut stmt = self.conn.prepare(&sql)?;
+        let mut rows = stmt.query([])?;
+
+        let batch_size = batch_size.max(1);
+        let mut batch = Vec::with_capacity(batch_size);
+        let mut count = 0;
+        while let Some(row) = rows.next()? {
+            let values = (0..columns.len())
+                .map(|i| row.get::<_, SqlValue>(i))
+                .collect::<SqliteResult<Vec<_>>>()?;
+            batch.push(values);
+            count += 1;
+            if batch.len() == batch_size {
+                on_batch(std::mem::take(&mut batch))?;
+            }
+        }
+        if !batch.is_empty() {
+            on_batch(batch)?;
+        }
+        Ok(count)
+    }
+
+    /// Hashed files to verify, at most `limit`
+    ///
+    /// `VerifyOrder::Oldest` returns never verified files first, then the least
+    /// recently verified ones.
+    pub fn verify_candidates(
+        &self,
+        order: VerifyOrder,
+        limit: Option<u64>,
+    ) -> SqliteResult<Vec<FileRecord>> {
+        let order = match order {
+            VerifyOrder::Oldest => "last_verified IS NOT NULL, last_verified, path",
+            VerifyOrder::Random => "random()",
+        };
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM files WHERE hash IS NOT NULL ORDER BY {} LIMIT ?1",
+            FILE_RECORD_COLUMNS, order
+        ))?;
+        let limit = limit.map_or(-1, |l| l as i64);
+        let rows = stmt.query_map([limit], file_record_from_row)?;
+        rows.collect()
+    }
+
+    /// Record that `path` was verified at `verified_at` (Unix seconds)
+    ///
+    /// A mismatch marks the file `corrupted`; a match clears that mark.
+    pub fn record_verification(&self, path: &str, ok: bool, verified_at: i64) -> SqliteResult<()> {
+        let status = if ok {
+            "CASE status WHEN 'corrupted' THEN 'unchanged' ELSE status END"
+        } else {
+            "'corrupted'"
+        };
+        self.conn.execute(
+            &format!(
+                "UPDATE files SET last_verified = ?2, status = {} WHERE path = ?1",
+                status
+            ),
+            params![path, verified_at],
+        )?;
+        Ok(())
+    }
+
+    /// Record that `path` was looked at by a verification at `verified_at`
+    /// without being re-hashed (it was missing, changed or unreadable)
+    ///
+    /// The status is left alone; stamping the file keeps limited runs moving
+    /// on to other files instead of picking it again.
+    pub fn record_verification_skipped(&self, path: &str, verified_at: i64) -> SqliteResult<()> {
+        self.conn.execute(
+            "UPDATE files SET last_verified = ?2 WHERE path = ?1",
+            params![path, verified_at],
+        )?;
+        Ok(())
+    }
+
+    /// Compare this index (the older snapshot) with `other`
+    ///
+    /// The changes are returned as a `ScanResult` sorted by path:
+    /// - files only in `other` are new
+    /// - files whose size, mtime or hash changed are modified (hashes are only
+    ///   compared when both sides have one of the same kind)
+    /// - a new file with the size and hash of a removed one is moved, with
+    ///   `old_path` set
+    /// - the remaining removed files are in `deleted_paths`
+    ///
+    /// `total_files` is the number of files in `other`.
+    pub fn diff(&self, other: &ScanDatabase) -> SqliteResult<ScanResult> {
+        let start = std::time::Instant::now();
+        let mut old: HashMap<String, ScannedFile> = self
+            .query_files(&FileQuery::default())?
+            .into_iter()
+            .map(|f| (file_path(&f), f))
+            .collect();
+
+        let mut result = ScanResult::new();
+        let mut added = Vec::new();
+        for mut file in other.query_files(&FileQuery::default())? {
+            result.total_files += 1;
+            match old.remove(&file_path(&file)) {
+                None => added.push(file),
+                Some(before) if file_changed(&before, &file) => {
+                    file.status = FileStatus::Modified;
+                    file.old_path = None;
+                    result.modified_files += 1;
+                    result.files.push(file);
+                }
+                Some(_) => result.unchanged_files += 1,
+            }
+        }
+
+        // Removed files by content, each matched to at most one new file
+        let mut removed: Vec<ScannedFile> = old.into_values().collect();
+        removed.sort_by(|a, b| a.path.cmp(&b.path));
+        let mut by_content: HashMap<(String, u64), Vec<&str>> = HashMap::new();
+        for file in removed.iter().rev() {
+            if let Some(ref hash) = file.hash {
+                by_content
+                    .entry((hash.clone(), file.size))
+                    .or_default()
+                    .push(file.path.as_deref().and_then(Path::to_str).unwrap_or(""));
+            }
+        }
+        added.sort_by(|a, b| a.path.cmp(&b.path));
+        let mut moved_from = HashSet::new();
+        for mut file in added {
+            let source = file
+                .hash
+                .clone()
+                .and_then(|hash| by_content.get_mut(&(hash, file.size)))
+                .and_then(Vec::pop);
+            match source {
+                Some(old_path) => {
+                    moved_from.insert(old_path);
+                    file.status = FileStatus::Moved;
+                    file.old_path = Some(old_path.to_string());
+                    result.moved_files += 1;
+                }
+                None => {
+                    file.status = FileStatus::New;
+                    file.old_path = None;
+                    result.new_files += 1;
+                }
+            }
+            result.files.push(file);
+        }
+        result.deleted_paths = removed
+            .iter()
+            .map(file_path)
+            .filter(|path| !moved_from.contains(path.as_str()))
+            .collect();
+        result.deleted_files = result.deleted_paths.len() as u64;
+
+        result.sort(OutputOrder::Path);
+        result.duration_ms = start.elapsed().as_millis() as u64;
+        Ok(result)
+    }
+}
+
+/// Path of a file loaded from the database
+fn file_path(file: &ScannedFile) -> String {
+    file.path
+        .as_deref()
+        .map(|p| p.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Whether a file at the same path differs between two snapshots
+fn file_changed(before: &ScannedFile, after: &ScannedFile) -> bool {
+    let hash_changed = match (&before.hash, &after.hash) {
+        (Some(a), Some(b)) => before.is_partial_hash == after.is_partial_hash && a != b,
+        _ => false,
+    };
+    before.size != after.size || before.mtime != after.mtime || hash_changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::FileId;
+    use std::path::PathBuf;
+
+    fn file(path: &str, hash: &str, id: Option<(u64, u64)>) -> ScannedFile {
+        let name = path.rsplit('/').next().unwrap().to_string();
+        let mut file = ScannedFile::new(PathBuf::from(path), name, 10, 1, 1, "mp4".to_string())
+            .with_hash(hash.to_string(), false);
+        if let Some((dev, ino)) = id {
+            file = file.with_file_id(FileId { dev, ino });
+        }
+        file
+    }
+
+    #[test]
+    fn test_duplicates_count_hard_links_once() {
+        let mut db = ScanDatabase::open_memory().unwrap();
+        db.upsert_files(&[
+            file("/a/one.mp4", "h1", Some((1, 100))),
+            file("/a/one_link.mp4", "h1", Some((1, 100))),
+            file("/b/one_copy.mp4", "h1", Some((1, 200))),
+            file("/a/two.mp4", "h2", Some((1, 300))),
+            file("/a/two_link.mp4", "h2", Some((1, 300))),
+        ])
+        .unwrap();
+
+        let groups = db.get_duplicates().unwrap();
+        // h2 only has hard links to a single file
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].hash, "h1");
+        assert_eq!(groups[0].paths, vec!["/a/one.mp4", "/b/one_copy.mp4"]);
+        assert_eq!(groups[0].hard_links, 1);
+    }
+
+    #[test]
+    fn test_rename_replaces_indexed_target() {
+        let mut db = ScanDatabase::open_memory().unwrap();
+        db.upsert_files(&[
+            file("/m/new.mkv", "h1", None),
+            file("/m/old.mkv", "h2", None),
+            file("/m/a/clip.mp4", "h3", None),
+            file("/m/b/clip.mp4", "h4", None),
+        ])
+        .unwrap();
+        let dir = |path: &str| DirectoryRecord {
+            path: path.to_string(),
+            mtime_ns: 1,
+            entry_count: 0,
+            verified_at: 1,
+        };
+        db.upsert_directories(&[dir("/m/a"), dir("/m/a/sub"), dir("/m/b")])
+            .unwrap();
+
+        assert!(db.rename_file("/m/new.mkv", "/m/old.mkv").unwrap());
+        assert_eq!(
+            db.get_file("/m/old.mkv").unwrap().unwrap().hash.unwrap(),
+            "h1"
+        );
+
+        assert_eq!(db.rename_dir("/m/a", "/m/b").unwrap(), 1);
+        assert_eq!(
+            db.get_file("/m/b/clip.mp4").unwrap().unwrap().hash.unwrap(),
+            "h3"
+        );
+        let mut dirs: Vec<String> = db.load_directory_index().unwrap().into_keys().collect();
+        dirs.sort();
+        assert_eq!(dirs, vec!["/m/b", "/m/b/sub"]);
+
+        // Both replaced files are kept as deleted records
+        let deleted: Vec<String> = db
+            .get_deleted_files(None)
+            .unwrap()
+            .into_iter()
+            .filter_map(|f| f.hash)
+            .collect();
+        assert_eq!(deleted.len(), 2);
+        assert!(deleted.contains(&"h2".to_string()) && deleted.contains(&"h4".to_string()));
+    }
+
+    #[test]
+    fn test_query_files_and_stats() {
+        let mut db = ScanDatabase::open_memory().unwrap();
+        let mut big = file("/media/b/big.mp4", "h2", None);
+        big.size = 5000;
+        db.upsert_files(&[
+            file("/media/a/clip.mp4", "h1", None),
+            big,
+            file("/other/clip.mp4", "h3", None).with_status(FileStatus::Modified),
+        ])
+        .unwrap();
+
+        let all = db.query_files(&FileQuery::default()).unwrap();
+        assert_eq!(all.len(), 3);
+
+        let query = FileQuery {
+            path_prefix: Some("/media/".to_string()),
+            name: Some("clip".to_string()),
+            ..Default::default()
+        };
+        let files = db.query_files(&query).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path, Some(PathBuf::from("/media/a/clip.mp4")));
+        assert_eq!(files[0].media_type, MediaType::Video);
+
+        let query = FileQuery {
+            min_size: Some(100),
+            ..Default::default()
+        };
+        assert_eq!(db.query_files(&query).unwrap()[0].size, 5000);
+
+        let query = FileQuery {
+            status: Some(FileStatus::Modified),
+            path_prefix: Some("/other".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(db.query_files(&query).unwrap().len(), 1);
+
+        let query = FileQuery {
+            limit: Some(1),
+            offset: Some(1),
+            ..Default::default()
+        };
+        let files = db.query_files(&query).unwrap();
+        assert_eq!(files[0].path, Some(PathBuf::from("/media/b/big.mp4")));
+
+        let stats = db.get_stats().unwrap();
+        assert_eq!(stats.total_files, 3);
+        assert_eq!(stats.total_size, 5020);
+        assert_eq!(stats.by_media_type["video"], 3);
+        assert_eq!(stats.by_status["modified"], 1);
+    }
+
+    #[test]
+    fn test_scan_history() {
+        let mut db = ScanDatabase::open_memory().unwrap();
+        let mut result = ScanResult::new();
+        result.total_files = 3;
+        let roots = [PathBuf::from("/a"), PathBuf::from("/b")];
+        db.record_scan(&ScanRecord::from_result(&result, &roots, false))
+            .unwrap();
+        result.new_files = 1;
+        result.cancelled = true;
+        db.record_scan(&ScanRecord::from_result(&result, &roots[..1], true))
+            .unwrap();
+
+        let scans = db.get_scans(10).unwrap();
+        assert_eq!(scans.len(), 2);
+        assert!(scans[0].incremental && scans[0].cancelled);
+        assert_eq!(scans[0].new_files, 1);
+        assert_eq!(scans[0].roots, vec!["/a"]);
+        assert_eq!(scans[1].roots, vec!["/a", "/b"]);
+        assert_eq!(db.get_scans(1).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_purge_deleted_files() {
+        let mut db = ScanDatabase::open_memory().unwrap();
+        let paths = ["/m/a.mp4", "/m/b.mp4", "/n/c.mp4"];
+        db.upsert_files(&[
+            file(paths[0], "h1", None),
+            file(paths[1], "h2", None),
+            file(paths[2], "h3", None),
+        ])
+        .unwrap();
+        db.move_to_deleted(&paths.map(String::from)).unwrap();
+        // Deleted before, between and after two scans finishing at 100 and 200
+        for (path, deleted_at) in paths.iter().zip([50, 150, 250]) {
+            db.conn
+                .execute(
+                    "UPDATE deleted_files SET deleted_at = ?2 WHERE path = ?1",
+                    params![path, deleted_at],
+                )
+                .unwrap();
+        }
+        for finished_at in [100, 200] {
+            let mut record = ScanRecord::from_result(&ScanResult::new(), &[], true);
+            record.finished_at = finished_at;
+            db.record_scan(&record).unwrap();
+        }
+
+        let purge =
+            |db: &mut ScanDatabase, filter: DeletedFilter| db.purge_deleted_files(&filter).unwrap();
+        let under_n = DeletedFilter {
+            path_prefix: Some("/n/".to_string()),
+            deleted_before: Some(200),
+            ..Default::default()
+        };
+        assert_eq!(purge(&mut db, under_n), 0);
+        let keep_runs = |runs| DeletedFilter {
+            keep_runs: Some(runs),
+            ..Default::default()
+        };
+        assert_eq!(purge(&mut db, keep_runs(2)), 0);
+        assert_eq!(purge(&mut db, keep_runs(u64::MAX)), 0);
+        assert_eq!(purge(&mut db, keep_runs(1)), 1);
+
+        let index = db.load_deleted_hash_index().unwrap();
+        assert_eq!(index[&("h2".to_string(), 10)], vec!["/m/b.mp4"]);
+        assert!(!index.contains_key(&("h1".to_string(), 10)));
+
+        assert_eq!(db.apply_deleted_retention(None, None).unwrap(), 0);
+        // Limits too large to compute with keep everything
+        assert_eq!(db.apply_deleted_retention(Some(u64::MAX), None).unwrap(), 0);
+        assert_eq!(db.apply_deleted_retention(None, Some(u64::MAX)).unwrap(), 0);
+        assert_eq!(db.apply_deleted_retention(Some(1), None).unwrap(), 2);
+        assert_eq!(db.deleted_files_count().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_maintenance() {
+        let mut db = ScanDatabase::open_memory().unwrap();
+        db.upsert_files(&[
+            file("/m/a.mp4", "h1", None),
+            file("/m/sub/b.mp4", "h2", None),
+            file("/other/c.mp4", "h3", None),
+        ])
+        .unwrap();
+        db.move_to_deleted(&["/m/sub/b.mp4".to_string()]).unwrap();
+        let roots = [PathBuf::from("/m/")];
+        db.record_scan(&ScanRecord::from_result(&ScanResult::new(), &roots, false))
+            .unwrap();
+
+        let info = db.info().unwrap();
+        assert_eq!(info.schema_version, SCHEMA_VERSION);
+        assert_eq!(info.tables["files"], 2);
+        assert_eq!(info.tables["deleted_files"], 1);
+        assert!(info.size_bytes > 0);
+        assert!(info.last_scan.is_some());
+        db.vacuum().unwrap();
+
+        // Roots from the scan history
+        let check = db.check(&[]).unwrap();
+        assert!(check.integrity_errors.is_empty());
+        assert_eq!(check.roots, vec!["/m/"]);
+        assert_eq!(check.files_outside_roots, 1);
+        assert_eq!(check.outside_samples, vec!["/other/c.mp4"]);
+        assert!(!check.is_ok());
+        assert!(db.check(&["/m".into(), "/other".into()]).unwrap().is_ok());
+
+        let counts = db.prune_root("/m").unwrap();
+        assert_eq!((counts.files, counts.deleted_files), (1, 1));
+        assert_eq!(db.file_count().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_read_table_batches() {
+        let mut db = ScanDatabase::open_memory().unwrap();
+        db.upsert_files(&[
+            file("/m/c.mp4", "h3", None),
+            file("/m/a.mp4", "h1", None),
+            file("/m/b.mp4", "h2", None),
+        ])
+        .unwrap();
+
+        let columns = db.table_columns("files").unwrap();
+        let path = columns.iter().position(|c| c.name == "path").unwrap();
+        assert!(columns[path].primary_key && !columns[path].nullable);
+        assert_eq!(columns[path].decl_type, "TEXT");
+
+        let mut batches = Vec::new();
+        let rows = db
+            .read_table("files", 2, |batch| {
+                batches.push(batch);
+                Ok::<_, rusqlite::Error>(())
+            })
+            .unwrap();
+        assert_eq!(rows, 3);
+        assert_eq!(batches.iter().map(Vec::len).collect::<Vec<_>>(), vec![2, 1]);
+        assert_eq!(batches[0][0][path], SqlValue::Text("/m/a.mp4".to_string()));
+        assert_eq!(batches[0][0].len(), columns.len());
+
+        assert!(db.table_columns("sqlite_master").is_err());
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut old = ScanDatabase::open_memory().unwrap();
+        old.upsert_files(&[
+            file("/m/a.mp4", "h1", None),
+            file("/m/b.mp4", "h2", None),
+            file("/m/c.mp4", "h3", None),
+            file("/m/d.mp4", "h4", None),
+        ])
+        .unwrap();
+        let mut new = ScanDatabase::open_memory().unwrap();
+        new.upsert_files(&[
+            file("/m/a.mp4", "h1", None),
+            file("/m/b.mp4", "h2x", None),
+            file("/m/sub/c.mp4", "h3", None),
+            file("/m/e.mp4", "h5", None),
+        ])
+        .unwrap();
+
+        let diff = old.diff(&new).unwrap();
+        let changes: Vec<(&str, FileStatus, Option<&str>)> = diff
+            .files
+            .iter()
+            .map(|f| {
+                (
+                    f.path.as_deref().unwrap().to_str().unwrap(),
+                    f.status,
+                    f.old_path.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                ("/m/b.mp4", FileStatus::Modified, None),
+                ("/m/e.mp4", FileStatus::New, None),
+                ("/m/sub/c.mp4", FileStatus::Moved, Some("/m/c.mp4")),
+            ]
+        );
+        assert_eq!(diff.deleted_paths, vec!["/m/d.mp4"]);
+        assert_eq!(
+            (
+                diff.total_files,
+                diff.new_files,
+                diff.modified_files,
+                diff.moved_files,
+                diff.deleted_files,
+                diff.unchanged_files
+            ),
+            (4, 1, 1, 1, 1, 1)
+        );
+        assert!(new.diff(&new).unwrap().files.is_empty());
+    }
 }
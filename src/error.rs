@@ -18,6 +18,8 @@ pub enum ScanErrorKind {
     HashError,
     /// Invalid path encoding
     InvalidPath,
+    /// Invalid or unreadable configuration
+    ConfigError,
     /// Unknown error
     Unknown,
 }
@@ -72,6 +74,11 @@ impl ScanError {
         Self::new(ScanErrorKind::DatabaseError, None, message)
     }
 
+    /// Create a configuration error
+    pub fn config_error(path: Option<PathBuf>, message: impl Into<String>) -> Self {
+        Self::new(ScanErrorKind::ConfigError, path, message)
+    }
+
     /// Create a hash computation error
     pub fn hash_error(path: PathBuf, message: impl Into<String>) -> Self {
         Self::new(ScanErrorKind::HashError, Some(path), message)
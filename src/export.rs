@@ -0,0 +1,302 @@
+//! Tabular export of scanned files (CSV and TSV)
+//!
+//! Rows hold a selectable subset of `ScannedFile` fields. Fields containing
+//! the separator, a quote or a line break are quoted as in RFC 4180, for both
+//! separators, so paths with commas, tabs or newlines survive a round trip.
+
+use std::io::{self, Write};
+
+use crate::models::{FileStatus, ScannedFile};
+
+/// Separated-values flavor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableFormat {
+    /// Comma-separated values
+    Csv,
+    /// Tab-separated values
+    Tsv,
+}
+
+impl TableFormat {
+    /// Get string representation
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TableFormat::Csv => "csv",
+            TableFormat::Tsv => "tsv",
+        }
+    }
+
+    /// Field separator
+    pub fn separator(&self) -> char {
+        match self {
+            TableFormat::Csv => ',',
+            TableFormat::Tsv => '\t',
+        }
+    }
+
+    /// MIME type of the output
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            TableFormat::Csv => "text/csv; charset=utf-8",
+            TableFormat::Tsv => "text/tab-separated-values; charset=utf-8",
+        }
+    }
+}
+
+impl std::str::FromStr for TableFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(TableFormat::Csv),
+            "tsv" => Ok(TableFormat::Tsv),
+            _ => Err(format!(
+                "invalid table format {:?} (expected csv or tsv)",
+                s
+            )),
+        }
+    }
+}
+
+/// A `ScannedFile` field that can be exported as a column
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    Path,
+    Name,
+    Size,
+    Mtime,
+    Ctime,
+    Extension,
+    MediaType,
+    Hash,
+    Status,
+    OldPath,
+}
+
+impl Column {
+    /// All columns, in the default order
+    pub const ALL: [Column; 10] = [
+        Column::Path,
+        Column::Name,
+        Column::Size,
+        Column::Mtime,
+        Column::Ctime,
+        Column::Extension,
+        Column::MediaType,
+        Column::Hash,
+        Column::Status,
+        Column::OldPath,
+    ];
+
+    /// Header name (the `ScannedFile` field name)
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Column::Path => "path",
+            Column::Name => "name",
+            Column::Size => "size",
+            Column::Mtime => "mtime",
+            Column::Ctime => "ctime",
+            Column::Extension => "extension",
+            Column::MediaType => "media_type",
+            Column::Hash => "hash",
+            Column::Status => "status",
+            Column::OldPath => "old_path",
+        }
+    }
+
+    /// Value of this column for `file` (empty when unset)
+    pub fn value(&self, file: &ScannedFile) -> String {
+        match self {
+            Column::Path => file
+                .full_path()
+                .map(|p| p.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            Column::Name => file.name.clone(),
+            Column::Size => file.size.to_string(),
+            Column::Mtime => file.mtime.to_string(),
+            Column::Ctime => file.ctime.to_string(),
+            Column::Extension => file.extension.clone(),
+            Column::MediaType => file.media_type.as_str().to_string(),
+            Column::Hash => file.hash.clone().unwrap_or_default(),
+            Column::Status => file.status.as_str().to_string(),
+            Column::OldPath => file.old_path.clone().unwrap_or_default(),
+        }
+    }
+
+    /// Parse a comma-separated column list such as `path,size,hash`
+    pub fn parse_list(s: &str) -> Result<Vec<Column>, String> {
+        let columns = s
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<Column>, String>>()?;
+        if columns.is_empty() {
+            return Err("no columns selected".to_string());
+        }
+        Ok(columns)
+    }
+}
+
+impl std::str::FromStr for Column {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_lowercase();
+        Column::ALL
+            .into_iter()
+            .find(|c| c.as_str() == name)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Column::ALL.iter().map(Column::as_str).collect();
+                format!("unknown column {:?} (expected {})", s, names.join(", "))
+            })
+    }
+}
+
+/// Writer of a header row followed by one row per file
+pub struct TableWriter<W: Write> {
+    writer: W,
+    format: TableFormat,
+    columns: Vec<Column>,
+}
+
+impl<W: Write> TableWriter<W> {
+    /// Create a writer and write the header row
+    pub fn new(mut writer: W, format: TableFormat, columns: &[Column]) -> io::Result<Self> {
+        let header: Vec<&str> = columns.iter().map(Column::as_str).collect();
+        write_row(&mut writer, format, header)?;
+        Ok(Self {
+            writer,
+            format,
+            columns: columns.to_vec(),
+        })
+    }
+
+    /// Write the row for `file`
+    pub fn write_file(&mut self, file: &ScannedFile) -> io::Result<()> {
+        let values: Vec<String> = self.columns.iter().map(|c| c.value(file)).collect();
+        write_row(
+            &mut self.writer,
+            self.format,
+            values.iter().map(String::as_str),
+        )
+    }
+
+    /// Write the row for a path deleted since the previous scan
+    ///
+    /// Only the `path`, `name` and `status` columns are filled.
+    pub fn write_deleted(&mut self, path: &str) -> io::Result<()> {
+        let name = std::path::Path::new(path)
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let values = self.columns.iter().map(|c| match c {
+            Column::Path => path,
+            Column::Name => name.as_str(),
+            Column::Status => FileStatus::Deleted.as_str(),
+            _ => "",
+        });
+        write_row(&mut self.writer, self.format, values)
+    }
+
+    /// Get the underlying writer back
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Render `files` as a table, header included
+pub fn to_table(files: &[ScannedFile], format: TableFormat, columns: &[Column]) -> String {
+    let mut table = TableWriter::new(Vec::new(), format, columns).expect("writing to a Vec");
+    for file in files {
+        table.write_file(file).expect("writing to a Vec");
+    }
+    String::from_utf8(table.into_inner()).expect("fields are UTF-8")
+}
+
+fn write_row<'a, W: Write>(
+    writer: &mut W,
+    format: TableFormat,
+    values: impl IntoIterator<Item = &'a str>,
+) -> io::Result<()> {
+    let separator = format.separator();
+    for (i, value) in values.into_iter().enumerate() {
+        if i > 0 {
+            write!(writer, "{}", separator)?;
+        }
+        write_field(writer, value, separator)?;
+    }
+    // CRLF as in RFC 4180; spreadsheets accept it for TSV too
+    writer.write_all(b"\r\n")
+}
+
+fn write_field<W: Write>(writer: &mut W, value: &str, separator: char) -> io::Result<()> {
+    let needs_quotes = value
+        .chars()
+        .any(|c| c == separator || c == '"' || c == '\n' || c == '\r');
+    if needs_quotes {
+        write!(writer, "\"{}\"", value.replace('"', "\"\""))
+    } else {
+        writer.write_all(value.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str) -> ScannedFile {
+        let name = path.rsplit('/').next().unwrap().to_string();
+        ScannedFile::new(path.into(), name, 42, 100, 90, "mp4".to_string())
+    }
+
+    #[test]
+    fn test_parse_columns() {
+        assert_eq!(
+            Column::parse_list("path, size,MEDIA_TYPE").unwrap(),
+            vec![Column::Path, Column::Size, Column::MediaType]
+        );
+        assert!(Column::parse_list("path,inode").is_err());
+        assert!(Column::parse_list(" , ").is_err());
+    }
+
+    #[test]
+    fn test_table_format_from_str() {
+        assert_eq!("TSV".parse::<TableFormat>(), Ok(TableFormat::Tsv));
+        assert!("xls".parse::<TableFormat>().is_err());
+    }
+
+    #[test]
+    fn test_csv_quoting() {
+        let mut quoted = file("/media/a, \"b\"\nc.mp4");
+        quoted.hash = Some("abc".to_string());
+        let files = [file("/media/plain.mp4"), quoted];
+        let columns = [Column::Path, Column::Size, Column::Hash, Column::Status];
+
+        let csv = to_table(&files, TableFormat::Csv, &columns);
+        assert_eq!(
+            csv,
+            "path,size,hash,status\r\n\
+             /media/plain.mp4,42,,new\r\n\
+             \"/media/a, \"\"b\"\"\nc.mp4\",42,abc,new\r\n"
+        );
+
+        // Commas need no quoting in TSV, tabs do
+        let tsv = to_table(&[file("/media/a,b\tc.mp4")], TableFormat::Tsv, &columns);
+        assert_eq!(
+            tsv,
+            "path\tsize\thash\tstatus\r\n\"/media/a,b\tc.mp4\"\t42\t\tnew\r\n"
+        );
+    }
+
+    #[test]
+    fn test_deleted_rows() {
+        let mut table = TableWriter::new(Vec::new(), TableFormat::Csv, &Column::ALL).unwrap();
+        table.write_deleted("/media/gone.mkv").unwrap();
+        let text = String::from_utf8(table.into_inner()).unwrap();
+        assert_eq!(
+            text.lines().nth(1).unwrap(),
+            "/media/gone.mkv,gone.mkv,,,,,,,deleted,"
+        );
+    }
+}
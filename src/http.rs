@@ -0,0 +1,495 @@
+//! HTTP API - REST endpoints over the scan database
+//!
+//! Meant for a dashboard on the same machine, so it should be bound to a
+//! loopback address. Responses are JSON unless a table format is requested;
+//! files use the same fields as the NDJSON output.
+//!
+//! Requests must name a loopback `Host` (and `Origin`, when sent), and POST
+//! requests must be `application/json`, so web pages cannot reach the API
+//! through DNS rebinding or simple cross-site form posts.
+//!
+//! Endpoints:
+//! - `GET /api/files`: files filtered by `status`, `media_type`, `path_prefix`,
+//!   `name`, `min_size`, `max_size`, paged with `limit` and `offset`;
+//!   `format=csv|tsv` returns a table of the `columns` (comma-separated)
+//! - `GET /api/stats`: `DbStats`
+//! - `GET /api/duplicates`: groups of files sharing a hash
+//! - `GET /api/deleted`: deleted files, optionally `since` a Unix timestamp
+//! - `GET /api/scans`: scan history, newest first (`limit`, default 20)
+//! - `GET /api/scan`: whether a scan is running
+//! - `POST /api/scan`: start a scan; the body holds `ScanConfig` fields and
+//!   `?incremental=true` selects an incremental scan
+//! - `POST /api/scan/cancel`: stop the running scan
+
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::thread::JoinHandle;
+use tiny_http::{Header, Method, Request, Response};
+
+use crate::config::ScanConfig;
+use crate::db::{FileQuery, ScanDatabase};
+use crate::error::ScanError;
+use crate::export::{to_table, Column, TableFormat};
+use crate::progress::ProgressReporter;
+use crate::scanner::CancelToken;
+use crate::serve::{run_scan, scan_config};
+
+/// Number of files returned by `/api/files` when no `limit` is given
+pub const DEFAULT_PAGE_SIZE: u64 = 1000;
+
+/// Number of scans returned by `/api/scans` when no `limit` is given
+const DEFAULT_SCAN_HISTORY: u64 = 20;
+
+/// Body of a response
+#[derive(Debug, Clone, PartialEq)]
+pub enum Body {
+    /// Sent as `application/json`
+    Json(Value),
+    /// Sent as is, e.g. a CSV or TSV table
+    Text {
+        content_type: &'static str,
+        body: String,
+    },
+}
+
+/// Status code and body of a response
+type ApiResponse = (u16, Body);
+
+/// Request router holding the database and the background scan
+pub struct Api {
+    /// Server configuration as JSON, the base for scan requests
+    base_config: Map<String, Value>,
+    db_path: PathBuf,
+    db: ScanDatabase,
+    cancel: CancelToken,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Api {
+    /// Create a router using `config` as the base scan configuration
+    pub fn new(config: &ScanConfig, db_path: PathBuf) -> Result<Self, ScanError> {
+        let base_config = match serde_json::to_value(config) {
+            Ok(Value::Object(map)) => map,
+            Ok(_) => Map::new(),
+            Err(e) => return Err(ScanError::config_error(None, e.to_string())),
+        };
+        let db = ScanDatabase::open(&db_path)?;
+        Ok(Self {
+            base_config,
+            db_path,
+            db,
+            cancel: CancelToken::new(),
+            worker: None,
+        })
+    }
+
+    /// Whether a scan is currently running
+    pub fn is_scanning(&self) -> bool {
+        self.worker.as_ref().is_some_and(|w| !w.is_finished())
+    }
+
+    /// Wait for a running scan to finish
+    pub fn wait(&mut self) {
+        if let Some(worker) = self.worker.take() {
+            if worker.join().is_err() {
+                log::error!("Scan worker panicked");
+            }
+        }
+    }
+
+    /// Handle a request for `url` (path and query string)
+    pub fn route(&mut self, method: &Method, url: &str, body: &str) -> ApiResponse {
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+        let params = parse_query(query);
+
+        let result = match (method, path.trim_end_matches('/')) {
+            (Method::Get, "/api/files") => self.files(&params),
+            (Method::Get, "/api/stats") => to_json(self.db.get_stats()),
+            (Method::Get, "/api/duplicates") => to_json(self.db.get_duplicates()),
+            (Method::Get, "/api/deleted") => match number(&params, "since") {
+                Ok(since) => to_json(self.db.get_deleted_files(since.map(|s| s as i64))),
+                Err(e) => Err(e),
+            },
+            (Method::Get, "/api/scans") => match number(&params, "limit") {
+                Ok(limit) => to_json(self.db.get_scans(limit.unwrap_or(DEFAULT_SCAN_HISTORY))),
+                Err(e) => Err(e),
+            },
+            (Method::Get, "/api/scan") => {
+                Ok((200, Body::Json(json!({ "running": self.is_scanning() }))))
+            }
+            (Method::Post, "/api/scan") => self.start_scan(&params, body),
+            (Method::Post, "/api/scan/cancel") => Ok((200, Body::Json(self.cancel()))),
+            (
+                _,
+                "/api/files" | "/api/stats" | "/api/duplicates" | "/api/deleted" | "/api/scans"
+                | "/api/scan" | "/api/scan/cancel",
+            ) => Err((405, "method not allowed".to_string())),
+            _ => Err((404, format!("no such endpoint: {}", path))),
+        };
+
+        result.unwrap_or_else(|(status, message)| (status, error_body(&message)))
+    }
+
+    fn files(&self, params: &HashMap<String, String>) -> Result<ApiResponse, (u16, String)> {
+        let mut query = file_query(params)?;
+        query.limit = Some(query.limit.unwrap_or(DEFAULT_PAGE_SIZE));
+        let Some(format) = table_format(params)? else {
+            return to_json(self.db.query_files(&query));
+        };
+        let columns = match params.get("columns") {
+            Some(list) => Column::parse_list(list).map_err(|e| (400, e))?,
+            None => Column::ALL.to_vec(),
+        };
+        let files = self
+            .db
+            .query_files(&query)
+            .map_err(|e| (500, e.to_string()))?;
+        Ok((
+            200,
+            Body::Text {
+                content_type: format.content_type(),
+                body: to_table(&files, format, &columns),
+            },
+        ))
+    }
+
+    /// Start a scan in the background
+    fn start_scan(
+        &mut self,
+        params: &HashMap<String, String>,
+        body: &str,
+    ) -> Result<ApiResponse, (u16, String)> {
+        if self.is_scanning() {
+            return Err((409, "a scan is already running".to_string()));
+        }
+        let incremental = match params.get("incremental").map(String::as_str) {
+            None | Some("false") | Some("0") => false,
+            Some("true") | Some("1") | Some("") => true,
+            Some(other) => return Err((400, format!("invalid incremental: {:?}", other))),
+        };
+        let body = if body.trim().is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_str(body).map_err(|e| (400, e.to_string()))?
+        };
+        let config = scan_config(&self.base_config, body, &self.db_path).map_err(|e| (400, e))?;
+        self.wait();
+
+        self.cancel = CancelToken::new();
+        let cancel = self.cancel.clone();
+        let db_path = self.db_path.clone();
+        self.worker = Some(std::thread::spawn(move || {
+            let reporter = ProgressReporter::from_config(&config, config.show_progress);
+            match run_scan(&config, &db_path, &reporter, &cancel, incremental) {
+                Ok(result) => log::info!(
+                    "Scan finished: {} files, {} new, {} modified, {} deleted",
+                    result.total_files,
+                    result.new_files,
+                    result.modified_files,
+                    result.deleted_files
+                ),
+                Err(e) => log::error!("Scan failed: {}", e),
+            }
+        }));
+        Ok((
+            202,
+            Body::Json(json!({ "started": true, "incremental": incremental })),
+        ))
+    }
+
+    fn cancel(&self) -> Value {
+        let running = self.is_scanning();
+        if running {
+            self.cancel.cancel();
+        }
+        json!({ "cancelled": running })
+    }
+
+    /// Read a request, route it and send the response
+    fn respond(&mut self, mut request: Request) {
+        let mut body = String::new();
+        let (status, response) = match check_headers(request.method(), request.headers()) {
+            Err((status, message)) => (status, error_body(&message)),
+            Ok(()) => match request.as_reader().read_to_string(&mut body) {
+                Ok(_) => self.route(request.method(), request.url(), &body),
+                Err(e) => (400, error_body(&e.to_string())),
+            },
+        };
+        let (body, content_type) = match response {
+            Body::Json(value) => (value.to_string(), "application/json"),
+            Body::Text { content_type, body } => (body, content_type),
+        };
+        let content_type = Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes())
+            .expect("content types are valid header values");
+        let response = Response::from_string(body)
+            .with_status_code(status)
+            .with_header(content_type);
+        if let Err(e) = request.respond(response) {
+            log::warn!("Failed to send response: {}", e);
+        }
+    }
+}
+
+/// Value of a request header
+fn header<'a>(headers: &'a [Header], name: &'static str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|h| h.field.equiv(name))
+        .map(|h| h.value.as_str())
+}
+
+/// Check if the host part of a `Host` header or origin is a loopback name
+fn is_loopback_host(host: &str) -> bool {
+    let name = match host.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or(""),
+        None => host.split(':').next().unwrap_or(""),
+    };
+    name.eq_ignore_ascii_case("localhost")
+        || name.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+/// Reject requests a web page could send from another site
+fn check_headers(method: &Method, headers: &[Header]) -> Result<(), (u16, String)> {
+    match header(headers, "Host") {
+        Some(host) if is_loopback_host(host) => {}
+        host => return Err((403, format!("host not allowed: {:?}", host.unwrap_or("")))),
+    }
+    if let Some(origin) = header(headers, "Origin") {
+        let host = origin.split_once("://").map_or("", |(_, rest)| rest);
+        if !is_loopback_host(host) {
+            return Err((403, format!("origin not allowed: {:?}", origin)));
+        }
+    }
+    if *method == Method::Post {
+        let content_type = header(headers, "Content-Type").unwrap_or("");
+        let mime = content_type.split(';').next().unwrap_or("").trim();
+        if !mime.eq_ignore_ascii_case("application/json") {
+            return Err((415, "POST requests must be application/json".to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// JSON body of an error response
+fn error_body(message: &str) -> Body {
+    Body::Json(json!({ "error": message }))
+}
+
+/// Serialize a database result as a 200 response
+fn to_json<T: serde::Serialize>(result: rusqlite::Result<T>) -> Result<ApiResponse, (u16, String)> {
+    let value = result.map_err(|e| (500, e.to_string()))?;
+    serde_json::to_value(value)
+        .map(|v| (200, Body::Json(v)))
+        .map_err(|e| (500, e.to_string()))
+}
+
+/// Parse the optional `format` query parameter (`json`, `csv` or `tsv`)
+fn table_format(params: &HashMap<String, String>) -> Result<Option<TableFormat>, (u16, String)> {
+    match params.get("format").map(String::as_str) {
+        None | Some("json") => Ok(None),
+        Some(format) => format.parse().map(Some).map_err(|e| (400, e)),
+    }
+}
+
+/// Parse an optional numeric query parameter
+fn number(params: &HashMap<String, String>, key: &str) -> Result<Option<u64>, (u16, String)> {
+    params
+        .get(key)
+        .map(|v| {
+            v.parse()
+                .map_err(|_| (400, format!("invalid {}: {:?}", key, v)))
+        })
+        .transpose()
+}
+
+/// Build a FileQuery from query parameters
+fn file_query(params: &HashMap<String, String>) -> Result<FileQuery, (u16, String)> {
+    Ok(FileQuery {
+        status: params
+            .get("status")
+            .map(|s| s.parse())
+            .transpose()
+            .map_err(|e| (400, e))?,
+        media_type: params
+            .get("media_type")
+            .map(|s| s.parse())
+            .transpose()
+            .map_err(|e| (400, e))?,
+        path_prefix: params.get("path_prefix").cloned(),
+        name: params.get("name").cloned(),
+        min_size: number(params, "min_size")?,
+        max_size: number(params, "max_size")?,
+        limit: number(params, "limit")?,
+        offset: number(params, "offset")?,
+    })
+}
+
+/// Split a query string into decoded key/value pairs
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect()
+}
+
+/// Decode `%XX` escapes and `+` in a query string component
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len()
+                && bytes[i + 1].is_ascii_hexdigit()
+                && bytes[i + 2].is_ascii_hexdigit() =>
+            {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+                out.push(u8::from_str_radix(hex, 16).expect("checked hex digits"));
+                i += 2;
+            }
+            byte => out.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Serve the HTTP API on `listen` (e.g. `127.0.0.1:8080`) until the process exits
+pub fn serve_http(config: &ScanConfig, db_path: PathBuf, listen: &str) -> Result<(), ScanError> {
+    let mut api = Api::new(config, db_path)?;
+    let server = tiny_http::Server::http(listen)
+        .map_err(|e| ScanError::io_error(None, format!("cannot listen on {}: {}", listen, e)))?;
+    log::info!("HTTP API listening on http://{}", listen);
+
+    for request in server.incoming_requests() {
+        api.respond(request);
+    }
+    api.wait();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("a%20b+c"), "a b c");
+        assert_eq!(percent_decode("%E8%A7%86%E9%A2%91"), "视频");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz"), "%zz");
+        assert_eq!(percent_decode("%+1"), "% 1");
+    }
+
+    #[test]
+    fn test_check_headers() {
+        let headers = |pairs: &[(&str, &str)]| -> Vec<Header> {
+            pairs
+                .iter()
+                .map(|(k, v)| Header::from_bytes(k.as_bytes(), v.as_bytes()).unwrap())
+                .collect()
+        };
+        let local = headers(&[("Host", "127.0.0.1:8080")]);
+        assert!(check_headers(&Method::Get, &local).is_ok());
+        assert!(check_headers(&Method::Get, &headers(&[("Host", "[::1]:8080")])).is_ok());
+        assert!(check_headers(&Method::Get, &headers(&[("Host", "localhost")])).is_ok());
+
+        // DNS rebinding keeps the attacker's name in Host
+        let rebound = headers(&[("Host", "evil.example:8080")]);
+        assert_eq!(check_headers(&Method::Get, &rebound).unwrap_err().0, 403);
+        assert_eq!(check_headers(&Method::Get, &[]).unwrap_err().0, 403);
+        let cross = headers(&[
+            ("Host", "127.0.0.1:8080"),
+            ("Origin", "https://evil.example"),
+        ]);
+        assert_eq!(check_headers(&Method::Get, &cross).unwrap_err().0, 403);
+
+        // Simple cross-site POSTs cannot send application/json
+        let form = headers(&[("Host", "127.0.0.1:8080"), ("Content-Type", "text/plain")]);
+        assert_eq!(check_headers(&Method::Post, &form).unwrap_err().0, 415);
+        assert_eq!(check_headers(&Method::Post, &local).unwrap_err().0, 415);
+        let json = headers(&[
+            ("Host", "localhost:8080"),
+            ("Origin", "http://localhost:8080"),
+            ("Content-Type", "application/json; charset=utf-8"),
+        ]);
+        assert!(check_headers(&Method::Post, &json).is_ok());
+    }
+
+    /// Status and JSON value of a response that must have a JSON body
+    fn json_response((status, body): ApiResponse) -> (u16, Value) {
+        match body {
+            Body::Json(value) => (status, value),
+            other => panic!("expected a JSON body, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_http_api_routes() {
+        let dir = tempfile::tempdir().unwrap();
+        let media = dir.path().join("media");
+        fs::create_dir(&media).unwrap();
+        fs::write(media.join("a.mp4"), b"video").unwrap();
+        fs::write(media.join("b.jpg"), b"image").unwrap();
+
+        let mut api = Api::new(&ScanConfig::default(), dir.path().join("scan.db")).unwrap();
+        let (status, body) = json_response(api.route(&Method::Post, "/api/scan", "{}"));
+        assert_eq!(status, 400, "{}", body);
+
+        let request = json!({ "roots": [media] }).to_string();
+        let (status, _) = api.route(&Method::Post, "/api/scan?incremental=true", &request);
+        assert_eq!(status, 202);
+        let start = Instant::now();
+        while api.is_scanning() && start.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let (status, files) =
+            json_response(api.route(&Method::Get, "/api/files?media_type=video", ""));
+        assert_eq!(status, 200);
+        assert_eq!(files.as_array().unwrap().len(), 1);
+        assert_eq!(files[0]["name"], "a.mp4");
+        assert_eq!(files[0]["media_type"], "video");
+
+        let (_, stats) = json_response(api.route(&Method::Get, "/api/stats", ""));
+        assert_eq!(stats["total_files"], 2);
+
+        let (_, scans) = json_response(api.route(&Method::Get, "/api/scans", ""));
+        assert_eq!(scans[0]["incremental"], true);
+        assert_eq!(scans[0]["total_files"], 2);
+
+        let (_, deleted) = json_response(api.route(&Method::Get, "/api/deleted?since=0", ""));
+        assert!(deleted.as_array().unwrap().is_empty());
+
+        let (status, csv) = api.route(&Method::Get, "/api/files?format=csv&columns=name,size", "");
+        assert_eq!(status, 200);
+        let Body::Text { content_type, body } = csv else {
+            panic!("expected a text body, got {:?}", csv);
+        };
+        assert_eq!(content_type, "text/csv; charset=utf-8");
+        assert!(body.starts_with("name,size\r\n"));
+        assert!(body.contains("a.mp4,5\r\n"));
+        assert_eq!(api.route(&Method::Get, "/api/files?format=xls", "").0, 400);
+        assert_eq!(
+            api.route(&Method::Get, "/api/files?format=tsv&columns=x", "")
+                .0,
+            400
+        );
+
+        assert_eq!(api.route(&Method::Get, "/api/files?min_size=x", "").0, 400);
+        assert_eq!(api.route(&Method::Get, "/api/files?status=gone", "").0, 400);
+        assert_eq!(api.route(&Method::Delete, "/api/files", "").0, 405);
+        assert_eq!(api.route(&Method::Get, "/api/nope", "").0, 404);
+        let (_, cancel) = json_response(api.route(&Method::Post, "/api/scan/cancel", ""));
+        assert_eq!(cancel["cancelled"], false);
+    }
+}
@@ -5,20 +5,56 @@
 
 #![allow(dead_code)]
 
+#[cfg(feature = "columnar")]
+pub mod columnar;
 pub mod config;
 pub mod db;
 pub mod error;
+pub mod export;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod manifest;
 pub mod models;
+pub mod output;
 pub mod progress;
+pub mod rules;
 pub mod scanner;
+pub mod schema;
+pub mod serve;
+pub mod verify;
+#[cfg(target_os = "linux")]
+pub mod watch;
 
-pub use config::ScanConfig;
-pub use db::ScanDatabase;
+#[cfg(feature = "columnar")]
+pub use columnar::{export_database, ColumnarFormat};
+pub use config::{
+    ChangeDetection, ConfigFormat, HiddenPolicy, OutputOrder, PathRules, ProgressFormat, RootRules,
+    ScanConfig, TimeBound,
+};
+pub use db::{
+    DbCheck, DbInfo, DbStats, DeletedFilter, DirectoryRecord, FileQuery, PruneCounts, ScanDatabase,
+    ScanRecord, TableColumn, SCHEMA_VERSION,
+};
 pub use error::{ScanError, ScanErrorKind};
+pub use export::{Column, TableFormat, TableWriter};
+pub use manifest::{
+    build_manifest, read_manifest, verify_manifest, Manifest, ManifestAlgorithm, ManifestCheck,
+    ManifestEntry,
+};
 pub use models::{
-    CompactFile, FileStatus, MediaType, ScanProgress, ScanResult, ScannedDirectory, ScannedFile,
+    CompactFile, FileId, FileStatus, FilterCounts, MediaType, ScanProgress, ScanResult,
+    ScannedDirectory, ScannedFile,
 };
+pub use output::{CompactWriter, NdjsonWriter, OutputFormat, ResultWriter};
 pub use progress::{
-    DoneMessage, ErrorProgressMessage, ProgressMessage, ProgressReporter, ScanPhase, StartMessage,
+    DoneMessage, ErrorProgressMessage, JsonLineSink, JsonWriterSink, ProgressMessage,
+    ProgressReporter, ProgressSink, ScanPhase, StartMessage, StderrJsonSink, TerminalSink,
+    PROTOCOL_VERSION,
+};
+pub use rules::{FileFilter, FilterReason, PathFilter};
+pub use scanner::{
+    scan_full, scan_full_with_cancel, scan_full_with_sink, scan_incremental,
+    scan_incremental_with_cancel, scan_incremental_with_sink, CancelToken,
 };
-pub use scanner::{scan_full, scan_incremental};
+pub use schema::protocol_schema;
+pub use verify::{verify_files, VerifyOptions, VerifyOrder, VerifyResult};
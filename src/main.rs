@@ -2,18 +2,33 @@
 //!
 //! High-performance media file scanner with parallel directory traversal.
 
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use env_logger::Env;
 use log::info;
-use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufWriter, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::OnceLock;
 
+use media_scanner::columnar::{export_database, ColumnarFormat, DEFAULT_BATCH_ROWS};
+use media_scanner::config::{parse_duration, parse_size};
+use media_scanner::http::serve_http;
+use media_scanner::manifest::{build_manifest, verify_manifest, ManifestAlgorithm};
+use media_scanner::schema::protocol_schema;
+use media_scanner::serve::serve;
+use media_scanner::verify::{verify_files, VerifyOptions, VerifyOrder};
+#[cfg(target_os = "linux")]
+use media_scanner::watch::{watch, DEFAULT_DEBOUNCE_MS};
 use media_scanner::{
-    scan_full, scan_incremental, CompactFile, ScanConfig, ScanDatabase, ScannedDirectory,
+    scan_full_with_cancel, scan_incremental_with_cancel, CancelToken, ChangeDetection, Column,
+    ConfigFormat, DeletedFilter, HiddenPolicy, OutputFormat, OutputOrder, ProgressFormat,
+    ResultWriter, ScanConfig, ScanDatabase, ScanError, ScanRecord, ScanResult, TimeBound,
 };
 
+/// Default database file used when neither the CLI nor the config sets one
+const DEFAULT_DB_PATH: &str = "media_scanner.db";
+
 const ABOUT: &str = r#"
 Media Scanner - 高性能媒体文件扫描器
 
@@ -29,6 +44,13 @@ Media Scanner - 高性能媒体文件扫描器
   media_scanner scan -r /media -o result.json       输出到文件
   media_scanner scan -r /media -d output.db         指定数据库文件
   media_scanner scan -r /media -p --progress-interval 500  自定义进度报告间隔
+  media_scanner scan -r /media --progress-fd 3 3>progress.ndjson  进度写入单独的文件描述符
+  media_scanner scan -r /media --exclude '**/Samples/**' --exclude '*.part'  排除匹配的路径
+  media_scanner scan -c scanner.toml                从配置文件读取扫描设置
+  media_scanner watch -r /media -d media.db         增量扫描后持续监听变化（NDJSON 输出）
+  media_scanner serve --stdio -c scanner.toml       通过标准输入输出提供 JSON-RPC 服务
+  media_scanner http --listen 127.0.0.1:8080 -d media.db  提供本地 HTTP API
+  media_scanner config dump -c scanner.toml -t 8    输出合并后的有效配置
 
 更多信息请查看: https://github.com/your-repo/media-scanner
 "#;
@@ -54,21 +76,8 @@ enum Commands {
     /// 扫描目录中的媒体文件
     #[command(about = "扫描目录中的媒体文件")]
     Scan {
-        /// 扫描的根目录（可指定多个）
-        #[arg(short = 'r', long, required = true)]
-        roots: Vec<PathBuf>,
-
-        /// 并行线程数（0 = 自动检测）
-        #[arg(short = 't', long, default_value = "0")]
-        threads: usize,
-
-        /// 数据库批量写入大小
-        #[arg(short = 'b', long, default_value = "1000")]
-        batch_size: usize,
-
-        /// 数据库文件路径
-        #[arg(short = 'd', long)]
-        db: Option<PathBuf>,
+        #[command(flatten)]
+        config: ConfigArgs,
 
         /// 执行增量扫描
         #[arg(short = 'i', long)]
@@ -86,34 +95,629 @@ enum Commands {
         #[arg(long)]
         compact: bool,
 
+        /// 以 CSV 格式输出（每行一个文件，含表头）
+        #[arg(long, conflicts_with = "tsv")]
+        csv: bool,
+
+        /// 以 TSV 格式输出（制表符分隔，含表头）
+        #[arg(long)]
+        tsv: bool,
+
+        /// CSV/TSV 输出的列（逗号分隔，默认全部：path,name,size,mtime,ctime,extension,media_type,hash,status,old_path）
+        #[arg(long, value_name = "COLUMNS", value_delimiter = ',')]
+        columns: Vec<Column>,
+
         /// 输出结果到文件（避免stdout缓冲问题）
         #[arg(short = 'o', long)]
         output: Option<PathBuf>,
 
-        /// 计算文件哈希（默认不计算）
+        /// 清空已删除文件记录表
         #[arg(long)]
-        hash: bool,
+        clear_deleted: bool,
+    },
+
+    /// 先执行增量扫描，然后监听文件变化并实时更新数据库（仅 Linux）
+    #[cfg(target_os = "linux")]
+    #[command(about = "监听目录变化并实时更新数据库（仅 Linux）")]
+    Watch {
+        #[command(flatten)]
+        config: ConfigArgs,
+
+        /// 文件停止写入多少毫秒后才进行索引（避免处理写入中的文件）
+        #[arg(long, default_value_t = DEFAULT_DEBOUNCE_MS)]
+        debounce_ms: u64,
+    },
+
+    /// 以 JSON-RPC 服务模式运行（每行一个请求/响应）
+    #[command(about = "以 JSON-RPC 服务模式运行")]
+    Serve {
+        #[command(flatten)]
+        config: ConfigArgs,
 
-        /// 禁用递归扫描（只扫描根目录）
+        /// 通过标准输入/输出通信
         #[arg(long)]
-        no_recursive: bool,
+        stdio: bool,
+    },
 
-        /// 最大扫描深度
-        #[arg(long, default_value = "3")]
-        max_depth: usize,
+    /// 提供本地 HTTP API（查询数据库、触发扫描）
+    #[command(about = "提供本地 HTTP API")]
+    Http {
+        #[command(flatten)]
+        config: ConfigArgs,
 
-        /// 显示扫描进度（输出到stderr，不影响JSON输出）
-        #[arg(short = 'p', long)]
-        progress: bool,
+        /// 监听地址（建议只监听本机地址）
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+    },
 
-        /// 进度报告间隔（毫秒）
-        #[arg(long, default_value = "200")]
-        progress_interval: u64,
+    /// 输出进度消息和扫描输出的 JSON Schema（协议版本见 start 消息的 v 字段）
+    #[command(about = "输出进度与输出协议的 JSON Schema")]
+    Schema {
+        /// 只输出指定的定义（如 start、progress、compact_summary、file）
+        name: Option<String>,
+    },
 
-        /// 清空已删除文件记录表
+    /// 将数据库中的表导出为 Parquet 或 Arrow IPC 文件（每个表一个文件）
+    #[command(about = "导出数据库表为 Parquet / Arrow 文件")]
+    Export {
+        #[command(flatten)]
+        config: ConfigArgs,
+
+        /// 导出格式: parquet 或 arrow（Arrow IPC 文件，即 Feather v2）
+        #[arg(long, default_value = "parquet")]
+        format: ColumnarFormat,
+
+        /// 输出目录，文件名为 <表名>.parquet 或 <表名>.arrow（默认当前目录）；
+        /// 使用 --manifest 时为清单文件路径（默认 <根目录>/MD5SUMS 或 SHA256SUMS）
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// 要导出的表，逗号分隔（默认全部: files, deleted_files, directories, scans）
+        #[arg(long, value_delimiter = ',')]
+        tables: Vec<String>,
+
+        /// 每个 row group / record batch 的行数
+        #[arg(long, default_value_t = DEFAULT_BATCH_ROWS)]
+        batch_rows: usize,
+
+        /// 改为写出 md5sum/sha256sum 兼容的校验清单: md5 或 sha256，覆盖 --roots 下的文件
+        #[arg(long, value_name = "ALGORITHM")]
+        manifest: Option<ManifestAlgorithm>,
+
+        /// 每个目录各写一个清单，只列出该目录下的文件
+        #[arg(long, requires = "manifest", conflicts_with = "output")]
+        per_directory: bool,
+    },
+
+    /// 比较两个数据库（两台机器或两个时间点的索引），输出新增、删除、修改和移动的文件
+    #[command(about = "比较两个数据库的文件索引")]
+    Diff {
+        /// 旧的数据库
+        db_a: PathBuf,
+
+        /// 新的数据库
+        db_b: PathBuf,
+
+        /// 输出格式: ndjson（默认）、compact、json、csv、tsv、text
+        #[arg(long, default_value = "ndjson")]
+        format: OutputFormat,
+
+        /// 文件排序：path（默认）、size、mtime、none
+        #[arg(long, default_value = "path")]
+        sort: OutputOrder,
+
+        /// 输出结果到文件
+        #[arg(short = 'o', long)]
+        output: Option<PathBuf>,
+    },
+
+    /// 重新计算大小和修改时间未变化的文件的哈希，与数据库中的哈希比较，不一致的标记为 corrupted
+    #[command(about = "校验文件完整性（检测静默损坏）")]
+    Verify {
+        #[command(flatten)]
+        config: ConfigArgs,
+
+        /// 校验顺序：oldest（从未校验或最久未校验的优先，默认）、random（随机抽样）
+        #[arg(long, default_value = "oldest")]
+        order: VerifyOrder,
+
+        /// 最多校验的文件数
         #[arg(long)]
-        clear_deleted: bool,
+        limit: Option<u64>,
+
+        /// 读取速度上限（每秒字节数，如 50M）
+        #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+        rate: Option<u64>,
+
+        /// 以 JSON 格式输出结果
+        #[arg(long)]
+        json: bool,
+
+        /// 按 md5sum/sha256sum 清单校验文件（路径相对于清单所在目录），不使用数据库
+        #[arg(long, value_name = "FILE", conflicts_with = "limit")]
+        manifest: Option<PathBuf>,
+    },
+
+    /// 按删除时间、路径前缀或扫描次数清理删除文件记录；不指定条件时按配置的保留策略清理
+    #[command(about = "清理删除文件记录")]
+    PurgeDeleted {
+        #[command(flatten)]
+        config: ConfigArgs,
+
+        /// 只清理删除时间早于该时间的记录（如 2024-01-31、Unix 时间戳，或相对时间 30d）
+        #[arg(long, value_name = "TIME")]
+        before: Option<TimeBound>,
+
+        /// 只清理路径以该前缀开头的记录
+        #[arg(long, value_name = "PATH")]
+        prefix: Option<String>,
+
+        /// 保留最近 N 次扫描删除的记录，只清理更早的
+        #[arg(long, value_name = "N")]
+        keep_runs: Option<u64>,
+    },
+
+    /// 配置文件相关操作
+    #[command(about = "配置文件相关操作")]
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommands,
     },
+
+    /// 数据库维护：查看信息、压缩、检查和删除整个根目录的记录
+    #[command(about = "数据库维护")]
+    Db {
+        #[command(subcommand)]
+        action: DbCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum DbCommands {
+    /// 显示结构版本、大小、各表行数和最近一次扫描
+    #[command(about = "显示数据库信息")]
+    Info {
+        #[command(flatten)]
+        config: DbArgs,
+
+        /// 以 JSON 格式输出
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// 重建数据库文件，回收删除记录后留下的空闲空间
+    #[command(about = "压缩数据库")]
+    Vacuum {
+        #[command(flatten)]
+        config: DbArgs,
+    },
+
+    /// SQLite 完整性检查，并检查记录是否都在已知根目录下（根目录取 --roots，否则取扫描历史）
+    #[command(about = "检查数据库")]
+    Check {
+        #[command(flatten)]
+        config: DbArgs,
+
+        /// 已知的根目录（可指定多个；默认取配置文件，否则取扫描历史）
+        #[arg(short = 'r', long)]
+        roots: Vec<PathBuf>,
+
+        /// 以 JSON 格式输出
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// 删除一个根目录（媒体库）下的全部文件、删除记录和目录记录
+    #[command(about = "删除一个根目录的全部记录")]
+    Prune {
+        #[command(flatten)]
+        config: DbArgs,
+
+        /// 要删除的根目录
+        #[arg(long, value_name = "PATH")]
+        root: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// 输出合并后的有效配置（配置文件 + 命令行参数）
+    #[command(about = "输出合并后的有效配置")]
+    Dump {
+        #[command(flatten)]
+        config: ConfigArgs,
+
+        /// 以 TOML 格式输出（默认 JSON）
+        #[arg(long)]
+        toml: bool,
+    },
+}
+
+/// 数据库参数，用于只访问数据库的子命令（配置文件中只使用 db_path 等数据库设置）
+#[derive(Args)]
+struct DbArgs {
+    /// 配置文件路径（TOML 或 JSON）
+    #[arg(short = 'c', long)]
+    config: Option<PathBuf>,
+
+    /// 数据库文件路径（默认 media_scanner.db）
+    #[arg(short = 'd', long)]
+    db: Option<PathBuf>,
+}
+
+impl DbArgs {
+    /// Build the effective config from the file, with the database path from the CLI
+    fn resolve(&self) -> Result<ScanConfig, ScanError> {
+        let mut config = match self.config {
+            Some(ref path) => ScanConfig::from_file(path)?,
+            None => ScanConfig::default(),
+        };
+        if let Some(ref db) = self.db {
+            config.db_path = Some(db.clone());
+        }
+        if config.db_path.is_none() {
+            config.db_path = Some(PathBuf::from(DEFAULT_DB_PATH));
+        }
+        Ok(config)
+    }
+}
+
+/// 扫描配置参数（命令行参数覆盖配置文件中的值）
+#[derive(Args)]
+struct ConfigArgs {
+    #[command(flatten)]
+    base: DbArgs,
+
+    /// 扫描的根目录（可指定多个）
+    #[arg(short = 'r', long)]
+    roots: Vec<PathBuf>,
+
+    /// 并行线程数（0 = 自动检测，默认 0）
+    #[arg(short = 't', long)]
+    threads: Option<usize>,
+
+    /// 数据库批量写入大小（默认 1000）
+    #[arg(short = 'b', long)]
+    batch_size: Option<usize>,
+
+    /// 计算文件哈希（默认不计算）
+    #[arg(long, overrides_with = "no_hash")]
+    hash: bool,
+
+    /// 不计算文件哈希（覆盖配置文件中的 compute_hash）
+    #[arg(long, overrides_with = "hash")]
+    no_hash: bool,
+
+    /// 递归扫描子目录（默认；覆盖配置文件中的 recursive = false）
+    #[arg(long, overrides_with = "no_recursive")]
+    recursive: bool,
+
+    /// 禁用递归扫描（只扫描根目录）
+    #[arg(long, overrides_with = "recursive")]
+    no_recursive: bool,
+
+    /// 最大扫描深度（默认 3）
+    #[arg(long)]
+    max_depth: Option<usize>,
+
+    /// 显示扫描进度（输出到stderr，不影响JSON输出）
+    #[arg(short = 'p', long, overrides_with = "no_progress")]
+    progress: bool,
+
+    /// 不显示扫描进度（覆盖配置文件中的 show_progress）
+    #[arg(long, overrides_with = "progress")]
+    no_progress: bool,
+
+    /// 进度报告间隔（毫秒，默认 200）
+    #[arg(long)]
+    progress_interval: Option<u64>,
+
+    /// 把进度消息写入已打开的文件描述符（而不是 stderr，避免与日志混在一起；隐含 -p）
+    #[arg(long, value_name = "FD", conflicts_with = "progress_file")]
+    progress_fd: Option<i32>,
+
+    /// 把进度消息写入文件或命名管道（而不是 stderr；隐含 -p）
+    #[arg(long, value_name = "PATH")]
+    progress_file: Option<PathBuf>,
+
+    /// stderr 上的进度格式：auto（终端显示状态行，否则 JSON）、json、tty
+    #[arg(long, value_name = "FORMAT")]
+    progress_format: Option<ProgressFormat>,
+
+    /// 结果中文件的排序：path（按目录和文件名，默认）、size、mtime、none（遍历顺序，取决于文件系统）
+    #[arg(long = "sort", value_name = "ORDER")]
+    output_order: Option<OutputOrder>,
+
+    /// 只扫描匹配的文件（gitignore 风格，相对根目录；`re:` 前缀表示正则，可指定多个）
+    #[arg(long = "include", value_name = "PATTERN")]
+    include: Vec<String>,
+
+    /// 排除匹配的文件或目录（gitignore 风格，相对根目录；`re:` 前缀表示正则，可指定多个）
+    #[arg(long = "exclude", value_name = "PATTERN")]
+    exclude: Vec<String>,
+
+    /// 读取目录中的 .mediaignore 文件（默认；覆盖配置文件中的 use_ignore_files = false）
+    #[arg(long, overrides_with = "no_ignore_files")]
+    ignore_files: bool,
+
+    /// 不读取目录中的 .mediaignore 文件
+    #[arg(long, overrides_with = "ignore_files")]
+    no_ignore_files: bool,
+
+    /// 跳过小于该大小的文件（如 500、10K、5MB）
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    min_size: Option<u64>,
+
+    /// 跳过大于该大小的文件（如 4G）
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_size: Option<u64>,
+
+    /// 跳过修改时间早于该时间的文件（如 2024-01-31、RFC 3339 时间、Unix 时间戳，或相对时间 7d）
+    #[arg(long, value_name = "TIME")]
+    min_mtime: Option<TimeBound>,
+
+    /// 跳过修改时间晚于该时间的文件（如 10m 表示跳过最近 10 分钟内修改、可能仍在写入的文件）
+    #[arg(long, value_name = "TIME")]
+    max_mtime: Option<TimeBound>,
+
+    /// 跳过文件名匹配的文件（glob，如 '._*'，可指定多个）
+    #[arg(long = "exclude-name", value_name = "PATTERN")]
+    exclude_name: Vec<String>,
+
+    /// 跟随符号链接（按设备号/inode 检测循环，同一文件只索引一次）
+    #[arg(long, overrides_with = "no_follow_symlinks")]
+    follow_symlinks: bool,
+
+    /// 不跟随符号链接（覆盖配置文件中的 follow_symlinks）
+    #[arg(long, overrides_with = "follow_symlinks")]
+    no_follow_symlinks: bool,
+
+    /// 将符号链接本身记录为条目（包含目标路径及是否失效）
+    #[arg(long, overrides_with = "no_record_symlinks")]
+    record_symlinks: bool,
+
+    /// 不记录符号链接条目（覆盖配置文件中的 record_symlinks）
+    #[arg(long, overrides_with = "record_symlinks")]
+    no_record_symlinks: bool,
+
+    /// 不跨越文件系统边界（不进入挂载点）
+    #[arg(long, overrides_with = "no_one_file_system")]
+    one_file_system: bool,
+
+    /// 允许跨越文件系统边界（覆盖配置文件中的 one_file_system）
+    #[arg(long, overrides_with = "one_file_system")]
+    no_one_file_system: bool,
+
+    /// 隐藏项（以 . 开头）处理策略：dirs（跳过隐藏目录，默认）、files、all、none；不影响根目录本身
+    #[arg(long, value_name = "POLICY")]
+    hidden: Option<HiddenPolicy>,
+
+    /// 增量扫描的变更判定：fast（大小 + 秒级 mtime，默认）、strict（另比较纳秒 mtime、ctime 与 inode）
+    #[arg(long, value_name = "MODE")]
+    change_detection: Option<ChangeDetection>,
+
+    /// 增量扫描时跳过 mtime 未变化的目录，直接沿用数据库中的文件记录
+    #[arg(long, overrides_with = "no_dir_shortcut")]
+    dir_shortcut: bool,
+
+    /// 不使用目录捷径（覆盖配置文件中的 dir_shortcut）
+    #[arg(long, overrides_with = "dir_shortcut")]
+    no_dir_shortcut: bool,
+
+    /// 目录捷径的完整校验周期（如 12h、7d；0 表示每次都完整列出）
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    dir_verify_interval: Option<u64>,
+
+    /// 每次扫描后只保留最近 N 天的删除文件记录
+    #[arg(long, value_name = "DAYS")]
+    keep_deleted_days: Option<u64>,
+
+    /// 每次扫描后只保留最近 N 次扫描的删除文件记录（与 --keep-deleted-days 同时使用时满足任一条件即保留）
+    #[arg(long, value_name = "N")]
+    keep_deleted_runs: Option<u64>,
+}
+
+impl ConfigArgs {
+    /// Build the effective config: file values first, then CLI overrides
+    fn resolve(&self) -> Result<ScanConfig, ScanError> {
+        let mut config = self.base.resolve()?;
+
+        if !self.roots.is_empty() {
+            config.roots = self.roots.clone();
+        }
+        if let Some(threads) = self.threads {
+            config.num_threads = threads;
+        }
+        if let Some(batch_size) = self.batch_size {
+            config.batch_size = batch_size;
+        }
+        if let Some(hash) = flag(self.hash, self.no_hash) {
+            config.compute_hash = hash;
+        }
+        if let Some(recursive) = flag(self.recursive, self.no_recursive) {
+            config.recursive = recursive;
+        }
+        if let Some(max_depth) = self.max_depth {
+            config.max_depth = max_depth;
+        }
+        if let Some(progress) = flag(self.progress, self.no_progress) {
+            config.show_progress = progress;
+        }
+        if let Some(interval) = self.progress_interval {
+            config.progress_interval_ms = interval;
+        }
+        // A dedicated progress channel replaces the one from the config file
+        if let Some(fd) = self.progress_fd {
+            config.progress_fd = Some(fd);
+            config.progress_file = None;
+            config.show_progress = true;
+        }
+        if let Some(ref path) = self.progress_file {
+            config.progress_fd = None;
+            config.progress_file = Some(path.clone());
+            config.show_progress = true;
+        }
+        if let Some(format) = self.progress_format {
+            config.progress_format = format;
+        }
+        if let Some(order) = self.output_order {
+            config.output_order = order;
+        }
+        // Patterns from the command line are added to those from the file
+        config.rules.include.extend(self.include.iter().cloned());
+        config.rules.exclude.extend(self.exclude.iter().cloned());
+        if let Some(use_ignore_files) = flag(self.ignore_files, self.no_ignore_files) {
+            config.use_ignore_files = use_ignore_files;
+        }
+        if let Some(hidden) = self.hidden {
+            config.hidden = hidden;
+        }
+        if let Some(follow) = flag(self.follow_symlinks, self.no_follow_symlinks) {
+            config.follow_symlinks = follow;
+        }
+        if let Some(record) = flag(self.record_symlinks, self.no_record_symlinks) {
+            config.record_symlinks = record;
+        }
+        if let Some(one_fs) = flag(self.one_file_system, self.no_one_file_system) {
+            config.one_file_system = one_fs;
+        }
+        if let Some(policy) = self.change_detection {
+            config.change_detection = policy;
+        }
+        if let Some(shortcut) = flag(self.dir_shortcut, self.no_dir_shortcut) {
+            config.dir_shortcut = shortcut;
+        }
+        if let Some(interval) = self.dir_verify_interval {
+            config.dir_verify_interval_secs = interval;
+        }
+        if let Some(days) = self.keep_deleted_days {
+            config.keep_deleted_days = Some(days);
+        }
+        if let Some(runs) = self.keep_deleted_runs {
+            config.keep_deleted_runs = Some(runs);
+        }
+        if let Some(min_size) = self.min_size {
+            config.min_size = Some(min_size);
+        }
+        if let Some(max_size) = self.max_size {
+            config.max_size = Some(max_size);
+        }
+        if let Some(min_mtime) = self.min_mtime {
+            config.min_mtime = Some(min_mtime);
+        }
+        if let Some(max_mtime) = self.max_mtime {
+            config.max_mtime = Some(max_mtime);
+        }
+        config
+            .exclude_names
+            .extend(self.exclude_name.iter().cloned());
+
+        Ok(config)
+    }
+}
+
+/// Value of a `--x`/`--no-x` flag pair, or `None` to keep the config value
+///
+/// The pairs override each other, so at most one of them is set.
+fn flag(on: bool, off: bool) -> Option<bool> {
+    match (on, off) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    }
+}
+
+/// Token cancelled when SIGINT or SIGTERM arrives during a scan
+static SIGNAL_CANCEL: OnceLock<CancelToken> = OnceLock::new();
+
+/// Number of the signal that cancelled the scan (0 if none arrived)
+static CANCEL_SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+/// Exit code of a cancelled run: 128 + the signal number, as a shell reports it
+fn cancelled_exit_code() -> i32 {
+    match CANCEL_SIGNAL.load(Ordering::SeqCst) {
+        0 => 130,
+        signal => 128 + signal,
+    }
+}
+
+/// Cancel `token` on SIGINT/SIGTERM; a second signal terminates the process
+#[cfg(unix)]
+fn cancel_on_signal(token: &CancelToken) {
+    extern "C" fn handle(signal: libc::c_int) {
+        CANCEL_SIGNAL.store(signal, Ordering::SeqCst);
+        if let Some(token) = SIGNAL_CANCEL.get() {
+            token.cancel();
+        }
+    }
+
+    if SIGNAL_CANCEL.set(token.clone()).is_err() {
+        return;
+    }
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        // SAFETY: the handler only does atomic loads and stores. SA_RESETHAND
+        // restores the default action after the first signal.
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handle as *const () as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESETHAND;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(signal, &action, std::ptr::null_mut());
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn cancel_on_signal(_token: &CancelToken) {}
+
+/// Add a finished scan to the database's scan history, then apply the
+/// deleted files retention policy
+fn save_scan_record(
+    db: &mut ScanDatabase,
+    config: &ScanConfig,
+    result: &ScanResult,
+    incremental: bool,
+) {
+    let record = ScanRecord::from_result(result, &config.roots, incremental);
+    if let Err(e) = db.record_scan(&record) {
+        eprintln!("保存扫描记录失败: {}", e);
+    }
+    match db.apply_deleted_retention(config.keep_deleted_days, config.keep_deleted_runs) {
+        Ok(0) => {}
+        Ok(count) => info!("Purged {} deleted file records", count),
+        Err(e) => eprintln!("清理删除文件记录失败: {}", e),
+    }
+}
+
+/// Open the existing database named by a resolved config, exiting if it is missing
+fn open_existing_db(config: Result<ScanConfig, ScanError>) -> (ScanConfig, PathBuf, ScanDatabase) {
+    let config = match config {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("加载配置失败: {}", e.message);
+            std::process::exit(2);
+        }
+    };
+    let db_path = config
+        .db_path
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_DB_PATH));
+    if !db_path.exists() {
+        eprintln!("数据库不存在: {}", db_path.display());
+        std::process::exit(2);
+    }
+    match ScanDatabase::open(&db_path) {
+        Ok(db) => (config, db_path, db),
+        Err(e) => {
+            eprintln!("打开数据库失败: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Format a byte count in MB
+fn format_mb(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
 }
 
 fn main() {
@@ -124,23 +728,28 @@ fn main() {
 
     match cli.command {
         Some(Commands::Scan {
-            roots,
-            threads,
-            batch_size,
-            db,
+            config,
             incremental,
             json,
             ndjson,
             compact,
+            csv,
+            tsv,
+            columns,
             output,
-            hash,
-            no_recursive,
-            max_depth,
-            progress,
-            progress_interval,
             clear_deleted,
         }) => {
-            let db_path = db.unwrap_or_else(|| PathBuf::from("media_scanner.db"));
+            let config = match config.resolve() {
+                Ok(c) => c,
+                Err(e) => {
+                    eprintln!("加载配置失败: {}", e.message);
+                    std::process::exit(2);
+                }
+            };
+            let db_path = config
+                .db_path
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_DB_PATH));
 
             // Handle clear_deleted flag
             if clear_deleted {
@@ -162,41 +771,40 @@ fn main() {
                 return;
             }
 
+            if config.roots.is_empty() {
+                eprintln!("未指定扫描根目录：请使用 --roots 或在配置文件中设置 roots");
+                std::process::exit(2);
+            }
+
             info!("Starting media scan...");
-            info!("Roots: {:?}", roots);
+            info!("Roots: {:?}", config.roots);
             info!(
                 "Threads: {}",
-                if threads == 0 {
+                if config.num_threads == 0 {
                     "auto".to_string()
                 } else {
-                    threads.to_string()
+                    config.num_threads.to_string()
                 }
             );
-            info!("Batch size: {}", batch_size);
+            info!("Batch size: {}", config.batch_size);
             info!("Incremental: {}", incremental);
-            info!("Recursive: {}", !no_recursive);
-            info!("Max depth: {}", max_depth);
-            info!("Progress: {}", progress);
-
-            let config = ScanConfig::builder()
-                .roots(roots)
-                .num_threads(threads)
-                .batch_size(batch_size)
-                .compute_hash(hash)
-                .recursive(!no_recursive)
-                .max_depth(max_depth)
-                .show_progress(progress)
-                .progress_interval_ms(progress_interval)
-                .build();
+            info!("Recursive: {}", config.recursive);
+            info!("Max depth: {}", config.max_depth);
+            info!("Progress: {}", config.show_progress);
 
             info!("Config: {:?}", config);
 
+            // Ctrl-C / SIGTERM stop the scan; what was scanned is still saved
+            let cancel = CancelToken::new();
+            cancel_on_signal(&cancel);
+
             // Perform scan (incremental or full)
             let result = if incremental {
                 info!("Opening database: {:?}", db_path);
                 match ScanDatabase::open(&db_path) {
                     Ok(mut scan_db) => {
-                        let r = scan_incremental(&config, &mut scan_db);
+                        let r = scan_incremental_with_cancel(&config, &mut scan_db, &cancel);
+                        save_scan_record(&mut scan_db, &config, &r, true);
                         info!(
                             "Incremental scan: {} new, {} modified, {} deleted",
                             r.new_files, r.modified_files, r.deleted_files
@@ -206,24 +814,26 @@ fn main() {
                     Err(e) => {
                         eprintln!("无法打开数据库 {:?}: {}", db_path, e);
                         eprintln!("将执行完整扫描...");
-                        let r = scan_full(&config);
+                        let r = scan_full_with_cancel(&config, &cancel);
                         // Save to database for next incremental scan
                         if let Ok(mut scan_db) = ScanDatabase::open(&db_path) {
                             if let Err(e) = scan_db.upsert_files(&r.files) {
                                 eprintln!("保存到数据库失败: {}", e);
                             }
+                            save_scan_record(&mut scan_db, &config, &r, false);
                         }
                         r
                     }
                 }
             } else {
-                let r = scan_full(&config);
+                let r = scan_full_with_cancel(&config, &cancel);
                 // Save to database for future incremental scans
                 if let Ok(mut scan_db) = ScanDatabase::open(&db_path) {
                     info!("Saving {} files to database", r.files.len());
                     if let Err(e) = scan_db.upsert_files(&r.files) {
                         eprintln!("保存到数据库失败: {}", e);
                     }
+                    save_scan_record(&mut scan_db, &config, &r, false);
                 }
                 r
             };
@@ -241,142 +851,539 @@ fn main() {
                 Box::new(BufWriter::new(std::io::stdout()))
             };
 
-            if compact {
-                // 紧凑格式：按目录分组，字段使用缩写
-                // 第一行输出统计信息
-                let stats = serde_json::json!({
-                    "_t": "s",
-                    "tf": result.total_files,
-                    "td": result.total_dirs,
-                    "nf": result.new_files,
-                    "mf": result.modified_files,
-                    "uf": result.unchanged_files,
-                    "df": result.deleted_files,
-                    "ec": result.error_count(),
-                    "ms": result.duration_ms
-                });
-                writeln!(writer, "{}", stats).ok();
-
-                // 按目录分组 (新增+修改的文件)
-                let mut dirs: HashMap<String, Vec<CompactFile>> = HashMap::new();
-                for file in &result.files {
-                    if let Some(path) = file.full_path() {
-                        let dir = path
-                            .parent()
-                            .map(|p| p.to_string_lossy().to_string())
-                            .unwrap_or_default();
-                        dirs.entry(dir)
-                            .or_default()
-                            .push(CompactFile::from_scanned(file));
-                    }
+            let format = if csv {
+                OutputFormat::Csv
+            } else if tsv {
+                OutputFormat::Tsv
+            } else if compact {
+                OutputFormat::Compact
+            } else if ndjson {
+                OutputFormat::Ndjson
+            } else if json {
+                OutputFormat::Json
+            } else {
+                OutputFormat::Text
+            };
+            let written = ResultWriter::new(&mut writer, format)
+                .columns(&columns)
+                .write(&result);
+            if let Err(e) = written {
+                eprintln!("写入 {} 输出失败: {}", format, e);
+            }
+
+            if let Some(path) = output {
+                println!("结果已保存到: {:?}", path);
+            }
+            if result.cancelled {
+                eprintln!("扫描已取消，已保存部分结果");
+                std::process::exit(cancelled_exit_code());
+            }
+        }
+        #[cfg(target_os = "linux")]
+        Some(Commands::Watch {
+            config,
+            debounce_ms,
+        }) => {
+            let config = match config.resolve() {
+                Ok(c) => c,
+                Err(e) => {
+                    eprintln!("加载配置失败: {}", e.message);
+                    std::process::exit(2);
                 }
+            };
+            if config.roots.is_empty() {
+                eprintln!("未指定扫描根目录：请使用 --roots 或在配置文件中设置 roots");
+                std::process::exit(2);
+            }
+            let db_path = config
+                .db_path
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_DB_PATH));
 
-                // 每个目录一行
-                for (path, files) in dirs {
-                    let dir = ScannedDirectory { path, files };
-                    if let Ok(line) = serde_json::to_string(&dir) {
-                        writeln!(writer, "{}", line).ok();
-                    }
+            let mut scan_db = match ScanDatabase::open(&db_path) {
+                Ok(db) => db,
+                Err(e) => {
+                    eprintln!("无法打开数据库 {:?}: {}", db_path, e);
+                    std::process::exit(1);
                 }
+            };
+            let mut stdout = std::io::stdout();
+            let debounce = std::time::Duration::from_millis(debounce_ms);
+            if let Err(e) = watch(&config, &mut scan_db, debounce, &mut stdout) {
+                eprintln!("监听失败: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Serve { config, stdio }) => {
+            if !stdio {
+                eprintln!("目前仅支持 --stdio 模式");
+                std::process::exit(2);
+            }
+            let config = match config.resolve() {
+                Ok(c) => c,
+                Err(e) => {
+                    eprintln!("加载配置失败: {}", e.message);
+                    std::process::exit(2);
+                }
+            };
+            let db_path = config
+                .db_path
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_DB_PATH));
 
-                // 输出删除的文件（如果有）
-                if !result.deleted_paths.is_empty() {
-                    let deleted = serde_json::json!({
-                        "_t": "d",
-                        "paths": result.deleted_paths
-                    });
-                    writeln!(writer, "{}", deleted).ok();
+            let stdin = std::io::stdin().lock();
+            if let Err(e) = serve(&config, db_path, stdin, Box::new(std::io::stdout())) {
+                eprintln!("服务运行失败: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Http { config, listen }) => {
+            let config = match config.resolve() {
+                Ok(c) => c,
+                Err(e) => {
+                    eprintln!("加载配置失败: {}", e.message);
+                    std::process::exit(2);
                 }
-            } else if ndjson {
-                // NDJSON 格式：每行一个文件，适合大量文件流式处理
-                // 第一行输出统计信息
-                let stats = serde_json::json!({
-                    "_type": "summary",
-                    "total_files": result.total_files,
-                    "total_dirs": result.total_dirs,
-                    "new_files": result.new_files,
-                    "modified_files": result.modified_files,
-                    "unchanged_files": result.unchanged_files,
-                    "deleted_files": result.deleted_files,
-                    "error_count": result.error_count(),
-                    "duration_ms": result.duration_ms
-                });
-                writeln!(writer, "{}", stats).ok();
-
-                // 每个文件一行 (新增+修改)
-                for file in &result.files {
-                    if let Ok(line) = serde_json::to_string(file) {
-                        writeln!(writer, "{}", line).ok();
+            };
+            let db_path = config
+                .db_path
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_DB_PATH));
+
+            if let Err(e) = serve_http(&config, db_path, &listen) {
+                eprintln!("HTTP 服务运行失败: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Schema { name }) => {
+            let schema = protocol_schema();
+            let schema = match name {
+                Some(name) => match schema["$defs"].get(&name) {
+                    Some(def) => def.clone(),
+                    None => {
+                        let names: Vec<&String> = schema["$defs"]
+                            .as_object()
+                            .into_iter()
+                            .flat_map(|d| d.keys())
+                            .collect();
+                        eprintln!("未知的定义 {:?}，可用: {:?}", name, names);
+                        std::process::exit(2);
                     }
+                },
+                None => schema,
+            };
+            println!("{}", serde_json::to_string_pretty(&schema).unwrap());
+        }
+        Some(Commands::Export {
+            config,
+            format,
+            output,
+            tables,
+            batch_rows,
+            manifest,
+            per_directory,
+        }) => {
+            let (config, _, db) = open_existing_db(config.resolve());
+
+            if let Some(algorithm) = manifest {
+                if config.roots.is_empty() {
+                    eprintln!("请用 --roots 指定要生成清单的目录");
+                    std::process::exit(2);
                 }
+                if output.is_some() && config.roots.len() > 1 {
+                    eprintln!("使用 -o 指定清单文件时只能有一个根目录");
+                    std::process::exit(2);
+                }
+                let cancel = CancelToken::new();
+                cancel_on_signal(&cancel);
+                for root in &config.roots {
+                    let manifest =
+                        match build_manifest(&config, &db, root, algorithm, Some(&cancel)) {
+                            Ok(manifest) => manifest,
+                            Err(e) => {
+                                eprintln!("生成清单失败: {}", e.message);
+                                std::process::exit(1);
+                            }
+                        };
+                    for error in &manifest.errors {
+                        eprintln!("错误: {}", error);
+                    }
+                    if manifest.cancelled {
+                        eprintln!("生成清单已取消");
+                        std::process::exit(cancelled_exit_code());
+                    }
+                    let written = if per_directory {
+                        manifest.write_per_directory(root)
+                    } else {
+                        let path = output
+                            .clone()
+                            .unwrap_or_else(|| root.join(algorithm.file_name()));
+                        File::create(&path)
+                            .and_then(|f| manifest.write_to(BufWriter::new(f)))
+                            .map(|()| vec![path])
+                    };
+                    let written = match written {
+                        Ok(written) => written,
+                        Err(e) => {
+                            eprintln!("写入清单失败: {}", e);
+                            std::process::exit(1);
+                        }
+                    };
+                    println!(
+                        "{}: {} 个文件（重新计算哈希 {}，已不存在 {}）",
+                        root.display(),
+                        manifest.entries.len(),
+                        manifest.hashed,
+                        manifest.missing
+                    );
+                    for path in written {
+                        println!("  -> {}", path.display());
+                    }
+                }
+                return;
+            }
 
-                // 输出删除的文件
-                for path in &result.deleted_paths {
-                    let deleted = serde_json::json!({
-                        "_type": "deleted",
-                        "path": path
-                    });
-                    writeln!(writer, "{}", deleted).ok();
+            let output = output.unwrap_or_else(|| PathBuf::from("."));
+            match export_database(&db, &output, format, &tables, batch_rows) {
+                Ok(exported) => {
+                    for table in exported {
+                        println!(
+                            "{}: {} 行 -> {}",
+                            table.table,
+                            table.rows,
+                            table.path.display()
+                        );
+                    }
                 }
-            } else if json {
-                // JSON 格式：目录树结构，适合完整输出
-                // 按目录分组
-                let mut dirs: HashMap<String, Vec<CompactFile>> = HashMap::new();
-                for file in &result.files {
-                    if let Some(path) = file.full_path() {
-                        let dir = path
-                            .parent()
-                            .map(|p| p.to_string_lossy().to_string())
-                            .unwrap_or_default();
-                        dirs.entry(dir)
-                            .or_default()
-                            .push(CompactFile::from_scanned(file));
+                Err(e) => {
+                    eprintln!("导出失败: {}", e.message);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Commands::Diff {
+            db_a,
+            db_b,
+            format,
+            sort,
+            output,
+        }) => {
+            let open = |path: &PathBuf| {
+                if !path.exists() {
+                    eprintln!("数据库不存在: {}", path.display());
+                    std::process::exit(2);
+                }
+                ScanDatabase::open(path).unwrap_or_else(|e| {
+                    eprintln!("打开数据库失败 {}: {}", path.display(), e);
+                    std::process::exit(1);
+                })
+            };
+            let (old, new) = (open(&db_a), open(&db_b));
+            let mut result = match old.diff(&new) {
+                Ok(result) => result,
+                Err(e) => {
+                    eprintln!("比较数据库失败: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            result.sort(sort);
+
+            let writer: Box<dyn Write> = match output {
+                Some(ref path) => match File::create(path) {
+                    Ok(f) => Box::new(BufWriter::new(f)),
+                    Err(e) => {
+                        eprintln!("无法创建输出文件 {:?}: {}", path, e);
+                        std::process::exit(1);
                     }
+                },
+                None => Box::new(BufWriter::new(std::io::stdout())),
+            };
+            if let Err(e) = ResultWriter::new(writer, format).write(&result) {
+                eprintln!("写入 {} 输出失败: {}", format, e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Verify {
+            config,
+            order,
+            limit,
+            rate,
+            json,
+            manifest,
+        }) => {
+            if let Some(manifest) = manifest {
+                let base = manifest
+                    .parent()
+                    .filter(|p| !p.as_os_str().is_empty())
+                    .unwrap_or(Path::new("."));
+                let cancel = CancelToken::new();
+                cancel_on_signal(&cancel);
+                let result = match verify_manifest(&manifest, base, rate, Some(&cancel)) {
+                    Ok(result) => result,
+                    Err(e) => {
+                        eprintln!("校验失败: {}", e);
+                        std::process::exit(1);
+                    }
+                };
+
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&result).unwrap());
+                } else {
+                    println!("清单校验完成:");
+                    println!("  已校验: {}", result.checked);
+                    println!("  一致: {}", result.ok);
+                    println!("  不一致: {}", result.failed.len());
+                    println!("  缺失: {}", result.missing.len());
+                    println!("  读取: {} MB", result.bytes / (1024 * 1024));
+                    println!("  错误数: {}", result.error_count);
+                    println!("  耗时: {}ms", result.duration_ms);
+                    for path in &result.failed {
+                        println!("不一致: {}", path);
+                    }
+                    for path in &result.missing {
+                        println!("缺失: {}", path);
+                    }
+                }
+                for error in &result.errors {
+                    eprintln!("错误: {}", error);
+                }
+                if result.cancelled {
+                    eprintln!("校验已取消");
+                    std::process::exit(cancelled_exit_code());
                 }
+                if !result.is_ok() {
+                    std::process::exit(3);
+                }
+                return;
+            }
+
+            let (config, _, db) = open_existing_db(config.resolve());
+
+            let cancel = CancelToken::new();
+            cancel_on_signal(&cancel);
+            let options = VerifyOptions {
+                order,
+                limit,
+                max_bytes_per_sec: rate,
+            };
+            let result = match verify_files(&config, &db, &options, Some(&cancel)) {
+                Ok(result) => result,
+                Err(e) => {
+                    eprintln!("校验失败: {}", e.message);
+                    std::process::exit(1);
+                }
+            };
 
-                let directories: Vec<ScannedDirectory> = dirs
-                    .into_iter()
-                    .map(|(path, files)| ScannedDirectory { path, files })
-                    .collect();
-
-                let output_json = serde_json::json!({
-                    "summary": {
-                        "total_files": result.total_files,
-                        "total_dirs": result.total_dirs,
-                        "new_files": result.new_files,
-                        "modified_files": result.modified_files,
-                        "unchanged_files": result.unchanged_files,
-                        "deleted_files": result.deleted_files,
-                        "error_count": result.error_count(),
-                        "duration_ms": result.duration_ms
-                    },
-                    "directories": directories,
-                    "deleted": result.deleted_paths
-                });
-
-                writeln!(
-                    writer,
-                    "{}",
-                    serde_json::to_string_pretty(&output_json).unwrap()
-                )
-                .ok();
+            if json {
+                println!("{}", serde_json::to_string_pretty(&result).unwrap());
             } else {
-                // 人类可读格式
-                writeln!(writer, "扫描完成:").ok();
-                writeln!(writer, "  媒体文件数: {}", result.total_files).ok();
-                writeln!(writer, "  目录数: {}", result.total_dirs).ok();
-                writeln!(writer, "  新文件: {}", result.new_files).ok();
-                writeln!(writer, "  修改文件: {}", result.modified_files).ok();
-                writeln!(writer, "  未更改: {}", result.unchanged_files).ok();
-                writeln!(writer, "  删除文件: {}", result.deleted_files).ok();
-                writeln!(writer, "  错误数: {}", result.error_count()).ok();
-                writeln!(writer, "  耗时: {}ms", result.duration_ms).ok();
+                println!("校验完成:");
+                println!("  已校验: {}", result.checked);
+                println!("  一致: {}", result.ok);
+                println!("  损坏: {}", result.corrupted.len());
+                println!("  已变化（跳过）: {}", result.changed);
+                println!("  已不存在: {}", result.missing);
+                println!("  读取: {} MB", result.bytes / (1024 * 1024));
+                println!("  错误数: {}", result.error_count);
+                println!("  耗时: {}ms", result.duration_ms);
+                for path in &result.corrupted {
+                    println!("损坏: {}", path);
+                }
             }
+            for error in &result.errors {
+                eprintln!("错误: {}", error);
+            }
+            if result.cancelled {
+                eprintln!("校验已取消");
+                std::process::exit(cancelled_exit_code());
+            }
+            if !result.corrupted.is_empty() {
+                std::process::exit(3);
+            }
+        }
+        Some(Commands::PurgeDeleted {
+            config,
+            before,
+            prefix,
+            keep_runs,
+        }) => {
+            let (config, _, mut db) = open_existing_db(config.resolve());
 
-            writer.flush().ok();
-
-            if let Some(path) = output {
-                println!("结果已保存到: {:?}", path);
+            let purged = if before.is_none() && prefix.is_none() && keep_runs.is_none() {
+                if config.keep_deleted_days.is_none() && config.keep_deleted_runs.is_none() {
+                    eprintln!(
+                        "请指定 --before、--prefix 或 --keep-runs，或配置保留策略（--keep-deleted-days / --keep-deleted-runs）；清空全部记录请用 scan --clear-deleted"
+                    );
+                    std::process::exit(2);
+                }
+                db.apply_deleted_retention(config.keep_deleted_days, config.keep_deleted_runs)
+            } else {
+                let now = chrono::Utc::now().timestamp();
+                db.purge_deleted_files(&DeletedFilter {
+                    deleted_before: before.map(|b| b.resolve(now)),
+                    path_prefix: prefix,
+                    keep_runs,
+                })
+            };
+            match purged {
+                Ok(count) => println!("已清理 {} 条删除文件记录", count),
+                Err(e) => {
+                    eprintln!("清理删除文件记录失败: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Commands::Config {
+            action: ConfigCommands::Dump { config, toml },
+        }) => {
+            let config = match config.resolve() {
+                Ok(c) => c,
+                Err(e) => {
+                    eprintln!("加载配置失败: {}", e.message);
+                    std::process::exit(2);
+                }
+            };
+            let format = if toml {
+                ConfigFormat::Toml
+            } else {
+                ConfigFormat::Json
+            };
+            match config.to_string_with_format(format) {
+                Ok(text) => println!("{}", text.trim_end()),
+                Err(e) => {
+                    eprintln!("序列化配置失败: {}", e.message);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Commands::Db {
+            action: DbCommands::Info { config, json },
+        }) => {
+            let (_, db_path, db) = open_existing_db(config.resolve());
+            let info = match db.info() {
+                Ok(info) => info,
+                Err(e) => {
+                    eprintln!("读取数据库信息失败: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            if json {
+                println!("{}", serde_json::to_string_pretty(&info).unwrap());
+                return;
+            }
+            println!("数据库: {}", db_path.display());
+            println!("  结构版本: {}", info.schema_version);
+            println!(
+                "  大小: {}（空闲 {}）",
+                format_mb(info.size_bytes),
+                format_mb(info.free_bytes)
+            );
+            for (table, rows) in &info.tables {
+                println!("  {}: {} 行", table, rows);
+            }
+            match info.last_scan {
+                Some(scan) => {
+                    let finished = chrono::DateTime::from_timestamp(scan.finished_at, 0)
+                        .map(|t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+                        .unwrap_or_else(|| scan.finished_at.to_string());
+                    println!(
+                        "  最近扫描: #{} {}（{}，{} 个文件，耗时 {}ms）",
+                        scan.id,
+                        finished,
+                        if scan.incremental { "增量" } else { "完整" },
+                        scan.total_files,
+                        scan.duration_ms
+                    );
+                }
+                None => println!("  最近扫描: 无"),
+            }
+        }
+        Some(Commands::Db {
+            action: DbCommands::Vacuum { config },
+        }) => {
+            let (_, _, db) = open_existing_db(config.resolve());
+            let size = |db: &ScanDatabase| db.info().map(|info| info.size_bytes).unwrap_or(0);
+            let before = size(&db);
+            if let Err(e) = db.vacuum() {
+                eprintln!("压缩数据库失败: {}", e);
+                std::process::exit(1);
+            }
+            println!(
+                "压缩完成: {} -> {}",
+                format_mb(before),
+                format_mb(size(&db))
+            );
+        }
+        Some(Commands::Db {
+            action:
+                DbCommands::Check {
+                    config,
+                    roots,
+                    json,
+                },
+        }) => {
+            let (config, _, db) = open_existing_db(config.resolve());
+            let roots = if roots.is_empty() {
+                config.roots
+            } else {
+                roots
+            };
+            let roots: Vec<String> = roots
+                .iter()
+                .map(|r| r.to_string_lossy().replace('\\', "/"))
+                .collect();
+            let check = match db.check(&roots) {
+                Ok(check) => check,
+                Err(e) => {
+                    eprintln!("检查数据库失败: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            if json {
+                println!("{}", serde_json::to_string_pretty(&check).unwrap());
+            } else {
+                if check.integrity_errors.is_empty() {
+                    println!("完整性检查: 正常");
+                } else {
+                    println!("完整性检查: {} 个问题", check.integrity_errors.len());
+                    for message in &check.integrity_errors {
+                        println!("  {}", message);
+                    }
+                }
+                if check.roots.is_empty() {
+                    println!("根目录检查: 跳过（未指定 --roots，也没有扫描历史）");
+                } else {
+                    println!("根目录: {}", check.roots.join(", "));
+                    println!("  根目录之外的文件: {}", check.files_outside_roots);
+                    println!("  根目录之外的删除记录: {}", check.deleted_outside_roots);
+                    println!(
+                        "  根目录之外的目录记录: {}",
+                        check.directories_outside_roots
+                    );
+                    for path in &check.outside_samples {
+                        println!("    {}", path);
+                    }
+                }
+                println!("未知状态的文件: {}", check.invalid_status);
+            }
+            if !check.is_ok() {
+                std::process::exit(3);
+            }
+        }
+        Some(Commands::Db {
+            action: DbCommands::Prune { config, root },
+        }) => {
+            let (_, _, mut db) = open_existing_db(config.resolve());
+            let root = root.to_string_lossy().replace('\\', "/");
+            match db.prune_root(&root) {
+                Ok(counts) => println!(
+                    "已删除 {} 下的记录: 文件 {}，删除记录 {}，目录 {}",
+                    root, counts.files, counts.deleted_files, counts.directories
+                ),
+                Err(e) => {
+                    eprintln!("删除记录失败: {}", e);
+                    std::process::exit(1);
+                }
             }
         }
         None => {
@@ -0,0 +1,539 @@
+//! Checksum manifests compatible with `md5sum` and `sha256sum`
+//!
+//! A manifest lists `<hex digest>  <path>` lines with paths relative to the
+//! directory it describes, so `md5sum -c MD5SUMS` run from that directory
+//! checks it. Names containing a backslash or a line break are escaped the
+//! way GNU coreutils does: the line starts with `\` and the name has `\\`,
+//! `\n` and `\r` escapes.
+//!
+//! MD5 manifests reuse the full hashes stored in the index when the file is
+//! unchanged; other files, and all SHA-256 digests, are hashed from disk.
+
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::config::ScanConfig;
+use crate::db::ScanDatabase;
+use crate::error::ScanError;
+use crate::scanner::{is_unchanged, read_file_chunks, CancelToken};
+use crate::verify::Throttle;
+
+/// Digest algorithm of a manifest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ManifestAlgorithm {
+    Md5,
+    Sha256,
+}
+
+impl ManifestAlgorithm {
+    /// Get string representation
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ManifestAlgorithm::Md5 => "md5",
+            ManifestAlgorithm::Sha256 => "sha256",
+        }
+    }
+
+    /// Conventional manifest file name (`MD5SUMS` or `SHA256SUMS`)
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            ManifestAlgorithm::Md5 => "MD5SUMS",
+            ManifestAlgorithm::Sha256 => "SHA256SUMS",
+        }
+    }
+
+    /// Length of a digest in hex characters
+    pub fn hex_len(&self) -> usize {
+        match self {
+            ManifestAlgorithm::Md5 => 32,
+            ManifestAlgorithm::Sha256 => 64,
+        }
+    }
+
+    fn from_hex_len(len: usize) -> Option<Self> {
+        match len {
+            32 => Some(ManifestAlgorithm::Md5),
+            64 => Some(ManifestAlgorithm::Sha256),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ManifestAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl std::str::FromStr for ManifestAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "md5" => Ok(ManifestAlgorithm::Md5),
+            "sha256" => Ok(ManifestAlgorithm::Sha256),
+            _ => Err(format!(
+                "invalid manifest algorithm {:?} (expected md5 or sha256)",
+                s
+            )),
+        }
+    }
+}
+
+/// One line of a manifest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Lowercase hex digest
+    pub hash: String,
+    /// Path relative to the manifest's directory, `/`-separated
+    pub path: String,
+}
+
+impl ManifestEntry {
+    /// Format as a manifest line, without the line break
+    pub fn to_line(&self) -> String {
+        if self.path.contains(['\\', '\n', '\r']) {
+            let escaped = self
+                .path
+                .replace('\\', "\\\\")
+                .replace('\n', "\\n")
+                .replace('\r', "\\r");
+            format!("\\{}  {}", self.hash, escaped)
+        } else {
+            format!("{}  {}", self.hash, self.path)
+        }
+    }
+
+    /// Parse a manifest line in text (`hash  path`) or binary (`hash *path`) mode
+    pub fn parse_line(line: &str) -> Option<Self> {
+        let (escaped, line) = match line.strip_prefix('\\') {
+            Some(rest) => (true, rest),
+            None => (false, line.strip_suffix('\r').unwrap_or(line)),
+        };
+        let (hash, rest) = line.split_once(' ')?;
+        if ManifestAlgorithm::from_hex_len(hash.len()).is_none()
+            || !hash.bytes().all(|b| b.is_ascii_hexdigit())
+        {
+            return None;
+        }
+        let name = rest.strip_prefix(' ').or_else(|| rest.strip_prefix('*'))?;
+        if name.is_empty() {
+            return None;
+        }
+        let path = if escaped {
+            unescape(name)?
+        } else {
+            name.to_string()
+        };
+        Some(Self {
+            hash: hash.to_ascii_lowercase(),
+            path,
+        })
+    }
+}
+
+fn unescape(name: &str) -> Option<String> {
+    let mut out = String::with_capacity(name.len());
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next()? {
+            '\\' => out.push('\\'),
+            'n' => out.push('\n'),
+            'r' => out.push('\r'),
+            _ => return None,
+        }
+    }
+    Some(out)
+}
+
+/// Read a manifest file
+pub fn read_manifest(path: &Path) -> Result<Vec<ManifestEntry>, ScanError> {
+    let bytes =
+        std::fs::read(path).map_err(|e| ScanError::io_error(Some(path.into()), e.to_string()))?;
+    let text = String::from_utf8_lossy(&bytes);
+    let mut entries = Vec::new();
+    for (i, line) in text.split('\n').enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = ManifestEntry::parse_line(line).ok_or_else(|| {
+            ScanError::config_error(
+                Some(path.into()),
+                format!("line {}: not a checksum line", i + 1),
+            )
+        })?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Checksums of the indexed files below a directory
+#[derive(Debug, Clone)]
+pub struct Manifest {
+    pub algorithm: ManifestAlgorithm,
+    /// Entries sorted by path, relative to the manifest's root
+    pub entries: Vec<ManifestEntry>,
+    /// Files hashed from disk because the index had no usable hash
+    pub hashed: u64,
+    /// Indexed files no longer on disk (left out)
+    pub missing: u64,
+    /// Files that could not be read (left out)
+    pub errors: Vec<ScanError>,
+    pub cancelled: bool,
+}
+
+impl Manifest {
+    /// Write all entries as one manifest
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for entry in &self.entries {
+            writeln!(writer, "{}", entry.to_line())?;
+        }
+        writer.flush()
+    }
+
+    /// Write one manifest per directory below `root`, listing its own files
+    ///
+    /// Returns the manifest paths written.
+    pub fn write_per_directory(&self, root: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut dirs: BTreeMap<&str, Vec<ManifestEntry>> = BTreeMap::new();
+        for entry in &self.entries {
+            let (dir, name) = entry.path.rsplit_once('/').unwrap_or(("", &entry.path));
+            dirs.entry(dir).or_default().push(ManifestEntry {
+                hash: entry.hash.clone(),
+                path: name.to_string(),
+            });
+        }
+
+        let mut written = Vec::new();
+        for (dir, entries) in dirs {
+            let path = root.join(dir).join(self.algorithm.file_name());
+            let mut writer = BufWriter::new(File::create(&path)?);
+            for entry in entries {
+                writeln!(writer, "{}", entry.to_line())?;
+            }
+            writer.flush()?;
+            written.push(path);
+        }
+        Ok(written)
+    }
+}
+
+/// Collect the checksums of the indexed files below `root`
+///
+/// `config` supplies the change detection policy deciding whether a stored
+/// MD5 hash is still current.
+pub fn build_manifest(
+    config: &ScanConfig,
+    db: &ScanDatabase,
+    root: &Path,
+    algorithm: ManifestAlgorithm,
+    cancel: Option<&CancelToken>,
+) -> Result<Manifest, ScanError> {
+    let mut manifest = Manifest {
+        algorithm,
+        entries: Vec::new(),
+        hashed: 0,
+        missing: 0,
+        errors: Vec::new(),
+        cancelled: false,
+    };
+
+    for record in db.files_under(&root.to_string_lossy())? {
+        if cancel.is_some_and(CancelToken::is_cancelled) {
+            manifest.cancelled = true;
+            break;
+        }
+        let path = PathBuf::from(&record.path);
+        let Ok(relative) = path.strip_prefix(root) else {
+            continue;
+        };
+        let relative = relative
+            .to_string_lossy()
+            .replace(std::path::MAIN_SEPARATOR, "/");
+        let metadata = match std::fs::metadata(&path) {
+            Ok(m) => m,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                manifest.missing += 1;
+                continue;
+            }
+            Err(e) => {
+                manifest
+                    .errors
+                    .push(ScanError::io_error(Some(path), e.to_string()));
+                continue;
+            }
+        };
+
+        let stored = match (&record.hash, algorithm) {
+            (Some(hash), ManifestAlgorithm::Md5)
+                if !record.is_partial_hash
+                    && is_unchanged(&record, &metadata, config.change_detection) =>
+            {
+                Some(hash.clone())
+            }
+            _ => None,
+        };
+        let hash = match stored {
+            Some(hash) => hash,
+            None => match hash_file(&path, algorithm, cancel, |_| {}) {
+                Some(hash) => {
+                    manifest.hashed += 1;
+                    hash
+                }
+                None if cancel.is_some_and(CancelToken::is_cancelled) => {
+                    manifest.cancelled = true;
+                    break;
+                }
+                None => {
+                    manifest
+                        .errors
+                        .push(ScanError::hash_error(path, "failed to read file"));
+                    continue;
+                }
+            },
+        };
+        manifest.entries.push(ManifestEntry {
+            hash,
+            path: relative,
+        });
+    }
+    Ok(manifest)
+}
+
+/// Outcome of checking a tree against a manifest
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ManifestCheck {
+    /// Algorithm detected from the digest length
+    pub algorithm: Option<ManifestAlgorithm>,
+    /// Files hashed
+    pub checked: u64,
+    /// Files whose digest matched
+    pub ok: u64,
+    /// Paths whose digest does not match
+    pub failed: Vec<String>,
+    /// Paths listed in the manifest but not on disk
+    pub missing: Vec<String>,
+    /// Bytes read
+    pub bytes: u64,
+    /// Files that could not be read
+    #[serde(skip)]
+    pub errors: Vec<ScanError>,
+    pub error_count: usize,
+    pub duration_ms: u64,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cancelled: bool,
+}
+
+impl ManifestCheck {
+    /// Whether every listed file exists and matches
+    pub fn is_ok(&self) -> bool {
+        self.failed.is_empty() && self.missing.is_empty() && self.errors.is_empty()
+    }
+}
+
+/// Check the files below `base` against the manifest at `manifest_path`
+///
+/// The algorithm is detected from the digest length; a manifest mixing
+/// lengths is rejected. `max_bytes_per_sec` throttles reading.
+pub fn verify_manifest(
+    manifest_path: &Path,
+    base: &Path,
+    max_bytes_per_sec: Option<u64>,
+    cancel: Option<&CancelToken>,
+) -> Result<ManifestCheck, ScanError> {
+    let start = Instant::now();
+    let entries = read_manifest(manifest_path)?;
+    let mut result = ManifestCheck::default();
+    let Some(first) = entries.first() else {
+        return Ok(result);
+    };
+    let algorithm = ManifestAlgorithm::from_hex_len(first.hash.len()).expect("parsed digest");
+    if entries.iter().any(|e| e.hash.len() != algorithm.hex_len()) {
+        return Err(ScanError::config_error(
+            Some(manifest_path.into()),
+            "manifest mixes digest algorithms",
+        ));
+    }
+    result.algorithm = Some(algorithm);
+
+    let mut throttle = max_bytes_per_sec.map(Throttle::new);
+    for entry in entries {
+        if cancel.is_some_and(CancelToken::is_cancelled) {
+            result.cancelled = true;
+            break;
+        }
+        let path = base.join(&entry.path);
+        if !path.is_file() {
+            if path.exists() {
+                result
+                    .errors
+                    .push(ScanError::io_error(Some(path), "not a regular file"));
+            } else {
+                result.missing.push(entry.path);
+            }
+            continue;
+        }
+
+        let mut bytes = 0;
+        let hash = hash_file(&path, algorithm, cancel, |n| {
+            bytes += n as u64;
+            if let Some(ref mut throttle) = throttle {
+                throttle.consume(n as u64);
+            }
+        });
+        result.bytes += bytes;
+        let Some(hash) = hash else {
+            if cancel.is_some_and(CancelToken::is_cancelled) {
+                result.cancelled = true;
+                break;
+            }
+            result
+                .errors
+                .push(ScanError::hash_error(path, "failed to read file"));
+            continue;
+        };
+
+        result.checked += 1;
+        if hash == entry.hash {
+            result.ok += 1;
+        } else {
+            log::warn!("Checksum mismatch: {}", entry.path);
+            result.failed.push(entry.path);
+        }
+    }
+
+    result.error_count = result.errors.len();
+    result.duration_ms = start.elapsed().as_millis() as u64;
+    Ok(result)
+}
+
+/// Hash a whole file, calling `on_read` with the size of every chunk read
+fn hash_file(
+    path: &Path,
+    algorithm: ManifestAlgorithm,
+    cancel: Option<&CancelToken>,
+    on_read: impl FnMut(usize),
+) -> Option<String> {
+    match algorithm {
+        ManifestAlgorithm::Md5 => digest_file::<Md5>(path, cancel, on_read),
+        ManifestAlgorithm::Sha256 => digest_file::<Sha256>(path, cancel, on_read),
+    }
+}
+
+/// Hash a whole file with the digest `D`, as lowercase hex
+fn digest_file<D: Digest>(
+    path: &Path,
+    cancel: Option<&CancelToken>,
+    mut on_read: impl FnMut(usize),
+) -> Option<String> {
+    let mut hasher = D::new();
+    read_file_chunks(path, u64::MAX, cancel, |chunk| {
+        hasher.update(chunk);
+        on_read(chunk.len());
+    })?;
+    let digest = hasher.finalize();
+    Some(digest.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::scan_incremental;
+    use std::fs;
+
+    #[test]
+    fn test_line_format() {
+        let entry = ManifestEntry {
+            hash: "d41d8cd98f00b204e9800998ecf8427e".to_string(),
+            path: "a b/c.mp4".to_string(),
+        };
+        assert_eq!(entry.to_line(), format!("{}  a b/c.mp4", entry.hash));
+        assert_eq!(ManifestEntry::parse_line(&entry.to_line()), Some(entry));
+
+        let escaped = ManifestEntry {
+            hash: "d41d8cd98f00b204e9800998ecf8427e".to_string(),
+            path: "back\\slash\nnewline.mp4".to_string(),
+        };
+        let line = escaped.to_line();
+        assert_eq!(
+            line,
+            "\\d41d8cd98f00b204e9800998ecf8427e  back\\\\slash\\nnewline.mp4"
+        );
+        assert_eq!(ManifestEntry::parse_line(&line), Some(escaped));
+
+        let binary =
+            ManifestEntry::parse_line("D41D8CD98F00B204E9800998ECF8427E *x.jpg\r").unwrap();
+        assert_eq!(binary.hash, "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(binary.path, "x.jpg");
+        assert_eq!(ManifestEntry::parse_line("abc  x.jpg"), None);
+    }
+
+    #[test]
+    fn test_manifest_algorithm_from_str() {
+        assert_eq!("SHA256".parse(), Ok(ManifestAlgorithm::Sha256));
+        assert!("sha1".parse::<ManifestAlgorithm>().is_err());
+    }
+
+    #[test]
+    fn test_build_and_verify_manifest() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        fs::create_dir(dir.join("sub")).unwrap();
+        fs::write(dir.join("a.mp4"), b"").unwrap();
+        fs::write(dir.join("sub/b.jpg"), b"abc").unwrap();
+
+        let config = ScanConfig::builder()
+            .add_root(dir.clone())
+            .compute_hash(true)
+            .build();
+        let mut db = ScanDatabase::open_memory().unwrap();
+        scan_incremental(&config, &mut db);
+
+        let md5 = build_manifest(&config, &db, &dir, ManifestAlgorithm::Md5, None).unwrap();
+        assert_eq!(md5.hashed, 0);
+        let mut text = Vec::new();
+        md5.write_to(&mut text).unwrap();
+        assert_eq!(
+            String::from_utf8(text).unwrap(),
+            "d41d8cd98f00b204e9800998ecf8427e  a.mp4\n\
+             900150983cd24fb0d6963f7d28e17f72  sub/b.jpg\n"
+        );
+
+        let sha = build_manifest(&config, &db, &dir, ManifestAlgorithm::Sha256, None).unwrap();
+        assert_eq!(sha.hashed, 2);
+        let written = sha.write_per_directory(&dir).unwrap();
+        assert_eq!(
+            written,
+            vec![dir.join("SHA256SUMS"), dir.join("sub/SHA256SUMS")]
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("sub/SHA256SUMS")).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  b.jpg\n"
+        );
+
+        let manifest_path = dir.join("MD5SUMS");
+        md5.write_to(File::create(&manifest_path).unwrap()).unwrap();
+        let check = verify_manifest(&manifest_path, &dir, None, None).unwrap();
+        assert!(check.is_ok());
+        assert_eq!(check.algorithm, Some(ManifestAlgorithm::Md5));
+        assert_eq!((check.checked, check.ok), (2, 2));
+
+        fs::write(dir.join("sub/b.jpg"), b"abd").unwrap();
+        fs::remove_file(dir.join("a.mp4")).unwrap();
+        let check = verify_manifest(&manifest_path, &dir, Some(1 << 30), None).unwrap();
+        assert!(!check.is_ok());
+        assert_eq!(check.failed, vec!["sub/b.jpg"]);
+        assert_eq!(check.missing, vec!["a.mp4"]);
+    }
+}
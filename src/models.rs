@@ -1,8 +1,11 @@
 //! Core data models for the media scanner
 
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
+use crate::config::OutputOrder;
+use crate::db::DirectoryRecord;
 use crate::error::ScanError;
 
 /// File status in incremental scan
@@ -20,6 +23,10 @@ pub enum FileStatus {
     Deleted,
     /// Moved file (same hash, different path)
     Moved,
+    /// Content no longer matches the stored hash although size and mtime did not change
+    Corrupted,
+    /// New path whose hash and size match a deleted file (`old_path` is where it was)
+    Restored,
 }
 
 impl FileStatus {
@@ -31,6 +38,8 @@ impl FileStatus {
             FileStatus::Unchanged => 'u',
             FileStatus::Deleted => 'd',
             FileStatus::Moved => 'v', // 'v' for moVed
+            FileStatus::Corrupted => 'c',
+            FileStatus::Restored => 'r',
         }
     }
 
@@ -42,6 +51,25 @@ impl FileStatus {
             FileStatus::Unchanged => "unchanged",
             FileStatus::Deleted => "deleted",
             FileStatus::Moved => "moved",
+            FileStatus::Corrupted => "corrupted",
+            FileStatus::Restored => "restored",
+        }
+    }
+}
+
+impl std::str::FromStr for FileStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "new" => Ok(FileStatus::New),
+            "modified" => Ok(FileStatus::Modified),
+            "unchanged" => Ok(FileStatus::Unchanged),
+            "deleted" => Ok(FileStatus::Deleted),
+            "moved" => Ok(FileStatus::Moved),
+            "corrupted" => Ok(FileStatus::Corrupted),
+            "restored" => Ok(FileStatus::Restored),
+            _ => Err(format!("invalid file status {:?}", s)),
         }
     }
 }
@@ -95,6 +123,63 @@ impl std::fmt::Display for MediaType {
     }
 }
 
+impl std::str::FromStr for MediaType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "video" => Ok(MediaType::Video),
+            "image" => Ok(MediaType::Image),
+            "audio" => Ok(MediaType::Audio),
+            "unknown" => Ok(MediaType::Unknown),
+            _ => Err(format!("invalid media type {:?}", s)),
+        }
+    }
+}
+
+/// Identity of a file on disk (device + inode)
+///
+/// Two paths with the same `FileId` refer to the same underlying file or
+/// directory, e.g. through a symbolic link or a hard link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FileId {
+    /// Device number
+    pub dev: u64,
+    /// Inode number
+    pub ino: u64,
+}
+
+impl FileId {
+    /// Get the identity from file metadata (None on platforms without inodes)
+    #[cfg(unix)]
+    pub fn from_metadata(metadata: &std::fs::Metadata) -> Option<Self> {
+        use std::os::unix::fs::MetadataExt;
+        Some(Self {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+        })
+    }
+
+    /// Get the identity from file metadata (None on platforms without inodes)
+    #[cfg(not(unix))]
+    pub fn from_metadata(_metadata: &std::fs::Metadata) -> Option<Self> {
+        None
+    }
+
+    /// Number of hard links to the file (1 on platforms without inodes)
+    #[cfg(unix)]
+    pub fn link_count(metadata: &std::fs::Metadata) -> u64 {
+        use std::os::unix::fs::MetadataExt;
+        metadata.nlink()
+    }
+
+    /// Number of hard links to the file (1 on platforms without inodes)
+    #[cfg(not(unix))]
+    pub fn link_count(_metadata: &std::fs::Metadata) -> u64 {
+        1
+    }
+}
+
 /// Represents a scanned file with its metadata
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ScannedFile {
@@ -125,6 +210,24 @@ pub struct ScannedFile {
     /// Old path (for moved files)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub old_path: Option<String>,
+    /// Symlink target (only when the entry itself is a symbolic link)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link_target: Option<String>,
+    /// Whether the symlink target does not exist
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub broken_link: bool,
+    /// Device number (Unix only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dev: Option<u64>,
+    /// Inode number (Unix only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inode: Option<u64>,
+    /// Modification time in nanoseconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime_ns: Option<i64>,
+    /// Inode change time (st_ctime) in nanoseconds (Unix only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub change_ns: Option<i64>,
 }
 
 fn is_default_status(status: &FileStatus) -> bool {
@@ -154,6 +257,12 @@ impl ScannedFile {
             is_partial_hash: false,
             status: FileStatus::New,
             old_path: None,
+            link_target: None,
+            broken_link: false,
+            dev: None,
+            inode: None,
+            mtime_ns: None,
+            change_ns: None,
         }
     }
 
@@ -176,6 +285,35 @@ impl ScannedFile {
         self
     }
 
+    /// Mark the file as a symbolic link to `target`
+    pub fn with_link_target(mut self, target: String, broken: bool) -> Self {
+        self.link_target = Some(target);
+        self.broken_link = broken;
+        self
+    }
+
+    /// Set the device/inode identity
+    pub fn with_file_id(mut self, id: FileId) -> Self {
+        self.dev = Some(id.dev);
+        self.inode = Some(id.ino);
+        self
+    }
+
+    /// Set the nanosecond modification and inode change times
+    pub fn with_precise_times(mut self, mtime_ns: Option<i64>, change_ns: Option<i64>) -> Self {
+        self.mtime_ns = mtime_ns;
+        self.change_ns = change_ns;
+        self
+    }
+
+    /// Get the device/inode identity, if known
+    pub fn file_id(&self) -> Option<FileId> {
+        match (self.dev, self.inode) {
+            (Some(dev), Some(ino)) => Some(FileId { dev, ino }),
+            _ => None,
+        }
+    }
+
     /// Get full path (for internal use)
     pub fn full_path(&self) -> Option<&PathBuf> {
         self.path.as_ref()
@@ -212,6 +350,9 @@ pub struct CompactFile {
     /// File hash (optional)
     #[serde(rename = "h", skip_serializing_if = "Option::is_none")]
     pub hash: Option<String>,
+    /// Previous path of a moved file
+    #[serde(rename = "o", default, skip_serializing_if = "Option::is_none")]
+    pub old_path: Option<String>,
 }
 
 fn is_new_status_char(c: &char) -> bool {
@@ -233,10 +374,34 @@ impl CompactFile {
             },
             status: file.status.as_char(),
             hash: file.hash.clone(),
+            old_path: file.old_path.clone(),
         }
     }
 }
 
+/// Counts of files skipped by the size, mtime and name filters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FilterCounts {
+    /// Files outside the configured size range
+    pub size: u64,
+    /// Files outside the configured mtime range
+    pub mtime: u64,
+    /// Files matching an excluded name pattern
+    pub name: u64,
+}
+
+impl FilterCounts {
+    /// Total number of filtered files
+    pub fn total(&self) -> u64 {
+        self.size + self.mtime + self.name
+    }
+
+    /// Check if no files were filtered
+    pub fn is_empty(&self) -> bool {
+        self.total() == 0
+    }
+}
+
 /// Result of a scan operation
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ScanResult {
@@ -252,17 +417,42 @@ pub struct ScanResult {
     pub unchanged_files: u64,
     /// Number of deleted files (for incremental scans)
     pub deleted_files: u64,
+    /// Number of moved files (same content at a new path; diffs only)
+    #[serde(default)]
+    pub moved_files: u64,
+    /// Number of new files matching a deleted one (incremental scans with hashing)
+    #[serde(default)]
+    pub restored_files: u64,
+    /// Files skipped by the size, mtime and name filters
+    #[serde(default, skip_serializing_if = "FilterCounts::is_empty")]
+    pub filtered: FilterCounts,
+    /// Additional hard links to files already counted
+    /// (indexed, but not counted again in the totals above)
+    #[serde(default)]
+    pub hard_links: u64,
+    /// Directories skipped by the mtime shortcut (files taken from the index)
+    #[serde(default)]
+    pub shortcut_dirs: u64,
     /// List of scanned files with metadata (new + modified only in incremental mode)
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub files: Vec<ScannedFile>,
     /// List of deleted file paths (incremental mode only)
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub deleted_paths: Vec<String>,
+    /// Directories listed in full (incremental mode only), stored for the mtime shortcut
+    #[serde(skip)]
+    pub directories: Vec<DirectoryRecord>,
+    /// Stored directories not seen in this scan (incremental mode only)
+    #[serde(skip)]
+    pub stale_dirs: Vec<String>,
     /// Errors encountered during scanning
     #[serde(skip)]
     pub errors: Vec<ScanError>,
     /// Total scan duration in milliseconds
     pub duration_ms: u64,
+    /// Whether the scan was cancelled before finishing (results are partial)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cancelled: bool,
 }
 
 impl ScanResult {
@@ -280,6 +470,104 @@ impl ScanResult {
     pub fn is_success(&self) -> bool {
         self.errors.is_empty()
     }
+
+    /// Summary record of the compact output (`_t: "s"`, abbreviated keys)
+    pub fn compact_summary(&self) -> serde_json::Value {
+        serde_json::json!({
+            "_t": "s",
+            "tf": self.total_files,
+            "td": self.total_dirs,
+            "nf": self.new_files,
+            "mf": self.modified_files,
+            "uf": self.unchanged_files,
+            "df": self.deleted_files,
+            "vf": self.moved_files,
+            "rf": self.restored_files,
+            "ff": self.filtered.total(),
+            "hl": self.hard_links,
+            "sd": self.shortcut_dirs,
+            "cx": self.cancelled,
+            "ec": self.error_count(),
+            "ms": self.duration_ms
+        })
+    }
+
+    /// Summary record of the NDJSON and JSON outputs (full key names)
+    pub fn summary(&self) -> serde_json::Value {
+        serde_json::json!({
+            "total_files": self.total_files,
+            "total_dirs": self.total_dirs,
+            "new_files": self.new_files,
+            "modified_files": self.modified_files,
+            "unchanged_files": self.unchanged_files,
+            "deleted_files": self.deleted_files,
+            "moved_files": self.moved_files,
+            "restored_files": self.restored_files,
+            "filtered_files": self.filtered.total(),
+            "hard_links": self.hard_links,
+            "shortcut_dirs": self.shortcut_dirs,
+            "cancelled": self.cancelled,
+            "error_count": self.error_count(),
+            "duration_ms": self.duration_ms
+        })
+    }
+
+    /// Sort `files` and `deleted_paths` so the output is the same on every run
+    ///
+    /// Ties are broken by path. `OutputOrder::None` keeps traversal order.
+    pub fn sort(&mut self, order: OutputOrder) {
+        match order {
+            OutputOrder::Path => self.files.sort_by(|a, b| path_key(a).cmp(&path_key(b))),
+            OutputOrder::Size => self.files.sort_by(|a, b| {
+                a.size
+                    .cmp(&b.size)
+                    .then_with(|| path_key(a).cmp(&path_key(b)))
+            }),
+            OutputOrder::Mtime => self.files.sort_by(|a, b| {
+                a.mtime
+                    .cmp(&b.mtime)
+                    .then_with(|| path_key(a).cmp(&path_key(b)))
+            }),
+            OutputOrder::None => return,
+        }
+        self.deleted_paths
+            .sort_by(|a, b| Path::new(a).cmp(Path::new(b)));
+    }
+
+    /// Group `files` by parent directory, keeping their order
+    ///
+    /// Directories appear in the order of their first file, which is path
+    /// order once sorted by `OutputOrder::Path`.
+    pub fn grouped_directories(&self) -> Vec<ScannedDirectory> {
+        let mut dirs: Vec<ScannedDirectory> = Vec::new();
+        let mut index: HashMap<String, usize> = HashMap::new();
+        for file in &self.files {
+            let Some(path) = file.full_path() else {
+                continue;
+            };
+            let dir = path
+                .parent()
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let i = *index.entry(dir.clone()).or_insert_with(|| {
+                dirs.push(ScannedDirectory {
+                    path: dir,
+                    files: Vec::new(),
+                });
+                dirs.len() - 1
+            });
+            dirs[i].files.push(CompactFile::from_scanned(file));
+        }
+        dirs
+    }
+}
+
+/// Sort key of `OutputOrder::Path`: parent directory, then file name
+fn path_key(file: &ScannedFile) -> (Option<&Path>, &str) {
+    (
+        file.path.as_deref().and_then(Path::parent),
+        file.name.as_str(),
+    )
 }
 
 /// Progress information during a scan
@@ -401,4 +689,51 @@ mod tests {
         assert_eq!(result.error_count(), 0);
         assert!(result.is_success());
     }
+
+    #[test]
+    fn test_sort_and_group() {
+        let file = |path: &str, size: u64, mtime: i64| {
+            let name = path.rsplit('/').next().unwrap().to_string();
+            ScannedFile::new(PathBuf::from(path), name, size, mtime, 0, "mp4".to_string())
+        };
+        let mut result = ScanResult::new();
+        result.files = vec![
+            file("/m/b/z.mp4", 1, 30),
+            file("/m/b.mp4", 3, 10),
+            file("/m/a.mp4", 2, 30),
+            file("/m/b/c.mp4", 2, 20),
+        ];
+        result.deleted_paths = vec!["/m/y.mp4".to_string(), "/m/x.mp4".to_string()];
+        let paths = |result: &ScanResult| -> Vec<String> {
+            result
+                .files
+                .iter()
+                .map(|f| f.path.as_ref().unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+
+        result.sort(OutputOrder::Path);
+        assert_eq!(
+            paths(&result),
+            vec!["/m/a.mp4", "/m/b.mp4", "/m/b/c.mp4", "/m/b/z.mp4"]
+        );
+        assert_eq!(result.deleted_paths, vec!["/m/x.mp4", "/m/y.mp4"]);
+        let dirs: Vec<(String, usize)> = result
+            .grouped_directories()
+            .into_iter()
+            .map(|d| (d.path, d.files.len()))
+            .collect();
+        assert_eq!(dirs, vec![("/m".to_string(), 2), ("/m/b".to_string(), 2)]);
+
+        result.sort(OutputOrder::Size);
+        assert_eq!(
+            paths(&result),
+            vec!["/m/b/z.mp4", "/m/a.mp4", "/m/b/c.mp4", "/m/b.mp4"]
+        );
+        result.sort(OutputOrder::Mtime);
+        assert_eq!(
+            paths(&result),
+            vec!["/m/b.mp4", "/m/b/c.mp4", "/m/a.mp4", "/m/b/z.mp4"]
+        );
+    }
 }
@@ -0,0 +1,352 @@
+//! Output writers for scan results
+//!
+//! `ResultWriter` serializes a `ScanResult` in any `OutputFormat` to a
+//! `Write`; this is what the CLI prints. `NdjsonWriter` and
+//! `CompactWriter` write the NDJSON and compact formats record by record,
+//! for callers that produce files incrementally instead of holding a whole
+//! `ScanResult`.
+//!
+//! The JSON formats are described by `protocol_schema()`.
+
+use std::io::{self, Write};
+
+use crate::export::{Column, TableFormat, TableWriter};
+use crate::models::{ScanResult, ScannedDirectory, ScannedFile};
+
+/// Format of scan result output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// Human-readable summary
+    #[default]
+    Text,
+    /// One JSON document: summary, directories and deleted paths
+    Json,
+    /// Summary line, then one line per file and per deleted path
+    Ndjson,
+    /// Summary line, then one line per directory with abbreviated keys
+    Compact,
+    /// Comma-separated values with a header row
+    Csv,
+    /// Tab-separated values with a header row
+    Tsv,
+}
+
+impl OutputFormat {
+    /// Get string representation
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutputFormat::Text => "text",
+            OutputFormat::Json => "json",
+            OutputFormat::Ndjson => "ndjson",
+            OutputFormat::Compact => "compact",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Tsv => "tsv",
+        }
+    }
+
+    /// Table flavor of the CSV and TSV formats
+    pub fn table_format(&self) -> Option<TableFormat> {
+        match self {
+            OutputFormat::Csv => Some(TableFormat::Csv),
+            OutputFormat::Tsv => Some(TableFormat::Tsv),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            "compact" => Ok(OutputFormat::Compact),
+            "csv" => Ok(OutputFormat::Csv),
+            "tsv" => Ok(OutputFormat::Tsv),
+            _ => Err(format!(
+                "invalid output format {:?} (expected text, json, ndjson, compact, csv or tsv)",
+                s
+            )),
+        }
+    }
+}
+
+/// Writer of whole scan results
+pub struct ResultWriter<W: Write> {
+    writer: W,
+    format: OutputFormat,
+    columns: Vec<Column>,
+}
+
+impl<W: Write> ResultWriter<W> {
+    /// Create a writer for `format`
+    pub fn new(writer: W, format: OutputFormat) -> Self {
+        Self {
+            writer,
+            format,
+            columns: Column::ALL.to_vec(),
+        }
+    }
+
+    /// Set the CSV/TSV columns (all columns when empty)
+    pub fn columns(mut self, columns: &[Column]) -> Self {
+        if !columns.is_empty() {
+            self.columns = columns.to_vec();
+        }
+        self
+    }
+
+    /// Write `result` and flush
+    pub fn write(&mut self, result: &ScanResult) -> io::Result<()> {
+        match self.format {
+            OutputFormat::Text => write_text(&mut self.writer, result)?,
+            OutputFormat::Json => {
+                let output = serde_json::json!({
+                    "summary": result.summary(),
+                    "directories": result.grouped_directories(),
+                    "deleted": result.deleted_paths
+                });
+                serde_json::to_writer_pretty(&mut self.writer, &output)?;
+                writeln!(self.writer)?;
+            }
+            OutputFormat::Ndjson => {
+                let mut ndjson = NdjsonWriter::new(&mut self.writer);
+                ndjson.write_summary(result)?;
+                for file in &result.files {
+                    ndjson.write_file(file)?;
+                }
+                for path in &result.deleted_paths {
+                    ndjson.write_deleted(path)?;
+                }
+            }
+            OutputFormat::Compact => {
+                let mut compact = CompactWriter::new(&mut self.writer);
+                compact.write_summary(result)?;
+                for dir in result.grouped_directories() {
+                    compact.write_directory(&dir)?;
+                }
+                compact.write_deleted(&result.deleted_paths)?;
+            }
+            OutputFormat::Csv | OutputFormat::Tsv => {
+                let format = self.format.table_format().expect("table format");
+                let mut table = TableWriter::new(&mut self.writer, format, &self.columns)?;
+                for file in &result.files {
+                    table.write_file(file)?;
+                }
+                for path in &result.deleted_paths {
+                    table.write_deleted(path)?;
+                }
+            }
+        }
+        self.writer.flush()
+    }
+
+    /// Get the underlying writer back
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Streaming NDJSON writer: summary, files and deleted paths, one per line
+pub struct NdjsonWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> NdjsonWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Write the summary record (`"_type": "summary"`)
+    pub fn write_summary(&mut self, result: &ScanResult) -> io::Result<()> {
+        let mut summary = result.summary();
+        summary["_type"] = "summary".into();
+        self.line(&summary)
+    }
+
+    /// Write a new or modified file
+    pub fn write_file(&mut self, file: &ScannedFile) -> io::Result<()> {
+        self.line(file)
+    }
+
+    /// Write a deleted path (`"_type": "deleted"`)
+    pub fn write_deleted(&mut self, path: &str) -> io::Result<()> {
+        self.line(&serde_json::json!({
+            "_type": "deleted",
+            "path": path
+        }))
+    }
+
+    fn line(&mut self, value: &impl serde::Serialize) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, value)?;
+        writeln!(self.writer)
+    }
+
+    /// Get the underlying writer back
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Streaming compact writer: summary, directories and deleted paths
+pub struct CompactWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> CompactWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Write the summary record (`"_t": "s"`)
+    pub fn write_summary(&mut self, result: &ScanResult) -> io::Result<()> {
+        self.line(&result.compact_summary())
+    }
+
+    /// Write one directory with its new or modified files
+    pub fn write_directory(&mut self, dir: &ScannedDirectory) -> io::Result<()> {
+        self.line(dir)
+    }
+
+    /// Write the deleted paths record (`"_t": "d"`); nothing when empty
+    pub fn write_deleted(&mut self, paths: &[String]) -> io::Result<()> {
+        if paths.is_empty() {
+            return Ok(());
+        }
+        self.line(&serde_json::json!({
+            "_t": "d",
+            "paths": paths
+        }))
+    }
+
+    fn line(&mut self, value: &impl serde::Serialize) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, value)?;
+        writeln!(self.writer)
+    }
+
+    /// Get the underlying writer back
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+fn write_text<W: Write>(writer: &mut W, result: &ScanResult) -> io::Result<()> {
+    if result.cancelled {
+        writeln!(writer, "扫描已取消（部分结果）:")?;
+    } else {
+        writeln!(writer, "扫描完成:")?;
+    }
+    writeln!(writer, "  媒体文件数: {}", result.total_files)?;
+    writeln!(writer, "  目录数: {}", result.total_dirs)?;
+    writeln!(writer, "  新文件: {}", result.new_files)?;
+    writeln!(writer, "  修改文件: {}", result.modified_files)?;
+    writeln!(writer, "  未更改: {}", result.unchanged_files)?;
+    writeln!(writer, "  删除文件: {}", result.deleted_files)?;
+    if result.moved_files > 0 {
+        writeln!(writer, "  移动文件: {}", result.moved_files)?;
+    }
+    if result.restored_files > 0 {
+        writeln!(writer, "  恢复文件: {}", result.restored_files)?;
+    }
+    if !result.filtered.is_empty() {
+        writeln!(
+            writer,
+            "  已过滤: {} (大小 {}, 修改时间 {}, 文件名 {})",
+            result.filtered.total(),
+            result.filtered.size,
+            result.filtered.mtime,
+            result.filtered.name
+        )?;
+    }
+    if result.hard_links > 0 {
+        writeln!(writer, "  硬链接（不重复计数）: {}", result.hard_links)?;
+    }
+    if result.shortcut_dirs > 0 {
+        writeln!(writer, "  未变化目录（沿用索引）: {}", result.shortcut_dirs)?;
+    }
+    writeln!(writer, "  错误数: {}", result.error_count())?;
+    writeln!(writer, "  耗时: {}ms", result.duration_ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn result() -> ScanResult {
+        let file = |path: &str| {
+            let name = path.rsplit('/').next().unwrap().to_string();
+            ScannedFile::new(PathBuf::from(path), name, 42, 100, 90, "mp4".to_string())
+        };
+        let mut result = ScanResult::new();
+        result.total_files = 3;
+        result.new_files = 3;
+        result.deleted_files = 1;
+        result.files = vec![file("/m/a.mp4"), file("/m/b.mp4"), file("/m/s/c.mp4")];
+        result.deleted_paths = vec!["/m/old.mp4".to_string()];
+        result
+    }
+
+    fn render(format: OutputFormat) -> String {
+        let mut writer = ResultWriter::new(Vec::new(), format);
+        writer.write(&result()).unwrap();
+        String::from_utf8(writer.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn test_json_formats() {
+        let lines: Vec<serde_json::Value> = render(OutputFormat::Compact)
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0]["_t"], "s");
+        assert_eq!(lines[1]["path"], "/m");
+        assert_eq!(lines[1]["files"].as_array().unwrap().len(), 2);
+        assert_eq!(lines[3]["paths"][0], "/m/old.mp4");
+
+        let lines: Vec<serde_json::Value> = render(OutputFormat::Ndjson)
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0]["_type"], "summary");
+        assert_eq!(lines[1]["name"], "a.mp4");
+        assert_eq!(lines[4]["_type"], "deleted");
+
+        let json: serde_json::Value = serde_json::from_str(&render(OutputFormat::Json)).unwrap();
+        assert_eq!(json["summary"]["total_files"], 3);
+        assert_eq!(json["directories"][1]["path"], "/m/s");
+        assert_eq!(json["deleted"][0], "/m/old.mp4");
+    }
+
+    #[test]
+    fn test_text_and_table() {
+        let text = render(OutputFormat::Text);
+        assert!(text.starts_with("扫描完成:\n"));
+        assert!(text.contains("  删除文件: 1\n"));
+
+        let mut writer = ResultWriter::new(Vec::new(), OutputFormat::Csv)
+            .columns(&[Column::Name, Column::Status]);
+        writer.write(&result()).unwrap();
+        let csv = String::from_utf8(writer.into_inner()).unwrap();
+        assert_eq!(
+            csv,
+            "name,status\r\na.mp4,new\r\nb.mp4,new\r\nc.mp4,new\r\nold.mp4,deleted\r\n"
+        );
+    }
+
+    #[test]
+    fn test_output_format_from_str() {
+        assert_eq!("NDJSON".parse(), Ok(OutputFormat::Ndjson));
+        assert!("xml".parse::<OutputFormat>().is_err());
+    }
+}
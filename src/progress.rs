@@ -1,18 +1,28 @@
 //! Progress reporting module for scan operations
 //!
 //! This module provides data structures and utilities for reporting
-//! scan progress to external callers via stderr.
+//! scan progress to external callers, as JSON on stderr by default or as
+//! typed events through a `ProgressSink`. Interactive runs get a live status
+//! line instead (`TerminalSink`).
 
 use serde::Serialize;
-use std::io::Write;
+use std::cell::{Cell, RefCell};
+use std::fs::File;
+use std::io::{IsTerminal, Write};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Instant;
 
-use crate::config::ScanConfig;
+use crate::config::{ProgressFormat, ScanConfig};
 use crate::error::ScanError;
 use crate::models::ScanResult;
 use crate::scanner::ScanProgress;
 
+/// Version of the progress and output protocol, sent as `v` in the start message
+///
+/// Bumped when a field is renamed, removed or changes meaning; new optional
+/// fields do not change the version. `media_scanner schema` prints the schema.
+pub const PROTOCOL_VERSION: u32 = 1;
+
 /// Scan phase indicator
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Default)]
 #[serde(rename_all = "lowercase")]
@@ -43,6 +53,8 @@ pub struct StartMessage {
     /// Message type identifier
     #[serde(rename = "_t")]
     pub msg_type: &'static str,
+    /// Protocol version (`PROTOCOL_VERSION`)
+    pub v: u32,
     /// Sequence number
     pub seq: u64,
     /// Timestamp in milliseconds since reporter creation
@@ -69,6 +81,7 @@ impl StartMessage {
     ) -> Self {
         Self {
             msg_type: "start",
+            v: PROTOCOL_VERSION,
             seq,
             ts,
             roots,
@@ -114,6 +127,13 @@ pub struct ProgressMessage {
     /// Estimated remaining time in milliseconds
     #[serde(skip_serializing_if = "Option::is_none")]
     pub eta_ms: Option<u64>,
+    /// Bytes read for hashing so far
+    #[serde(rename = "hb", skip_serializing_if = "is_zero")]
+    pub hashed_bytes: u64,
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
 }
 
 impl ProgressMessage {
@@ -145,8 +165,33 @@ impl ProgressMessage {
             dir,
             ms,
             eta_ms,
+            hashed_bytes: 0,
         }
     }
+
+    /// Set the number of bytes read for hashing so far
+    pub fn with_hashed_bytes(mut self, hashed_bytes: u64) -> Self {
+        self.hashed_bytes = hashed_bytes;
+        self
+    }
+
+    /// Create a progress message from the scanner's progress counters
+    pub fn from_progress(seq: u64, ts: u64, progress: &ScanProgress) -> Self {
+        Self::new(
+            seq,
+            ts,
+            progress.phase,
+            progress.scanned_files,
+            progress.scanned_dirs,
+            progress.video_count,
+            progress.image_count,
+            progress.audio_count,
+            progress.current_dir.clone(),
+            progress.elapsed_ms,
+            progress.estimated_remaining_ms(),
+        )
+        .with_hashed_bytes(progress.hashed_bytes)
+    }
 }
 
 
@@ -250,24 +295,408 @@ impl DoneMessage {
     }
 }
 
-/// Progress reporter for outputting scan progress to stderr
+/// Receiver of scan progress events
+///
+/// All hooks default to doing nothing, so implementations only override the
+/// events they need. Hooks are called on the scanning thread.
+pub trait ProgressSink {
+    /// The scan is starting
+    fn on_start(&self, _msg: &StartMessage) {}
+
+    /// Periodic progress update (at most once per reporting interval)
+    fn on_progress(&self, _msg: &ProgressMessage) {}
+
+    /// The scan entered a new phase
+    fn on_phase(&self, _phase: ScanPhase) {}
+
+    /// An error was encountered; the scan continues
+    fn on_error(&self, _msg: &ErrorProgressMessage) {}
+
+    /// The scan finished (or was cancelled)
+    fn on_done(&self, _msg: &DoneMessage) {}
+}
+
+impl<T: ProgressSink + ?Sized> ProgressSink for &T {
+    fn on_start(&self, msg: &StartMessage) {
+        (**self).on_start(msg)
+    }
+
+    fn on_progress(&self, msg: &ProgressMessage) {
+        (**self).on_progress(msg)
+    }
+
+    fn on_phase(&self, phase: ScanPhase) {
+        (**self).on_phase(phase)
+    }
+
+    fn on_error(&self, msg: &ErrorProgressMessage) {
+        (**self).on_error(msg)
+    }
+
+    fn on_done(&self, msg: &DoneMessage) {
+        (**self).on_done(msg)
+    }
+}
+
+/// Sink writing each message as a JSON line to stderr (the default)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StderrJsonSink;
+
+impl ProgressSink for StderrJsonSink {
+    fn on_start(&self, msg: &StartMessage) {
+        write_stderr_json(msg);
+    }
+
+    fn on_progress(&self, msg: &ProgressMessage) {
+        write_stderr_json(msg);
+    }
+
+    fn on_error(&self, msg: &ErrorProgressMessage) {
+        write_stderr_json(msg);
+    }
+
+    fn on_done(&self, msg: &DoneMessage) {
+        write_stderr_json(msg);
+    }
+}
+
+/// Sink passing each message, serialized as a JSON string, to a closure
+pub struct JsonLineSink<F: Fn(&str)>(pub F);
+
+impl<F: Fn(&str)> JsonLineSink<F> {
+    fn send<T: Serialize>(&self, msg: &T) {
+        if let Ok(json) = serde_json::to_string(msg) {
+            (self.0)(&json);
+        }
+    }
+}
+
+impl<F: Fn(&str)> ProgressSink for JsonLineSink<F> {
+    fn on_start(&self, msg: &StartMessage) {
+        self.send(msg);
+    }
+
+    fn on_progress(&self, msg: &ProgressMessage) {
+        self.send(msg);
+    }
+
+    fn on_error(&self, msg: &ErrorProgressMessage) {
+        self.send(msg);
+    }
+
+    fn on_done(&self, msg: &DoneMessage) {
+        self.send(msg);
+    }
+}
+
+/// Sink writing each message as a JSON line to a writer, flushing after each line
+pub struct JsonWriterSink<W: Write>(RefCell<W>);
+
+impl<W: Write> JsonWriterSink<W> {
+    /// Create a sink writing to `writer`
+    pub fn new(writer: W) -> Self {
+        Self(RefCell::new(writer))
+    }
+
+    /// Get the underlying writer back
+    pub fn into_inner(self) -> W {
+        self.0.into_inner()
+    }
+
+    fn send<T: Serialize>(&self, msg: &T) {
+        let mut writer = self.0.borrow_mut();
+        let written = serde_json::to_writer(&mut *writer, msg)
+            .map_err(std::io::Error::from)
+            .and_then(|_| writeln!(writer))
+            .and_then(|_| writer.flush());
+        if let Err(e) = written {
+            log::debug!("Failed to write progress message: {}", e);
+        }
+    }
+}
+
+impl<W: Write> ProgressSink for JsonWriterSink<W> {
+    fn on_start(&self, msg: &StartMessage) {
+        self.send(msg);
+    }
+
+    fn on_progress(&self, msg: &ProgressMessage) {
+        self.send(msg);
+    }
+
+    fn on_error(&self, msg: &ErrorProgressMessage) {
+        self.send(msg);
+    }
+
+    fn on_done(&self, msg: &DoneMessage) {
+        self.send(msg);
+    }
+}
+
+/// Sink rendering a live status line on stderr, for interactive use
+///
+/// The line shows file and hashing rates, media counts, the ETA when known
+/// and the current directory, and is redrawn in place on every update.
+#[derive(Debug, Default)]
+pub struct TerminalSink {
+    /// Files, hashed bytes and elapsed ms at the previous update, for rates
+    last: Cell<(u64, u64, u64)>,
+    /// Whether a status line is currently drawn
+    drawn: Cell<bool>,
+}
+
+impl TerminalSink {
+    /// Create a sink drawing on stderr
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the status line with `line`, or clear it when `line` is empty
+    fn draw(&self, line: &str) {
+        let mut err = std::io::stderr().lock();
+        write!(err, "\r\x1b[2K{}", line).ok();
+        err.flush().ok();
+        self.drawn.set(!line.is_empty());
+    }
+
+    /// Print `text` on its own line, clearing the status line first
+    fn print(&self, text: &str) {
+        if self.drawn.get() {
+            self.draw("");
+        }
+        eprintln!("{}", text);
+    }
+}
+
+impl ProgressSink for TerminalSink {
+    fn on_progress(&self, msg: &ProgressMessage) {
+        let (files, bytes, ms) = self.last.get();
+        let span = msg.ms.saturating_sub(ms);
+        let rates = (span > 0).then(|| {
+            (
+                per_second(msg.files.saturating_sub(files), span),
+                per_second(msg.hashed_bytes.saturating_sub(bytes), span),
+            )
+        });
+        self.last.set((msg.files, msg.hashed_bytes, msg.ms));
+        self.draw(&status_line(msg, rates, terminal_width()));
+    }
+
+    fn on_phase(&self, phase: ScanPhase) {
+        if phase == ScanPhase::Process {
+            self.draw("处理中: 检测已删除文件并汇总结果...");
+        }
+    }
+
+    fn on_error(&self, msg: &ErrorProgressMessage) {
+        match msg.path {
+            Some(ref path) => self.print(&format!("错误: {} ({})", msg.message, path)),
+            None => self.print(&format!("错误: {}", msg.message)),
+        }
+    }
+
+    fn on_done(&self, msg: &DoneMessage) {
+        self.print(&format!(
+            "完成: {} 个文件, {} 个目录 (新增 {}, 修改 {}, 删除 {}, 错误 {}), 用时 {}",
+            msg.total_files,
+            msg.total_dirs,
+            msg.new_files,
+            msg.modified_files,
+            msg.deleted_files,
+            msg.error_count,
+            format_duration(msg.ms),
+        ));
+    }
+}
+
+/// Format the status line for `msg`, fitting it into `width` columns
+///
+/// `rates` holds files/s and hashed bytes/s since the previous update.
+fn status_line(msg: &ProgressMessage, rates: Option<(u64, u64)>, width: usize) -> String {
+    let mut line = format!("扫描 {} 个文件, {} 个目录", msg.files, msg.dirs);
+    if let Some((files_per_sec, bytes_per_sec)) = rates {
+        line.push_str(&format!(" | {} 文件/s", files_per_sec));
+        if msg.hashed_bytes > 0 {
+            line.push_str(&format!(", {}/s", format_bytes(bytes_per_sec)));
+        }
+    }
+    line.push_str(&format!(
+        " | 视频 {} 图片 {} 音频 {}",
+        msg.video_count, msg.image_count, msg.audio_count
+    ));
+    if let Some(eta) = msg.eta_ms {
+        line.push_str(&format!(" | 剩余 {}", format_duration(eta)));
+    }
+    // The directory gets the room left; a full-width line would wrap and break redrawing
+    let used = display_width(&line) + 3;
+    if used + 8 <= width {
+        line.push_str(" | ");
+        line.push_str(&truncate_left(&msg.dir, width - used - 1));
+    }
+    line
+}
+
+/// Amount per second for `count` over `ms` milliseconds
+fn per_second(count: u64, ms: u64) -> u64 {
+    count.saturating_mul(1000) / ms.max(1)
+}
+
+/// Format a byte count with a binary unit (e.g. `45.2 MB`)
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Format milliseconds as `mm:ss`, or `h:mm:ss` from one hour on
+fn format_duration(ms: u64) -> String {
+    let secs = ms / 1000;
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{:02}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+/// Columns taken by `c` on a terminal (wide CJK characters take two)
+fn char_width(c: char) -> usize {
+    match c as u32 {
+        0x1100..=0x115F
+        | 0x2E80..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1F64F
+        | 0x20000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+fn display_width(s: &str) -> usize {
+    s.chars().map(char_width).sum()
+}
+
+/// Shorten `s` to at most `width` columns, keeping its end (the most specific part of a path)
+fn truncate_left(s: &str, width: usize) -> String {
+    if display_width(s) <= width {
+        return s.to_string();
+    }
+    let mut tail = Vec::new();
+    let mut used = char_width('…');
+    for c in s.chars().rev() {
+        used += char_width(c);
+        if used > width {
+            break;
+        }
+        tail.push(c);
+    }
+    std::iter::once('…').chain(tail.into_iter().rev()).collect()
+}
+
+/// Width of the terminal on stderr, else the `COLUMNS` variable, else 100
+fn terminal_width() -> usize {
+    #[cfg(unix)]
+    {
+        // SAFETY: TIOCGWINSZ only writes into the winsize we pass
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        if unsafe { libc::ioctl(libc::STDERR_FILENO, libc::TIOCGWINSZ, &mut size) } == 0
+            && size.ws_col > 0
+        {
+            return size.ws_col as usize;
+        }
+    }
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|c| c.parse().ok())
+        .filter(|&c| c > 0)
+        .unwrap_or(100)
+}
+
+/// Whether progress on stderr should be drawn as a status line for `format`
+fn use_terminal(format: ProgressFormat) -> bool {
+    match format {
+        ProgressFormat::Auto => std::io::stderr().is_terminal(),
+        ProgressFormat::Json => false,
+        ProgressFormat::Tty => true,
+    }
+}
+
+/// Open the progress channel configured by `progress_fd` or `progress_file`
+fn open_progress_output(config: &ScanConfig) -> std::io::Result<Option<File>> {
+    if let Some(fd) = config.progress_fd {
+        return duplicate_fd(fd).map(Some);
+    }
+    match config.progress_file {
+        Some(ref path) => File::create(path).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Duplicate a caller-provided descriptor, so the caller keeps ownership of the original
+#[cfg(unix)]
+fn duplicate_fd(fd: i32) -> std::io::Result<File> {
+    use std::os::unix::io::FromRawFd;
+
+    // SAFETY: fcntl only reads `fd`; on success the returned descriptor is new and owned by us
+    let dup = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+    if dup < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: `dup` is a valid descriptor that nothing else owns
+    Ok(unsafe { File::from_raw_fd(dup) })
+}
+
+#[cfg(not(unix))]
+fn duplicate_fd(_fd: i32) -> std::io::Result<File> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "progress_fd is only supported on Unix",
+    ))
+}
+
+/// Write a serializable message to stderr as one JSON line
+pub(crate) fn write_stderr_json<T: Serialize>(msg: &T) {
+    if let Ok(json) = serde_json::to_string(msg) {
+        eprintln!("{}", json);
+        std::io::stderr().flush().ok();
+    }
+}
+
+/// Progress reporter for outputting scan progress
 ///
 /// This component manages the output of progress messages during scan operations.
-/// It handles timing, sequence numbers, and formatting of various message types.
-pub struct ProgressReporter {
+/// It handles timing, sequence numbers, and formatting of various message types,
+/// and passes each message to its sink (JSON on stderr unless another is given).
+/// Sinks must be `Send`, so a reporter can be moved to a worker thread.
+pub struct ProgressReporter<'a> {
     /// Whether progress reporting is enabled
     enabled: bool,
     /// Reporting interval in milliseconds
     interval_ms: u64,
     /// Last report time
-    last_report: std::cell::Cell<Instant>,
+    last_report: Cell<Instant>,
     /// Sequence number for messages (atomic for thread safety)
     seq: AtomicU64,
     /// Start time of the reporter
     start_time: Instant,
+    /// Phase of the last event, to detect phase changes
+    phase: Cell<Option<ScanPhase>>,
+    /// Receives every message
+    sink: Box<dyn ProgressSink + Send + 'a>,
 }
 
-impl ProgressReporter {
+impl ProgressReporter<'static> {
     /// Create a new ProgressReporter
     ///
     /// # Arguments
@@ -278,9 +707,62 @@ impl ProgressReporter {
         Self {
             enabled,
             interval_ms,
-            last_report: std::cell::Cell::new(now),
+            last_report: Cell::new(now),
+            seq: AtomicU64::new(0),
+            start_time: now,
+            phase: Cell::new(None),
+            sink: Box::new(StderrJsonSink),
+        }
+    }
+
+    /// Create a reporter for `config`, writing to its progress channel
+    ///
+    /// Messages go to `progress_fd` or `progress_file` when set (falling back to
+    /// stderr with a warning if it cannot be opened), otherwise to stderr, where
+    /// `progress_format` picks between JSON lines and a live status line.
+    pub fn from_config(config: &ScanConfig, enabled: bool) -> Self {
+        let interval = config.progress_interval_ms;
+        if enabled {
+            match open_progress_output(config) {
+                Ok(Some(file)) => {
+                    return ProgressReporter::with_sink(
+                        enabled,
+                        interval,
+                        JsonWriterSink::new(file),
+                    );
+                }
+                Ok(None) => {}
+                Err(e) => log::warn!("Cannot open progress output, using stderr: {}", e),
+            }
+            if use_terminal(config.progress_format) {
+                return ProgressReporter::with_sink(enabled, interval, TerminalSink::new());
+            }
+        }
+        Self::new(enabled, interval)
+    }
+
+    /// Create a reporter that passes each message, as a JSON string, to `output`
+    pub fn with_output(
+        enabled: bool,
+        interval_ms: u64,
+        output: impl Fn(&str) + Send + 'static,
+    ) -> Self {
+        ProgressReporter::with_sink(enabled, interval_ms, JsonLineSink(output))
+    }
+}
+
+impl<'a> ProgressReporter<'a> {
+    /// Create a reporter that passes each message to `sink`
+    pub fn with_sink(enabled: bool, interval_ms: u64, sink: impl ProgressSink + Send + 'a) -> Self {
+        let now = Instant::now();
+        Self {
+            enabled,
+            interval_ms,
+            last_report: Cell::new(now),
             seq: AtomicU64::new(0),
             start_time: now,
+            phase: Cell::new(None),
+            sink: Box::new(sink),
         }
     }
 
@@ -307,10 +789,16 @@ impl ProgressReporter {
 
     /// Output a serializable message to stderr as JSON
     pub fn output_to_stderr<T: Serialize>(&self, msg: &T) {
-        if let Ok(json) = serde_json::to_string(msg) {
-            eprintln!("{}", json);
-            std::io::stderr().flush().ok();
+        write_stderr_json(msg);
+    }
+
+    /// Report the current scan phase, notifying the sink when it changes
+    pub fn report_phase(&self, phase: ScanPhase) {
+        if !self.enabled || self.phase.get() == Some(phase) {
+            return;
         }
+        self.phase.set(Some(phase));
+        self.sink.on_phase(phase);
     }
 
     /// Report scan start
@@ -336,7 +824,8 @@ impl ProgressReporter {
             config.compute_hash,
         );
 
-        self.output_to_stderr(&msg);
+        self.sink.on_start(&msg);
+        self.report_phase(ScanPhase::Scan);
     }
 
     /// Report scan progress
@@ -348,25 +837,15 @@ impl ProgressReporter {
             return false;
         }
 
+        self.report_phase(progress.phase);
         if !self.should_report() {
             return false;
         }
 
-        let msg = ProgressMessage::new(
-            self.next_seq(),
-            self.current_timestamp(),
-            ScanPhase::Scan,
-            progress.scanned_files,
-            progress.scanned_dirs,
-            progress.video_count,
-            progress.image_count,
-            progress.audio_count,
-            progress.current_dir.clone(),
-            progress.elapsed_ms,
-            None, // eta_ms - can be calculated externally if needed
-        );
+        let msg =
+            ProgressMessage::from_progress(self.next_seq(), self.current_timestamp(), progress);
 
-        self.output_to_stderr(&msg);
+        self.sink.on_progress(&msg);
         self.last_report.set(Instant::now());
         true
     }
@@ -387,7 +866,7 @@ impl ProgressReporter {
             error.path.as_ref().map(|p| p.to_string_lossy().to_string()),
         );
 
-        self.output_to_stderr(&msg);
+        self.sink.on_error(&msg);
     }
 
     /// Report scan completion
@@ -410,7 +889,8 @@ impl ProgressReporter {
             result.duration_ms,
         );
 
-        self.output_to_stderr(&msg);
+        self.report_phase(ScanPhase::Done);
+        self.sink.on_done(&msg);
     }
 
     /// Check if the reporter is enabled
@@ -522,6 +1002,50 @@ mod tests {
         assert!(!json.contains("eta_ms"));
     }
 
+    #[test]
+    fn test_status_line() {
+        let msg = ProgressMessage::new(
+            1,
+            100,
+            ScanPhase::Scan,
+            1200,
+            30,
+            10,
+            20,
+            3,
+            "/media/photos/2024/summer".to_string(),
+            2000,
+            Some(83_000),
+        )
+        .with_hashed_bytes(5 << 20);
+        assert_eq!(serde_json::to_value(&msg).unwrap()["hb"], 5 << 20);
+
+        let line = status_line(&msg, Some((600, 3 << 20)), 200);
+        assert!(line.contains("1200 个文件"));
+        assert!(line.contains("600 文件/s, 3.0 MB/s"));
+        assert!(line.contains("视频 10 图片 20 音频 3"));
+        assert!(line.contains("剩余 01:23"));
+        assert!(line.ends_with("/media/photos/2024/summer"));
+
+        // The directory is shortened from the left to keep the line on one row
+        let line = status_line(&msg, None, 90);
+        assert!(display_width(&line) < 90);
+        assert!(line.ends_with("summer"));
+        assert!(line.contains('…'));
+    }
+
+    #[test]
+    fn test_terminal_formatting() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(45 * 1024 * 1024 + 200 * 1024), "45.2 MB");
+        assert_eq!(format_duration(59_999), "00:59");
+        assert_eq!(format_duration(3_723_000), "1:02:03");
+        assert_eq!(per_second(300, 200), 1500);
+        assert_eq!(truncate_left("/a/b/c", 10), "/a/b/c");
+        assert_eq!(truncate_left("/abc/def/ghi", 6), "…f/ghi");
+        assert_eq!(truncate_left("/照片/夏天", 5), "…夏天");
+    }
+
     #[test]
     fn test_error_message_serialization() {
         let msg = ErrorProgressMessage::new(
@@ -690,6 +1214,111 @@ mod tests {
         reporter.report_error(&error);
     }
 
+    #[test]
+    fn test_progress_reporter_with_output() {
+        use std::sync::{Arc, Mutex};
+
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&lines);
+        let reporter = ProgressReporter::with_output(true, 200, move |line| {
+            sink.lock().unwrap().push(line.to_string());
+        });
+        reporter.report_start(&ScanConfig::default());
+        reporter.report_done(&ScanResult::default());
+
+        let lines = lines.lock().unwrap();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("\"_t\":\"start\""));
+        assert!(lines[1].contains("\"_t\":\"done\""));
+    }
+
+    #[test]
+    fn test_progress_reporter_writes_progress_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("progress.ndjson");
+        let config = ScanConfig::builder().progress_file(&path).build();
+
+        let reporter = ProgressReporter::from_config(&config, true);
+        reporter.report_start(&config);
+        reporter.report_done(&ScanResult::default());
+        drop(reporter);
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("{\"_t\":\"start\""));
+        assert!(lines[1].starts_with("{\"_t\":\"done\""));
+
+        // Disabled reporters never touch the channel
+        std::fs::remove_file(&path).unwrap();
+        ProgressReporter::from_config(&config, false).report_start(&config);
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_progress_reporter_writes_progress_fd() {
+        use std::io::Read;
+        use std::os::unix::io::AsRawFd;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fd.ndjson");
+        let file = File::create(&path).unwrap();
+        let config = ScanConfig::builder().progress_fd(file.as_raw_fd()).build();
+
+        let reporter = ProgressReporter::from_config(&config, true);
+        reporter.report_start(&config);
+        drop(reporter);
+
+        // The caller's descriptor stays open
+        writeln!(&file, "after").unwrap();
+        let mut content = String::new();
+        File::open(&path)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert!(content.starts_with("{\"_t\":\"start\""));
+        assert!(content.ends_with("after\n"));
+    }
+
+    #[test]
+    fn test_progress_reporter_is_send() {
+        fn assert_send<T: Send>(_: &T) {}
+        assert_send(&ProgressReporter::new(true, 200));
+        assert_send(&ProgressReporter::with_output(true, 200, |_| {}));
+    }
+
+    #[test]
+    fn test_progress_reporter_sink_phases() {
+        use std::sync::Mutex;
+
+        #[derive(Default)]
+        struct Phases(Mutex<Vec<ScanPhase>>);
+
+        impl ProgressSink for Phases {
+            fn on_phase(&self, phase: ScanPhase) {
+                self.0.lock().unwrap().push(phase);
+            }
+        }
+
+        let sink = Phases::default();
+        let reporter = ProgressReporter::with_sink(true, 60_000, &sink);
+        reporter.report_start(&ScanConfig::default());
+        let mut progress = ScanProgress::default();
+        reporter.report_progress(&progress);
+        progress.phase = ScanPhase::Process;
+        // Phase changes are reported even when the progress update is throttled
+        assert!(!reporter.report_progress(&progress));
+        reporter.report_phase(ScanPhase::Process);
+        reporter.report_done(&ScanResult::default());
+        drop(reporter);
+
+        assert_eq!(
+            sink.0.into_inner().unwrap(),
+            vec![ScanPhase::Scan, ScanPhase::Process, ScanPhase::Done]
+        );
+    }
+
     #[test]
     fn test_progress_reporter_report_done_disabled() {
         use crate::models::ScanResult;
@@ -0,0 +1,352 @@
+//! Path filtering rules applied during directory traversal
+//!
+//! Combines the configured include/exclude patterns with `.mediaignore`
+//! files found while walking, so excluded subtrees can be pruned before
+//! they are descended into. Also holds the per-file size, mtime and name
+//! filters applied before a file is hashed.
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use regex::Regex;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::config::{PathRules, ScanConfig, IGNORE_FILE_NAME};
+use crate::error::ScanError;
+
+/// Prefix marking a pattern as a regular expression
+const REGEX_PREFIX: &str = "re:";
+
+/// A compiled set of gitignore-style globs and regular expressions
+#[derive(Debug)]
+struct PatternSet {
+    globs: Gitignore,
+    regexes: Vec<Regex>,
+}
+
+impl PatternSet {
+    /// Compile patterns relative to `root`
+    fn new(root: &Path, patterns: &[String]) -> Result<Self, ScanError> {
+        let mut builder = GitignoreBuilder::new(root);
+        let mut regexes = Vec::new();
+
+        for pattern in patterns {
+            if let Some(expr) = pattern.strip_prefix(REGEX_PREFIX) {
+                let regex = Regex::new(expr).map_err(|e| {
+                    ScanError::config_error(None, format!("Invalid regex {:?}: {}", pattern, e))
+                })?;
+                regexes.push(regex);
+            } else {
+                builder.add_line(None, pattern).map_err(|e| {
+                    ScanError::config_error(None, format!("Invalid pattern {:?}: {}", pattern, e))
+                })?;
+            }
+        }
+
+        let globs = builder
+            .build()
+            .map_err(|e| ScanError::config_error(None, e.to_string()))?;
+
+        Ok(Self { globs, regexes })
+    }
+
+    /// Check if the set has no patterns
+    fn is_empty(&self) -> bool {
+        self.globs.is_empty() && self.regexes.is_empty()
+    }
+
+    /// Check if a path matches the set
+    ///
+    /// `relative` is the path relative to the root with forward slashes.
+    fn is_match(&self, path: &Path, relative: &str, is_dir: bool) -> bool {
+        if self
+            .globs
+            .matched_path_or_any_parents(path, is_dir)
+            .is_ignore()
+        {
+            return true;
+        }
+        self.regexes.iter().any(|r| r.is_match(relative))
+    }
+}
+
+/// Path filter for a single scan root
+#[derive(Debug)]
+pub struct PathFilter {
+    /// Root directory the patterns are relative to
+    root: PathBuf,
+    /// Include patterns (files only)
+    include: PatternSet,
+    /// Exclude patterns (files and directories)
+    exclude: PatternSet,
+    /// Whether `.mediaignore` files are honoured
+    use_ignore_files: bool,
+    /// Parsed `.mediaignore` files keyed by directory (None = no file)
+    ignore_files: RefCell<HashMap<PathBuf, Option<Gitignore>>>,
+}
+
+impl PathFilter {
+    /// Create a filter for `root` from the scan configuration
+    pub fn new(config: &ScanConfig, root: &Path) -> Result<Self, ScanError> {
+        Self::from_rules(root, &config.rules_for_root(root), config.use_ignore_files)
+    }
+
+    /// Create a filter from an explicit rule set
+    pub fn from_rules(
+        root: &Path,
+        rules: &PathRules,
+        use_ignore_files: bool,
+    ) -> Result<Self, ScanError> {
+        Ok(Self {
+            root: root.to_path_buf(),
+            include: PatternSet::new(root, &rules.include)?,
+            exclude: PatternSet::new(root, &rules.exclude)?,
+            use_ignore_files,
+            ignore_files: RefCell::new(HashMap::new()),
+        })
+    }
+
+    /// Check if an entry should be skipped (and, for directories, not descended into)
+    pub fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        if path == self.root {
+            return false;
+        }
+
+        let relative = self.relative(path);
+        if self.exclude.is_match(path, &relative, is_dir) {
+            return true;
+        }
+
+        self.use_ignore_files && self.is_ignored_by_files(path, is_dir)
+    }
+
+    /// Check if a file passes the include patterns
+    pub fn is_included(&self, path: &Path) -> bool {
+        if self.include.is_empty() {
+            return true;
+        }
+        let relative = self.relative(path);
+        self.include.is_match(path, &relative, false)
+    }
+
+    /// Path relative to the root with forward slashes
+    fn relative(&self, path: &Path) -> String {
+        path.strip_prefix(&self.root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .replace('\\', "/")
+    }
+
+    /// Check the `.mediaignore` files of all ancestors, deepest first
+    fn is_ignored_by_files(&self, path: &Path, is_dir: bool) -> bool {
+        let mut dir = path.parent();
+        while let Some(current) = dir {
+            if !current.starts_with(&self.root) {
+                break;
+            }
+            match self.ignore_file_match(current, path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+            if current == self.root {
+                break;
+            }
+            dir = current.parent();
+        }
+        false
+    }
+
+    /// Match a path against the `.mediaignore` file in `dir`, loading it on first use
+    fn ignore_file_match(&self, dir: &Path, path: &Path, is_dir: bool) -> Match<()> {
+        let mut cache = self.ignore_files.borrow_mut();
+        let gitignore = cache.entry(dir.to_path_buf()).or_insert_with(|| {
+            let file = dir.join(IGNORE_FILE_NAME);
+            if !file.is_file() {
+                return None;
+            }
+            let (gitignore, err) = Gitignore::new(&file);
+            if let Some(e) = err {
+                log::warn!("Problem reading {:?}: {}", file, e);
+            }
+            Some(gitignore)
+        });
+
+        match gitignore {
+            Some(g) => match g.matched(path, is_dir) {
+                Match::Ignore(_) => Match::Ignore(()),
+                Match::Whitelist(_) => Match::Whitelist(()),
+                Match::None => Match::None,
+            },
+            None => Match::None,
+        }
+    }
+}
+
+/// Reason a file was skipped by [`FileFilter`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterReason {
+    /// File size outside the configured range
+    Size,
+    /// Modification time outside the configured range
+    Mtime,
+    /// File name matches an excluded pattern
+    Name,
+}
+
+/// Size, mtime and name filters with relative times resolved
+#[derive(Debug)]
+pub struct FileFilter {
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    min_mtime: Option<i64>,
+    max_mtime: Option<i64>,
+    exclude_names: GlobSet,
+}
+
+impl FileFilter {
+    /// Build the filter from the config, resolving relative times against `now`
+    pub fn new(config: &ScanConfig, now: i64) -> Result<Self, ScanError> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in &config.exclude_names {
+            let glob = Glob::new(pattern).map_err(|e| {
+                ScanError::config_error(None, format!("Invalid name pattern {:?}: {}", pattern, e))
+            })?;
+            builder.add(glob);
+        }
+        let exclude_names = builder
+            .build()
+            .map_err(|e| ScanError::config_error(None, e.to_string()))?;
+
+        Ok(Self {
+            min_size: config.min_size,
+            max_size: config.max_size,
+            min_mtime: config.min_mtime.map(|b| b.resolve(now)),
+            max_mtime: config.max_mtime.map(|b| b.resolve(now)),
+            exclude_names,
+        })
+    }
+
+    /// Check a file, returning the reason it is filtered out (None = keep)
+    pub fn check(&self, name: &str, size: u64, mtime: i64) -> Option<FilterReason> {
+        if !self.exclude_names.is_empty() && self.exclude_names.is_match(name) {
+            return Some(FilterReason::Name);
+        }
+        if self.min_size.is_some_and(|min| size < min)
+            || self.max_size.is_some_and(|max| size > max)
+        {
+            return Some(FilterReason::Size);
+        }
+        if self.min_mtime.is_some_and(|min| mtime < min)
+            || self.max_mtime.is_some_and(|max| mtime > max)
+        {
+            return Some(FilterReason::Mtime);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn rules(include: &[&str], exclude: &[&str]) -> PathRules {
+        PathRules {
+            include: include.iter().map(|s| s.to_string()).collect(),
+            exclude: exclude.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_exclude_globs() {
+        let root = Path::new("/media");
+        let filter = PathFilter::from_rules(
+            root,
+            &rules(&[], &["*.part", "**/Samples/**", "@eaDir/"]),
+            false,
+        )
+        .unwrap();
+
+        assert!(filter.is_excluded(&root.join("movie.mp4.part"), false));
+        assert!(filter.is_excluded(&root.join("a/Samples/clip.mp4"), false));
+        assert!(filter.is_excluded(&root.join("a/@eaDir"), true));
+        assert!(!filter.is_excluded(&root.join("a/movie.mp4"), false));
+        assert!(!filter.is_excluded(root, true));
+    }
+
+    #[test]
+    fn test_regex_and_include() {
+        let root = Path::new("/media");
+        let filter =
+            PathFilter::from_rules(root, &rules(&["Movies/**"], &[r"re:(?i)\bsample\b"]), false)
+                .unwrap();
+
+        assert!(filter.is_excluded(&root.join("Movies/x/Sample.mkv"), false));
+        assert!(filter.is_included(&root.join("Movies/x/movie.mkv")));
+        assert!(!filter.is_included(&root.join("Music/song.mp3")));
+    }
+
+    #[test]
+    fn test_invalid_regex_is_error() {
+        let result = PathFilter::from_rules(Path::new("/media"), &rules(&[], &["re:("]), false);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_mediaignore_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("shows/extras")).unwrap();
+        fs::write(
+            root.join("shows").join(IGNORE_FILE_NAME),
+            "extras/\n*.nfo\n",
+        )
+        .unwrap();
+
+        let filter = PathFilter::from_rules(root, &PathRules::default(), true).unwrap();
+        assert!(filter.is_excluded(&root.join("shows/extras"), true));
+        assert!(filter.is_excluded(&root.join("shows/a/info.nfo"), false));
+        assert!(!filter.is_excluded(&root.join("shows/a/ep1.mkv"), false));
+        assert!(!filter.is_excluded(&root.join("other/info.nfo"), false));
+
+        let disabled = PathFilter::from_rules(root, &PathRules::default(), false).unwrap();
+        assert!(!disabled.is_excluded(&root.join("shows/extras"), true));
+    }
+
+    #[test]
+    fn test_file_filter() {
+        use crate::config::TimeBound;
+
+        let config = ScanConfig::builder()
+            .min_size(1024)
+            .max_size(10 * 1024)
+            .max_mtime(TimeBound::Age(600))
+            .exclude_name("*.part")
+            .exclude_name("._*")
+            .build();
+        let now = 10_000;
+        let filter = FileFilter::new(&config, now).unwrap();
+
+        assert_eq!(filter.check("a.mp4", 2048, 1000), None);
+        assert_eq!(filter.check("a.mp4", 100, 1000), Some(FilterReason::Size));
+        assert_eq!(
+            filter.check("a.mp4", 20_000, 1000),
+            Some(FilterReason::Size)
+        );
+        assert_eq!(
+            filter.check("a.mp4", 2048, 9_900),
+            Some(FilterReason::Mtime)
+        );
+        assert_eq!(
+            filter.check("a.mp4.part", 2048, 1000),
+            Some(FilterReason::Name)
+        );
+        assert_eq!(
+            filter.check("._a.jpg", 2048, 1000),
+            Some(FilterReason::Name)
+        );
+    }
+}
@@ -1,19 +1,23 @@
 //! Scanner module - implements the actual file scanning logic
 
-use std::collections::HashSet;
-use std::io::Write;
-use std::path::Path;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
 use std::time::Instant;
 use walkdir::WalkDir;
 
-use crate::config::ScanConfig;
-use crate::db::{FileRecord, ScanDatabase};
+use crate::config::{ChangeDetection, ScanConfig};
+use crate::db::{DirectoryRecord, FileRecord, ScanDatabase};
 use crate::error::{ScanError, ScanErrorKind};
-use crate::models::{FileStatus, MediaType, ScanResult, ScannedFile};
-use crate::progress::{ProgressReporter, ScanPhase};
+use crate::models::{FileId, FileStatus, FilterCounts, MediaType, ScanResult, ScannedFile};
+use crate::progress::{
+    write_stderr_json, ProgressMessage, ProgressReporter, ProgressSink, ScanPhase,
+};
+use crate::rules::{FileFilter, FilterReason, PathFilter};
 
 /// Progress callback type
+#[deprecated(note = "implement `ProgressSink` and use `scan_full_with_sink` instead")]
 pub type ProgressCallback = Box<dyn Fn(&ScanProgress) + Send + Sync>;
 
 /// Scan progress information
@@ -37,23 +41,17 @@ pub struct ScanProgress {
     pub phase: ScanPhase,
     /// Estimated total files (for progress calculation)
     pub estimated_total: Option<u64>,
+    /// Bytes read for hashing so far
+    pub hashed_bytes: u64,
 }
 
 impl ScanProgress {
-    /// Output progress to stderr as JSON
+    /// Output progress to stderr as a JSON progress message
+    ///
+    /// The message has the same shape as those sent by `ProgressReporter`,
+    /// with sequence number 0 and `ts` equal to the elapsed time.
     pub fn print_to_stderr(&self) {
-        let json = serde_json::json!({
-            "_t": "p",
-            "f": self.scanned_files,
-            "d": self.scanned_dirs,
-            "v": self.video_count,
-            "i": self.image_count,
-            "a": self.audio_count,
-            "dir": self.current_dir,
-            "ms": self.elapsed_ms
-        });
-        eprintln!("{}", json);
-        std::io::stderr().flush().ok();
+        write_stderr_json(&ProgressMessage::from_progress(0, self.elapsed_ms, self));
     }
 
     /// Calculate estimated remaining time in milliseconds
@@ -74,18 +72,110 @@ impl ScanProgress {
     }
 }
 
+/// Cancellation flag shared between a running scan and the code stopping it
+///
+/// Clones share the same flag. A cancelled scan stops at the next file (or
+/// hash chunk) and returns the partial result with `cancelled` set.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Create a token that is not cancelled
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask the scan using this token to stop
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Check if cancellation was requested
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
 /// Perform a full scan of the configured directories
 pub fn scan_full(config: &ScanConfig) -> ScanResult {
-    scan_internal(config, None, config.show_progress)
+    scan_full_with_progress(config, config.show_progress)
 }
 
 /// Perform a full scan with progress callback
 pub fn scan_full_with_progress(config: &ScanConfig, show_progress: bool) -> ScanResult {
-    scan_internal(config, None, show_progress)
+    let reporter = ProgressReporter::from_config(config, show_progress);
+    scan_internal(config, None, None, None, &reporter, None)
 }
 
 /// Perform an incremental scan using database for comparison
 pub fn scan_incremental(config: &ScanConfig, db: &mut ScanDatabase) -> ScanResult {
+    let reporter = ProgressReporter::from_config(config, config.show_progress);
+    scan_incremental_with(config, db, &reporter, None)
+}
+
+/// Perform a full scan that stops early once `cancel` is cancelled
+pub fn scan_full_with_cancel(config: &ScanConfig, cancel: &CancelToken) -> ScanResult {
+    let reporter = ProgressReporter::from_config(config, config.show_progress);
+    scan_internal(config, None, None, None, &reporter, Some(cancel))
+}
+
+/// Perform an incremental scan that stops early once `cancel` is cancelled
+///
+/// Files processed before cancellation are stored; no files are marked deleted.
+pub fn scan_incremental_with_cancel(
+    config: &ScanConfig,
+    db: &mut ScanDatabase,
+    cancel: &CancelToken,
+) -> ScanResult {
+    let reporter = ProgressReporter::from_config(config, config.show_progress);
+    scan_incremental_with(config, db, &reporter, Some(cancel))
+}
+
+/// Perform a full scan, sending progress events to `sink`
+///
+/// Events are sent regardless of `config.show_progress`, at most once per
+/// `config.progress_interval_ms` for progress updates. The scan stops early
+/// once `cancel` is cancelled.
+pub fn scan_full_with_sink(
+    config: &ScanConfig,
+    sink: impl ProgressSink + Send,
+    cancel: Option<&CancelToken>,
+) -> ScanResult {
+    let reporter = ProgressReporter::with_sink(true, config.progress_interval_ms, sink);
+    scan_internal(config, None, None, None, &reporter, cancel)
+}
+
+/// Perform an incremental scan, sending progress events to `sink`
+///
+/// The scan stops early once `cancel` is cancelled.
+pub fn scan_incremental_with_sink(
+    config: &ScanConfig,
+    db: &mut ScanDatabase,
+    sink: impl ProgressSink + Send,
+    cancel: Option<&CancelToken>,
+) -> ScanResult {
+    let reporter = ProgressReporter::with_sink(true, config.progress_interval_ms, sink);
+    scan_incremental_with(config, db, &reporter, cancel)
+}
+
+/// Perform a full scan with an explicit reporter, stopping early once `cancel` is set
+pub(crate) fn scan_full_with(
+    config: &ScanConfig,
+    reporter: &ProgressReporter,
+    cancel: Option<&CancelToken>,
+) -> ScanResult {
+    scan_internal(config, None, None, None, reporter, cancel)
+}
+
+/// Perform an incremental scan with an explicit reporter, stopping early once `cancel` is set
+///
+/// A cancelled scan still stores the files it processed, but reports no deletions.
+pub(crate) fn scan_incremental_with(
+    config: &ScanConfig,
+    db: &mut ScanDatabase,
+    reporter: &ProgressReporter,
+    cancel: Option<&CancelToken>,
+) -> ScanResult {
     // Load existing file index from database
     let file_index = match db.load_file_index() {
         Ok(index) => {
@@ -100,7 +190,7 @@ pub fn scan_incremental(config: &ScanConfig, db: &mut ScanDatabase) -> ScanResul
         }
         Err(e) => {
             log::error!("Failed to load file index: {}", e);
-            return scan_full(config);
+            return scan_internal(config, None, None, None, reporter, cancel);
         }
     };
 
@@ -109,7 +199,45 @@ pub fn scan_incremental(config: &ScanConfig, db: &mut ScanDatabase) -> ScanResul
         log::info!("Database is empty, performing full scan");
     }
 
-    let result = scan_internal(config, Some(&file_index), config.show_progress);
+    let dir_index = match db.load_directory_index() {
+        Ok(index) => index,
+        Err(e) => {
+            log::warn!("Failed to load directory index: {}", e);
+            HashMap::new()
+        }
+    };
+
+    // Deleted files a new file can be restored from (matched by hash and size)
+    let deleted_index = if config.compute_hash {
+        match db.load_deleted_hash_index() {
+            Ok(index) => Some(index),
+            Err(e) => {
+                log::warn!("Failed to load deleted files: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let result = scan_internal(
+        config,
+        Some(&file_index),
+        Some(&dir_index),
+        deleted_index,
+        reporter,
+        cancel,
+    );
+
+    if result.restored_files > 0 {
+        log::info!(
+            "Restoring {} files from deleted_files table",
+            result.restored_files
+        );
+        if let Err(e) = db.remove_restored(&result.files) {
+            log::error!("Failed to remove restored files: {}", e);
+        }
+    }
 
     // Update database with all scanned files (new + modified)
     if !result.files.is_empty() {
@@ -119,6 +247,14 @@ pub fn scan_incremental(config: &ScanConfig, db: &mut ScanDatabase) -> ScanResul
         }
     }
 
+    // Record listed directories for the mtime shortcut and drop vanished ones
+    if let Err(e) = db.upsert_directories(&result.directories) {
+        log::error!("Failed to update directories: {}", e);
+    }
+    if let Err(e) = db.delete_directories(&result.stale_dirs) {
+        log::error!("Failed to remove stale directories: {}", e);
+    }
+
     // Delete removed files from database (move to deleted_files table first)
     if !result.deleted_paths.is_empty() {
         log::info!(
@@ -136,8 +272,11 @@ pub fn scan_incremental(config: &ScanConfig, db: &mut ScanDatabase) -> ScanResul
 /// Internal scan implementation
 fn scan_internal(
     config: &ScanConfig,
-    file_index: Option<&std::collections::HashMap<String, FileRecord>>,
-    show_progress: bool,
+    file_index: Option<&HashMap<String, FileRecord>>,
+    dir_index: Option<&HashMap<String, DirectoryRecord>>,
+    deleted_index: Option<HashMap<(String, u64), Vec<String>>>,
+    progress_reporter: &ProgressReporter,
+    cancel: Option<&CancelToken>,
 ) -> ScanResult {
     let start = Instant::now();
     let total_files = AtomicU64::new(0);
@@ -148,165 +287,447 @@ fn scan_internal(
     let video_count = AtomicU64::new(0);
     let image_count = AtomicU64::new(0);
     let audio_count = AtomicU64::new(0);
+    let hard_links = AtomicU64::new(0);
     let mut errors = Vec::new();
     let mut files = Vec::new();
     let mut seen_paths: HashSet<String> = HashSet::new();
 
-    // Create progress reporter
-    let progress_reporter = ProgressReporter::new(show_progress, config.progress_interval_ms);
+    let mut cancelled = false;
 
     // Report scan start
     progress_reporter.report_start(config);
 
     // Progress tracking
     let mut current_dir = String::new();
+    let mut hashed_bytes = 0u64;
+    // Incremental scans expect roughly as many files as the index holds
+    let estimated_total = file_index
+        .filter(|index| !index.is_empty())
+        .map(|index| index.len() as u64);
+    let mut filtered = FilterCounts::default();
+    let mut visited_dirs: HashSet<FileId> = HashSet::new();
+    // Files seen when following links
+    let mut seen_targets: HashSet<FileId> = HashSet::new();
+    let mut linked_ids: HashSet<FileId> = HashSet::new();
+
+    let now = unix_now();
+    let max_depth = config.effective_max_depth();
+
+    // Directory mtime shortcut: listed directories are recorded in incremental
+    // mode, and unchanged ones are skipped when the shortcut is enabled
+    let track_dirs = file_index.is_some() && dir_index.is_some();
+    let shortcut = dir_index.filter(|_| track_dirs && config.dir_shortcut);
+    let files_by_dir = group_by_parent(file_index.filter(|_| shortcut.is_some()));
+    let child_dirs = group_by_parent(shortcut);
+    let mut listed: HashMap<PathBuf, (i64, u64)> = HashMap::new();
+    let mut seen_dirs: HashSet<String> = HashSet::new();
+    let shortcut_dirs = AtomicU64::new(0);
 
-    for root in &config.roots {
-        if !root.exists() {
+    // Size/mtime/name filters; relative times are resolved once against the scan start
+    let file_filter = match FileFilter::new(config, now) {
+        Ok(f) => f,
+        Err(error) => {
+            progress_reporter.report_error(&error);
+            let result = ScanResult {
+                errors: vec![error],
+                duration_ms: start.elapsed().as_millis() as u64,
+                ..Default::default()
+            };
+            progress_reporter.report_done(&result);
+            return result;
+        }
+    };
+
+    // Links met while following them are walked in a second pass over the roots,
+    // after every real path, so the real copy of a file or directory is the one
+    // indexed whatever the walk order
+    let mut deferred: Vec<Vec<(PathBuf, usize)>> = vec![Vec::new(); config.roots.len()];
+    let passes = [false, true].into_iter().flat_map(|via_links| {
+        config
+            .roots
+            .iter()
+            .enumerate()
+            .map(move |(index, root)| (index, root, via_links))
+    });
+
+    for (root_index, root, via_links) in passes {
+        if cancelled {
+            break;
+        }
+        if via_links && deferred[root_index].is_empty() {
+            continue;
+        }
+        if !via_links && !root.exists() {
             let error = ScanError::not_found(root.clone());
             progress_reporter.report_error(&error);
             errors.push(error);
             continue;
         }
 
-        let walker = WalkDir::new(root)
-            .max_depth(config.effective_max_depth())
-            .follow_links(false)
-            .into_iter();
+        // Compile include/exclude rules for this root
+        let filter = match PathFilter::new(config, root) {
+            Ok(f) => f,
+            Err(e) => {
+                let error = ScanError::config_error(Some(root.clone()), e.message);
+                progress_reporter.report_error(&error);
+                errors.push(error);
+                continue;
+            }
+        };
 
-        for entry in walker {
-            match entry {
-                Ok(entry) => {
-                    let path = entry.path();
+        // Directories are walked from the root and from each child of a directory
+        // skipped by the mtime shortcut (with its depth below the root)
+        let mut pending: Vec<(PathBuf, usize)> = if via_links {
+            let mut links = std::mem::take(&mut deferred[root_index]);
+            links.reverse();
+            links
+        } else {
+            vec![(root.clone(), 0)]
+        };
+        while let Some((walk_root, base_depth)) = pending.pop() {
+            if cancelled {
+                break;
+            }
+            let mut skipped: Vec<(PathBuf, usize)> = Vec::new();
+            let mut failed: Vec<PathBuf> = Vec::new();
+            let mut links: Vec<(PathBuf, usize)> = Vec::new();
 
-                    // Check if directory should be ignored
-                    if entry.file_type().is_dir() {
-                        if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                            if config.should_ignore_dir(name) {
-                                continue;
+            // Ignored and excluded directories are pruned here so they are never descended into.
+            // The root itself (depth 0) is always scanned, even if it is hidden.
+            // When following links, each directory (by device/inode) is walked only once,
+            // which also breaks symlink cycles.
+            let walker = walk_dir(&walk_root, max_depth - base_depth, config)
+                .into_iter()
+                .filter_entry(|entry| {
+                    let is_dir = entry.file_type().is_dir();
+                    if entry.depth() > 0 {
+                        if let Some(counts) = entry.path().parent().and_then(|p| listed.get_mut(p))
+                        {
+                            counts.1 += 1;
+                        }
+                    }
+                    let metadata = if is_dir && (config.follow_symlinks || track_dirs) {
+                        entry.metadata().ok()
+                    } else {
+                        None
+                    };
+                    if entry.depth() > 0 {
+                        let ignored = entry.file_name().to_str().is_some_and(|name| {
+                            if is_dir {
+                                config.should_ignore_dir(name)
+                            } else {
+                                config.should_ignore_file(name)
                             }
+                        });
+                        if ignored || filter.is_excluded(entry.path(), is_dir) {
+                            return false;
+                        }
+                        if config.follow_symlinks && !via_links && entry.path_is_symlink() {
+                            links.push((entry.path().to_path_buf(), base_depth + entry.depth()));
+                            return false;
                         }
-                        total_dirs.fetch_add(1, Ordering::Relaxed);
-                        current_dir = path.to_string_lossy().to_string();
-                        continue;
                     }
-
-                    // Process files
-                    if entry.file_type().is_file() {
-                        // Normalize path separators for cross-platform consistency
-                        let path_str = normalize_path(path);
-                        seen_paths.insert(path_str.clone());
-
-                        // Check if file changed (incremental mode)
-                        if let Some(index) = file_index {
-                            if let Some(record) = index.get(&path_str) {
-                                // Quick check: size + mtime
-                                let metadata = match std::fs::metadata(path) {
-                                    Ok(m) => m,
-                                    Err(_) => continue,
-                                };
-
-                                let current_size = metadata.len();
-                                let current_mtime = metadata
-                                    .modified()
-                                    .ok()
-                                    .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
-                                    .map(|d| d.as_secs() as i64)
-                                    .unwrap_or(0);
-
-                                // File unchanged - skip detailed processing
-                                if record.size == current_size && record.mtime == current_mtime {
-                                    total_files.fetch_add(1, Ordering::Relaxed);
-                                    unchanged_files.fetch_add(1, Ordering::Relaxed);
-                                    continue;
-                                }
-
-                                // File modified - process and mark
+                    // Marked only once the directory passed the rules, so a pruned
+                    // alias does not hide an allowed path to the same directory
+                    if config.follow_symlinks {
+                        if let Some(id) = metadata.as_ref().and_then(FileId::from_metadata) {
+                            if !visited_dirs.insert(id) {
                                 log::debug!(
-                                    "File modified: {} (size: {} -> {}, mtime: {} -> {})",
-                                    path_str,
-                                    record.size,
-                                    current_size,
-                                    record.mtime,
-                                    current_mtime
+                                    "Skipping already visited directory: {:?}",
+                                    entry.path()
                                 );
-                                if let Some(scanned) = process_file(path, config) {
-                                    let scanned = scanned.with_status(FileStatus::Modified);
-                                    update_media_counts(
-                                        &scanned.media_type,
-                                        &video_count,
-                                        &image_count,
-                                        &audio_count,
-                                    );
-                                    files.push(scanned);
-                                    total_files.fetch_add(1, Ordering::Relaxed);
-                                    modified_files.fetch_add(1, Ordering::Relaxed);
-                                }
-                                continue;
-                            } else {
-                                // Log first few new files for debugging
-                                if new_files.load(Ordering::Relaxed) < 3 {
-                                    log::debug!("New file (not in DB): {}", path_str);
-                                }
+                                return false;
+                            }
+                        }
+                    }
+
+                    // Unchanged directories are not listed; their files come from the index
+                    if let Some(mtime) = metadata.as_ref().and_then(mtime_ns) {
+                        let depth = base_depth + entry.depth();
+                        let record =
+                            shortcut.and_then(|index| index.get(&normalize_path(entry.path())));
+                        if can_skip_dir(
+                            record,
+                            entry.path(),
+                            mtime,
+                            now,
+                            config.dir_verify_interval_secs,
+                        ) {
+                            skipped.push((entry.path().to_path_buf(), depth));
+                            return false;
+                        }
+                        if track_dirs && depth < max_depth {
+                            listed.insert(entry.path().to_path_buf(), (mtime, 0));
+                        }
+                    }
+                    true
+                });
+
+            for entry in walker {
+                if is_cancelled(cancel) {
+                    log::info!("Scan cancelled");
+                    cancelled = true;
+                    break;
+                }
+
+                // Regular files, plus symlinks when they are recorded as entries
+                let (path, is_link) = match entry {
+                    Ok(entry) => {
+                        if entry.file_type().is_dir() {
+                            total_dirs.fetch_add(1, Ordering::Relaxed);
+                            current_dir = entry.path().to_string_lossy().to_string();
+                            if track_dirs {
+                                seen_dirs.insert(normalize_path(entry.path()));
                             }
+                            continue;
+                        }
+
+                        let is_link = entry.path_is_symlink();
+                        let is_file = entry.file_type().is_file()
+                            || (config.record_symlinks && entry.file_type().is_symlink());
+                        if !is_file {
+                            continue;
+                        }
+                        (entry.into_path(), is_link)
+                    }
+                    Err(e) => {
+                        // Directories already walked are pruned by the filter, so a loop
+                        // only reaches here as walkdir's own ancestor check
+                        if e.loop_ancestor().is_some() {
+                            log::debug!("Skipping symlink loop: {}", e);
+                            continue;
                         }
 
-                        // New file or full scan mode
-                        if let Some(scanned) = process_file(path, config) {
-                            let scanned = if file_index.is_some() {
-                                scanned.with_status(FileStatus::New)
+                        // Broken symlinks fail to resolve when links are followed
+                        if let Some(path) =
+                            e.path().filter(|p| config.record_symlinks && is_symlink(p))
+                        {
+                            (path.to_path_buf(), true)
+                        } else {
+                            let path = e.path().map(|p| p.to_path_buf());
+                            let kind = if e.io_error().map(|e| e.kind())
+                                == Some(std::io::ErrorKind::PermissionDenied)
+                            {
+                                ScanErrorKind::PermissionDenied
                             } else {
-                                scanned
+                                ScanErrorKind::IoError
                             };
-                            update_media_counts(
-                                &scanned.media_type,
-                                &video_count,
-                                &image_count,
-                                &audio_count,
-                            );
-                            files.push(scanned);
+                            if let Some(ref path) = path {
+                                failed.push(path.clone());
+                            }
+                            let error = ScanError::new(kind, path, e.to_string());
+                            progress_reporter.report_error(&error);
+                            errors.push(error);
+                            continue;
+                        }
+                    }
+                };
+                let path = path.as_path();
+
+                if !filter.is_included(path) {
+                    continue;
+                }
+
+                // Files reached again through a link are indexed once, under the real
+                // path walked in the first pass; hard links without a symlink involved
+                // are still recorded and counted below
+                if config.follow_symlinks {
+                    let id = std::fs::metadata(path)
+                        .ok()
+                        .and_then(|m| FileId::from_metadata(&m));
+                    if id.is_some_and(|id| !seen_targets.insert(id)) && (is_link || via_links) {
+                        continue;
+                    }
+                }
+
+                // Normalize path separators for cross-platform consistency
+                let path_str = normalize_path(path);
+                seen_paths.insert(path_str.clone());
+
+                // Check if file changed (incremental mode)
+                if let Some(index) = file_index {
+                    if let Some(record) = index.get(&path_str) {
+                        // Quick check: size + mtime
+                        let metadata = match std::fs::metadata(path) {
+                            Ok(m) => m,
+                            Err(_) => continue,
+                        };
+
+                        let current_size = metadata.len();
+                        let current_mtime = metadata
+                            .modified()
+                            .ok()
+                            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                            .map(|d| d.as_secs() as i64)
+                            .unwrap_or(0);
+
+                        // File unchanged - skip detailed processing
+                        if is_unchanged(record, &metadata, config.change_detection) {
+                            let id = FileId::from_metadata(&metadata);
+                            if is_extra_hard_link(
+                                &mut linked_ids,
+                                id,
+                                FileId::link_count(&metadata),
+                            ) {
+                                hard_links.fetch_add(1, Ordering::Relaxed);
+                                continue;
+                            }
                             total_files.fetch_add(1, Ordering::Relaxed);
-                            if file_index.is_some() {
-                                new_files.fetch_add(1, Ordering::Relaxed);
+                            unchanged_files.fetch_add(1, Ordering::Relaxed);
+                            continue;
+                        }
+
+                        // File modified - process and mark
+                        log::debug!(
+                            "File modified: {} (size: {} -> {}, mtime: {} -> {})",
+                            path_str,
+                            record.size,
+                            current_size,
+                            record.mtime,
+                            current_mtime
+                        );
+                        let outcome = process_entry(path, is_link, config, &file_filter, cancel);
+                        // A file whose hashing was interrupted is left for the next scan
+                        if is_cancelled(cancel) {
+                            log::info!("Scan cancelled");
+                            cancelled = true;
+                            break;
+                        }
+                        match outcome {
+                            FileOutcome::Scanned(scanned, links) => {
+                                let scanned = (*scanned).with_status(FileStatus::Modified);
+                                hashed_bytes += hashed_len(&scanned);
+                                if is_extra_hard_link(&mut linked_ids, scanned.file_id(), links) {
+                                    hard_links.fetch_add(1, Ordering::Relaxed);
+                                    files.push(scanned);
+                                    continue;
+                                }
+                                update_media_counts(
+                                    &scanned.media_type,
+                                    &video_count,
+                                    &image_count,
+                                    &audio_count,
+                                );
+                                files.push(scanned);
+                                total_files.fetch_add(1, Ordering::Relaxed);
+                                modified_files.fetch_add(1, Ordering::Relaxed);
+                            }
+                            FileOutcome::Filtered(reason) => {
+                                count_filtered(&mut filtered, reason);
                             }
+                            FileOutcome::Skipped => {}
                         }
+                        continue;
+                    } else {
+                        // Log first few new files for debugging
+                        if new_files.load(Ordering::Relaxed) < 3 {
+                            log::debug!("New file (not in DB): {}", path_str);
+                        }
+                    }
+                }
 
-                        // Report progress periodically using ProgressReporter
-                        let progress = ScanProgress {
-                            scanned_files: total_files.load(Ordering::Relaxed),
-                            scanned_dirs: total_dirs.load(Ordering::Relaxed),
-                            video_count: video_count.load(Ordering::Relaxed),
-                            image_count: image_count.load(Ordering::Relaxed),
-                            audio_count: audio_count.load(Ordering::Relaxed),
-                            current_dir: current_dir.clone(),
-                            elapsed_ms: start.elapsed().as_millis() as u64,
-                            phase: ScanPhase::Scan,
-                            estimated_total: None,
+                // New file or full scan mode
+                let outcome = process_entry(path, is_link, config, &file_filter, cancel);
+                if is_cancelled(cancel) {
+                    log::info!("Scan cancelled");
+                    cancelled = true;
+                    break;
+                }
+                match outcome {
+                    FileOutcome::Scanned(scanned, links) => {
+                        let scanned = if file_index.is_some() {
+                            (*scanned).with_status(FileStatus::New)
+                        } else {
+                            *scanned
                         };
-                        progress_reporter.report_progress(&progress);
+                        hashed_bytes += hashed_len(&scanned);
+                        // Extra hard links are indexed under their own path but counted once
+                        if is_extra_hard_link(&mut linked_ids, scanned.file_id(), links) {
+                            hard_links.fetch_add(1, Ordering::Relaxed);
+                            files.push(scanned);
+                            continue;
+                        }
+                        update_media_counts(
+                            &scanned.media_type,
+                            &video_count,
+                            &image_count,
+                            &audio_count,
+                        );
+                        files.push(scanned);
+                        total_files.fetch_add(1, Ordering::Relaxed);
+                        if file_index.is_some() {
+                            new_files.fetch_add(1, Ordering::Relaxed);
+                        }
                     }
+                    FileOutcome::Filtered(reason) => count_filtered(&mut filtered, reason),
+                    FileOutcome::Skipped => {}
                 }
-                Err(e) => {
-                    let path = e.path().map(|p| p.to_path_buf());
-                    let kind = if e.io_error().map(|e| e.kind())
-                        == Some(std::io::ErrorKind::PermissionDenied)
-                    {
-                        ScanErrorKind::PermissionDenied
-                    } else {
-                        ScanErrorKind::IoError
-                    };
-                    let error = ScanError::new(kind, path, e.to_string());
-                    progress_reporter.report_error(&error);
-                    errors.push(error);
+
+                // Report progress periodically using ProgressReporter
+                let progress = ScanProgress {
+                    scanned_files: total_files.load(Ordering::Relaxed),
+                    scanned_dirs: total_dirs.load(Ordering::Relaxed),
+                    video_count: video_count.load(Ordering::Relaxed),
+                    image_count: image_count.load(Ordering::Relaxed),
+                    audio_count: audio_count.load(Ordering::Relaxed),
+                    current_dir: current_dir.clone(),
+                    elapsed_ms: start.elapsed().as_millis() as u64,
+                    phase: ScanPhase::Scan,
+                    estimated_total,
+                    hashed_bytes,
+                };
+                progress_reporter.report_progress(&progress);
+            }
+            deferred[root_index].extend(links);
+            // Directories that could not be read must be listed again next time
+            for path in failed {
+                listed.remove(&path);
+            }
+
+            for (dir, depth) in skipped {
+                let dir_str = normalize_path(&dir);
+                log::debug!("Directory unchanged, using index: {}", dir_str);
+                total_dirs.fetch_add(1, Ordering::Relaxed);
+                shortcut_dirs.fetch_add(1, Ordering::Relaxed);
+                seen_dirs.insert(dir_str.clone());
+
+                for record in files_by_dir.get(dir_str.as_str()).into_iter().flatten() {
+                    seen_paths.insert(record.path.clone());
+                    let id = record
+                        .dev
+                        .zip(record.inode)
+                        .map(|(dev, ino)| FileId { dev, ino });
+                    if id.is_some_and(|id| !linked_ids.insert(id)) {
+                        hard_links.fetch_add(1, Ordering::Relaxed);
+                        continue;
+                    }
+                    total_files.fetch_add(1, Ordering::Relaxed);
+                    unchanged_files.fetch_add(1, Ordering::Relaxed);
+                }
+
+                if depth >= max_depth {
+                    continue;
+                }
+                for child in child_dirs.get(dir_str.as_str()).into_iter().flatten() {
+                    let child = PathBuf::from(&child.path);
+                    let ignored = child
+                        .file_name()
+                        .and_then(|n| n.to_str())
+                        .is_some_and(|name| config.should_ignore_dir(name));
+                    if !ignored && !filter.is_excluded(&child, true) {
+                        pending.push((child, depth + 1));
+                    }
                 }
             }
         }
     }
 
-    // Find deleted files (only in incremental mode)
+    progress_reporter.report_phase(ScanPhase::Process);
+
+    // Find deleted files (only in incremental mode, and only after a complete walk)
     let mut deleted_paths = Vec::new();
     let deleted_count;
-    if let Some(index) = file_index {
+    if let Some(index) = file_index.filter(|_| !cancelled) {
         for path in index.keys() {
             if !seen_paths.contains(path) {
                 deleted_paths.push(path.clone());
@@ -317,28 +738,79 @@ fn scan_internal(
         deleted_count = 0;
     }
 
+    // Directories listed in full this scan, and stored ones no longer seen.
+    // A cancelled walk may have stopped inside a directory, so nothing is recorded.
+    let directories: Vec<DirectoryRecord> = listed
+        .into_iter()
+        .filter(|_| !cancelled)
+        .map(|(path, (mtime_ns, entry_count))| DirectoryRecord {
+            path: normalize_path(&path),
+            mtime_ns,
+            entry_count,
+            verified_at: now,
+        })
+        .collect();
+    let stale_dirs: Vec<String> = match dir_index {
+        Some(index) if track_dirs && !cancelled => index
+            .keys()
+            .filter(|path| !seen_dirs.contains(*path))
+            .cloned()
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    // New files with the hash and size of a deleted one are restored
+    let mut restored_count = 0;
+    if let Some(mut deleted) = deleted_index {
+        for file in files.iter_mut().filter(|f| f.status == FileStatus::New) {
+            let Some(ref hash) = file.hash else {
+                continue;
+            };
+            let old_path = deleted
+                .get_mut(&(hash.clone(), file.size))
+                .and_then(Vec::pop);
+            if let Some(old_path) = old_path {
+                file.status = FileStatus::Restored;
+                file.old_path = Some(old_path);
+                restored_count += 1;
+            }
+        }
+    }
+
     let duration = start.elapsed();
 
     let total = total_files.load(Ordering::Relaxed);
     let new_count = if file_index.is_some() {
-        new_files.load(Ordering::Relaxed)
+        new_files
+            .load(Ordering::Relaxed)
+            .saturating_sub(restored_count)
     } else {
         total
     };
 
     // Build the scan result
-    let result = ScanResult {
+    let mut result = ScanResult {
         total_files: total,
         total_dirs: total_dirs.load(Ordering::Relaxed),
         new_files: new_count,
         modified_files: modified_files.load(Ordering::Relaxed),
         unchanged_files: unchanged_files.load(Ordering::Relaxed),
         deleted_files: deleted_count,
+        moved_files: 0,
+        restored_files: restored_count,
+        filtered,
+        hard_links: hard_links.load(Ordering::Relaxed),
+        shortcut_dirs: shortcut_dirs.load(Ordering::Relaxed),
         files,
+        directories,
+        stale_dirs,
         deleted_paths,
         errors,
         duration_ms: duration.as_millis() as u64,
+        cancelled,
     };
+    // Apply the configured output_order to files and deleted paths
+    result.sort(config.output_order);
 
     // Report scan completion
     progress_reporter.report_done(&result);
@@ -361,8 +833,124 @@ fn update_media_counts(
     };
 }
 
-/// Process a single file and return ScannedFile if it matches the filter
-fn process_file(path: &Path, config: &ScanConfig) -> Option<ScannedFile> {
+/// Outcome of processing a single file
+pub(crate) enum FileOutcome {
+    /// File matched all filters (with its hard link count)
+    Scanned(Box<ScannedFile>, u64),
+    /// File was skipped by a size, mtime or name filter
+    Filtered(FilterReason),
+    /// File is not a media file or could not be read
+    Skipped,
+}
+
+/// Record a filtered file in the per-reason counts
+fn count_filtered(counts: &mut FilterCounts, reason: FilterReason) {
+    match reason {
+        FilterReason::Size => counts.size += 1,
+        FilterReason::Mtime => counts.mtime += 1,
+        FilterReason::Name => counts.name += 1,
+    }
+}
+
+/// Check if an optional token has been cancelled
+fn is_cancelled(cancel: Option<&CancelToken>) -> bool {
+    cancel.is_some_and(CancelToken::is_cancelled)
+}
+
+/// Current time as a Unix timestamp
+pub(crate) fn unix_now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Process a walked entry, recording symlinks as such when configured
+pub(crate) fn process_entry(
+    path: &Path,
+    is_link: bool,
+    config: &ScanConfig,
+    filter: &FileFilter,
+    cancel: Option<&CancelToken>,
+) -> FileOutcome {
+    if is_link && config.record_symlinks {
+        process_symlink(path, config, filter, cancel)
+    } else {
+        process_file(path, config, filter, cancel)
+    }
+}
+
+/// Check if a path is a symbolic link (without following it)
+pub(crate) fn is_symlink(path: &Path) -> bool {
+    std::fs::symlink_metadata(path)
+        .map(|m| m.file_type().is_symlink())
+        .unwrap_or(false)
+}
+
+/// Process a symbolic link, recording its target and whether it is broken
+///
+/// Links to files are scanned like the target file; broken links use the
+/// link's own metadata and are never hashed. Links to directories are skipped.
+fn process_symlink(
+    path: &Path,
+    config: &ScanConfig,
+    filter: &FileFilter,
+    cancel: Option<&CancelToken>,
+) -> FileOutcome {
+    let target = match std::fs::read_link(path) {
+        Ok(t) => normalize_path(&t),
+        Err(_) => return FileOutcome::Skipped,
+    };
+
+    match std::fs::metadata(path) {
+        Ok(metadata) if metadata.is_file() => match process_file(path, config, filter, cancel) {
+            FileOutcome::Scanned(scanned, links) => {
+                FileOutcome::Scanned(Box::new(scanned.with_link_target(target, false)), links)
+            }
+            other => other,
+        },
+        Ok(_) => FileOutcome::Skipped,
+        Err(_) => {
+            let extension = path
+                .extension()
+                .and_then(|e| e.to_str())
+                .map(|e| e.to_lowercase())
+                .unwrap_or_default();
+            if !config.should_include_extension(&extension) {
+                return FileOutcome::Skipped;
+            }
+            let metadata = match std::fs::symlink_metadata(path) {
+                Ok(m) => m,
+                Err(_) => return FileOutcome::Skipped,
+            };
+            let name = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("")
+                .to_string();
+            let mtime = metadata
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or(0);
+            if let Some(reason) = filter.check(&name, 0, mtime) {
+                return FileOutcome::Filtered(reason);
+            }
+            let scanned = ScannedFile::new(path.to_path_buf(), name, 0, mtime, mtime, extension)
+                .with_link_target(target, true);
+            FileOutcome::Scanned(Box::new(scanned), 1)
+        }
+    }
+}
+
+/// Process a single file, applying the extension and file filters before hashing
+fn process_file(
+    path: &Path,
+    config: &ScanConfig,
+    filter: &FileFilter,
+    cancel: Option<&CancelToken>,
+) -> FileOutcome {
     // Get file extension
     let extension = path
         .extension()
@@ -372,13 +960,13 @@ fn process_file(path: &Path, config: &ScanConfig) -> Option<ScannedFile> {
 
     // Check if extension is in whitelist
     if !config.should_include_extension(&extension) {
-        return None;
+        return FileOutcome::Skipped;
     }
 
     // Get file metadata
     let metadata = match std::fs::metadata(path) {
         Ok(m) => m,
-        Err(_) => return None,
+        Err(_) => return FileOutcome::Skipped,
     };
 
     // Get file name
@@ -403,6 +991,11 @@ fn process_file(path: &Path, config: &ScanConfig) -> Option<ScannedFile> {
         .map(|d| d.as_secs() as i64)
         .unwrap_or(mtime);
 
+    // Apply size/mtime/name filters before any hashing work
+    if let Some(reason) = filter.check(&name, metadata.len(), mtime) {
+        return FileOutcome::Filtered(reason);
+    }
+
     let mut scanned = ScannedFile::new(
         path.to_path_buf(),
         name,
@@ -411,27 +1004,183 @@ fn process_file(path: &Path, config: &ScanConfig) -> Option<ScannedFile> {
         ctime,
         extension,
     );
+    if let Some(id) = FileId::from_metadata(&metadata) {
+        scanned = scanned.with_file_id(id);
+    }
+    scanned = scanned.with_precise_times(mtime_ns(&metadata), change_ns(&metadata));
 
     // Compute hash if enabled
     if config.compute_hash {
-        if let Some((hash, is_partial)) = compute_file_hash(path, config.large_file_threshold) {
+        if let Some((hash, is_partial)) =
+            compute_file_hash(path, config.large_file_threshold, cancel)
+        {
             scanned = scanned.with_hash(hash, is_partial);
         }
     }
 
-    Some(scanned)
+    FileOutcome::Scanned(Box::new(scanned), FileId::link_count(&metadata))
+}
+
+/// Group stored paths by their parent directory
+fn group_by_parent<T>(index: Option<&HashMap<String, T>>) -> HashMap<&str, Vec<&T>> {
+    let mut groups: HashMap<&str, Vec<&T>> = HashMap::new();
+    for (path, value) in index.into_iter().flatten() {
+        if let Some((parent, _)) = path.rsplit_once('/') {
+            groups.entry(parent).or_default().push(value);
+        }
+    }
+    groups
+}
+
+/// Walker for `root` with the link and filesystem options of `config`
+///
+/// When following links, entries are sorted by name so the same link wins on
+/// every machine.
+fn walk_dir(root: &Path, max_depth: usize, config: &ScanConfig) -> WalkDir {
+    let walk = WalkDir::new(root)
+        .max_depth(max_depth)
+        .follow_links(config.follow_symlinks)
+        .same_file_system(config.one_file_system);
+    if config.follow_symlinks {
+        walk.sort_by_file_name()
+    } else {
+        walk
+    }
+}
+
+/// Check if a directory can be skipped by the mtime shortcut
+///
+/// Its mtime and entry count must match the stored ones, and its last full
+/// listing must be within the verification interval. The entry count catches
+/// listing changes that leave a coarse-grained mtime untouched; it is only
+/// read once the cheaper checks pass.
+fn can_skip_dir(
+    record: Option<&DirectoryRecord>,
+    path: &Path,
+    mtime_ns: i64,
+    now: i64,
+    interval: u64,
+) -> bool {
+    record.is_some_and(|r| {
+        r.mtime_ns == mtime_ns
+            && now.saturating_sub(r.verified_at) < interval as i64
+            && std::fs::read_dir(path).is_ok_and(|entries| entries.count() as u64 == r.entry_count)
+    })
+}
+
+/// Compare a stored record with the file's current metadata
+///
+/// Records written before strict fields were tracked count as changed in
+/// strict mode, so they are re-processed once and stored with the new fields.
+pub(crate) fn is_unchanged(
+    record: &FileRecord,
+    metadata: &std::fs::Metadata,
+    policy: ChangeDetection,
+) -> bool {
+    let mtime = mtime_ns(metadata)
+        .map(|ns| ns.div_euclid(1_000_000_000))
+        .unwrap_or(0);
+    if record.size != metadata.len() || record.mtime != mtime {
+        return false;
+    }
+    match policy {
+        ChangeDetection::Fast => true,
+        ChangeDetection::Strict => {
+            let id = FileId::from_metadata(metadata);
+            record.mtime_ns.is_some()
+                && record.mtime_ns == mtime_ns(metadata)
+                && record.change_ns == change_ns(metadata)
+                && record.inode == id.map(|id| id.ino)
+                && record.dev == id.map(|id| id.dev)
+        }
+    }
+}
+
+/// Modification time in nanoseconds since the Unix epoch
+fn mtime_ns(metadata: &std::fs::Metadata) -> Option<i64> {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos() as i64)
+}
+
+/// Inode change time (st_ctime) in nanoseconds (None on platforms without it)
+#[cfg(unix)]
+fn change_ns(metadata: &std::fs::Metadata) -> Option<i64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.ctime() * 1_000_000_000 + metadata.ctime_nsec())
+}
+
+#[cfg(not(unix))]
+fn change_ns(_metadata: &std::fs::Metadata) -> Option<i64> {
+    None
+}
+
+/// Check if a file is another hard link to a file already counted in this scan
+///
+/// Only files with more than one link are remembered, keeping the set small.
+fn is_extra_hard_link(seen: &mut HashSet<FileId>, id: Option<FileId>, links: u64) -> bool {
+    links > 1 && id.is_some_and(|id| !seen.insert(id))
 }
 
 /// Normalize path separators for cross-platform consistency
 /// Always uses forward slashes for storage and comparison
-fn normalize_path(path: &Path) -> String {
+pub(crate) fn normalize_path(path: &Path) -> String {
     path.to_string_lossy().replace('\\', "/")
 }
 
+/// Size of the chunks read when hashing, and of each end of a partial hash
+const HASH_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Number of bytes read to compute the hash of `file`
+fn hashed_len(file: &ScannedFile) -> u64 {
+    match (&file.hash, file.is_partial_hash) {
+        (None, _) => 0,
+        (Some(_), false) => file.size,
+        (Some(_), true) => file.size.min(2 * HASH_CHUNK_SIZE as u64),
+    }
+}
+
 /// Compute file hash (MD5)
 /// For large files, compute partial hash (first 1MB + last 1MB)
-fn compute_file_hash(path: &Path, large_file_threshold: u64) -> Option<(String, bool)> {
+/// Returns None if `cancel` is cancelled while reading.
+fn compute_file_hash(
+    path: &Path,
+    large_file_threshold: u64,
+    cancel: Option<&CancelToken>,
+) -> Option<(String, bool)> {
+    compute_file_hash_with(path, large_file_threshold, cancel, |_| {})
+}
+
+/// `compute_file_hash`, calling `on_read` with the size of every chunk read
+pub(crate) fn compute_file_hash_with(
+    path: &Path,
+    large_file_threshold: u64,
+    cancel: Option<&CancelToken>,
+    mut on_read: impl FnMut(usize),
+) -> Option<(String, bool)> {
     use md5::{Digest, Md5};
+
+    let mut hasher = Md5::new();
+    let partial = read_file_chunks(path, large_file_threshold, cancel, |chunk| {
+        hasher.update(chunk);
+        on_read(chunk.len());
+    })?;
+    Some((format!("{:x}", hasher.finalize()), partial))
+}
+
+/// Feed the content hashed by `compute_file_hash` to `on_chunk`
+///
+/// Files above `large_file_threshold` only yield their first and last 1MB.
+/// Returns whether the read was partial, or None if the file could not be
+/// read or `cancel` was cancelled.
+pub(crate) fn read_file_chunks(
+    path: &Path,
+    large_file_threshold: u64,
+    cancel: Option<&CancelToken>,
+    mut on_chunk: impl FnMut(&[u8]),
+) -> Option<bool> {
     use std::fs::File;
     use std::io::{Read, Seek, SeekFrom};
 
@@ -439,41 +1188,49 @@ fn compute_file_hash(path: &Path, large_file_threshold: u64) -> Option<(String,
     let metadata = file.metadata().ok()?;
     let file_size = metadata.len();
 
-    let mut hasher = Md5::new();
+    let chunk_size = HASH_CHUNK_SIZE;
+    let mut file = file;
+    let mut buffer = vec![0u8; chunk_size];
 
     if file_size <= large_file_threshold {
-        // Full hash for small files
-        let mut file = file;
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer).ok()?;
-        hasher.update(&buffer);
-        let result = hasher.finalize();
-        Some((format!("{:x}", result), false))
+        // Full hash for small files, read in chunks so cancellation is noticed
+        loop {
+            if is_cancelled(cancel) {
+                return None;
+            }
+            let bytes_read = match file.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(_) => return None,
+            };
+            on_chunk(&buffer[..bytes_read]);
+        }
+        Some(false)
     } else {
         // Partial hash for large files (first 1MB + last 1MB)
-        let chunk_size = 1024 * 1024; // 1MB
-        let mut file = file;
-        let mut buffer = vec![0u8; chunk_size];
 
         // Read first 1MB
         let bytes_read = file.read(&mut buffer).ok()?;
-        hasher.update(&buffer[..bytes_read]);
+        on_chunk(&buffer[..bytes_read]);
 
         // Read last 1MB
         if file_size > chunk_size as u64 {
             file.seek(SeekFrom::End(-(chunk_size as i64))).ok()?;
             let bytes_read = file.read(&mut buffer).ok()?;
-            hasher.update(&buffer[..bytes_read]);
+            on_chunk(&buffer[..bytes_read]);
         }
 
-        let result = hasher.finalize();
-        Some((format!("{:x}", result), true))
+        Some(true)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use crate::config::HiddenPolicy;
     use crate::models::MediaType;
+    use std::fs;
 
     #[test]
     fn test_media_type_detection() {
@@ -482,4 +1239,504 @@ mod tests {
         assert_eq!(MediaType::from_extension("mp3"), MediaType::Audio);
         assert_eq!(MediaType::from_extension("txt"), MediaType::Unknown);
     }
+
+    #[test]
+    fn test_excluded_subtree_is_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("Movies/Samples")).unwrap();
+        fs::write(root.join("Movies/movie.mp4"), b"movie").unwrap();
+        fs::write(root.join("Movies/Samples/sample.mp4"), b"sample").unwrap();
+
+        let config = ScanConfig::builder()
+            .add_root(root.to_path_buf())
+            .exclude("Samples/")
+            .build();
+        let result = scan_full(&config);
+
+        let names: Vec<&str> = result.files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["movie.mp4"]);
+        // root + Movies; the excluded directory is never entered
+        assert_eq!(result.total_dirs, 2);
+    }
+
+    #[test]
+    fn test_hidden_root_and_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join(".media");
+        fs::create_dir_all(root.join(".extras")).unwrap();
+        fs::write(root.join("a.mp4"), b"a").unwrap();
+        fs::write(root.join(".b.mp4"), b"b").unwrap();
+        fs::write(root.join(".extras/c.mp4"), b"c").unwrap();
+
+        let scan_names = |policy: HiddenPolicy| {
+            let config = ScanConfig::builder()
+                .add_root(root.clone())
+                .hidden(policy)
+                .build();
+            let mut names: Vec<String> = scan_full(&config)
+                .files
+                .into_iter()
+                .map(|f| f.name)
+                .collect();
+            names.sort();
+            names
+        };
+
+        assert_eq!(scan_names(HiddenPolicy::Dirs), vec![".b.mp4", "a.mp4"]);
+        assert_eq!(scan_names(HiddenPolicy::Files), vec!["a.mp4", "c.mp4"]);
+        assert_eq!(scan_names(HiddenPolicy::All), vec!["a.mp4"]);
+        assert_eq!(
+            scan_names(HiddenPolicy::None),
+            vec![".b.mp4", "a.mp4", "c.mp4"]
+        );
+    }
+
+    #[test]
+    fn test_file_filters_counted() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join("movie.mp4"), vec![0u8; 4096]).unwrap();
+        fs::write(root.join("thumb.jpg"), b"tiny").unwrap();
+        fs::write(root.join("._movie.mp4"), vec![0u8; 4096]).unwrap();
+
+        let config = ScanConfig::builder()
+            .add_root(root.to_path_buf())
+            .hidden(HiddenPolicy::None)
+            .min_size(1024)
+            .exclude_name("._*")
+            .build();
+        let result = scan_full(&config);
+
+        assert_eq!(result.total_files, 1);
+        assert_eq!(result.files[0].name, "movie.mp4");
+        assert_eq!(result.filtered.size, 1);
+        assert_eq!(result.filtered.name, 1);
+        assert_eq!(result.filtered.total(), 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_follow_symlinks_dedup_and_loops() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("library");
+        let real = dir.path().join("real");
+        fs::create_dir_all(&root).unwrap();
+        fs::create_dir_all(&real).unwrap();
+        fs::write(real.join("movie.mp4"), b"movie").unwrap();
+        // Two links to the same folder plus a loop back to the root
+        symlink(&real, root.join("link_a")).unwrap();
+        symlink(&real, root.join("link_b")).unwrap();
+        symlink(&root, root.join("loop")).unwrap();
+
+        let config = ScanConfig::builder()
+            .add_root(root.clone())
+            .max_depth(10)
+            .follow_symlinks(true)
+            .build();
+        let result = scan_full(&config);
+
+        assert_eq!(result.total_files, 1);
+        assert!(result.errors.is_empty(), "{:?}", result.errors);
+
+        // Without following, linked folders are invisible
+        let config = ScanConfig::builder().add_root(root).build();
+        assert_eq!(scan_full(&config).total_files, 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_follow_symlinks_prefers_real_path() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let real = root.join("z_real");
+        fs::create_dir_all(&real).unwrap();
+        fs::write(real.join("movie.mp4"), b"movie").unwrap();
+        // Both links sort before the real paths they point to
+        symlink(&real, root.join("a_link")).unwrap();
+        symlink(real.join("movie.mp4"), root.join("a_movie.mp4")).unwrap();
+
+        let config = ScanConfig::builder()
+            .add_root(root.to_path_buf())
+            .follow_symlinks(true)
+            .build();
+        let real_path = normalize_path(&real.join("movie.mp4"));
+        let mut db = ScanDatabase::open_memory().unwrap();
+        let first = scan_incremental(&config, &mut db);
+        assert_eq!(first.new_files, 1);
+        assert_eq!(first.files.len(), 1);
+        assert_eq!(
+            first.files[0].path.as_deref(),
+            Some(real.join("movie.mp4").as_path())
+        );
+
+        let second = scan_incremental(&config, &mut db);
+        assert_eq!(second.unchanged_files, 1);
+        assert_eq!(second.new_files, 0);
+        assert!(second.deleted_paths.is_empty());
+        assert!(db.get_file(&real_path).unwrap().is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_follow_symlinks_pruned_alias() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().unwrap();
+        let library = dir.path().join("library");
+        let real = dir.path().join("real");
+        fs::create_dir_all(&library).unwrap();
+        fs::create_dir_all(&real).unwrap();
+        fs::write(real.join("movie.mp4"), b"movie").unwrap();
+        fs::hard_link(real.join("movie.mp4"), real.join("copy.mp4")).unwrap();
+        symlink(&real, library.join("alias")).unwrap();
+
+        // The excluded alias is reached first and must not hide the real folder
+        let config = ScanConfig::builder()
+            .roots(vec![library, real])
+            .follow_symlinks(true)
+            .exclude("alias/")
+            .build();
+        let result = scan_full(&config);
+
+        assert_eq!(result.files.len(), 2);
+        assert_eq!(result.total_files, 1);
+        assert_eq!(result.hard_links, 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_record_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join("movie.mp4"), b"movie").unwrap();
+        symlink(root.join("movie.mp4"), root.join("alias.mp4")).unwrap();
+        symlink(root.join("missing.mp4"), root.join("broken.mp4")).unwrap();
+
+        for follow in [false, true] {
+            let config = ScanConfig::builder()
+                .add_root(root.to_path_buf())
+                .follow_symlinks(follow)
+                .record_symlinks(true)
+                .build();
+            let result = scan_full(&config);
+
+            let broken = result
+                .files
+                .iter()
+                .find(|f| f.name == "broken.mp4")
+                .unwrap();
+            assert!(broken.broken_link);
+            assert!(broken
+                .link_target
+                .as_deref()
+                .unwrap()
+                .ends_with("missing.mp4"));
+            assert!(result.errors.is_empty(), "{:?}", result.errors);
+
+            let alias = result.files.iter().find(|f| f.name == "alias.mp4");
+            if follow {
+                // Same target as movie.mp4, indexed once
+                assert_eq!(result.total_files, 2);
+            } else {
+                let alias = alias.unwrap();
+                assert!(!alias.broken_link);
+                assert_eq!(alias.size, 5);
+                assert_eq!(result.total_files, 3);
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hard_links_counted_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join("movie.mp4"), b"movie").unwrap();
+        fs::hard_link(root.join("movie.mp4"), root.join("copy.mp4")).unwrap();
+        fs::write(root.join("other.mp4"), b"other").unwrap();
+
+        let config = ScanConfig::builder().add_root(root.to_path_buf()).build();
+        let result = scan_full(&config);
+
+        assert_eq!(result.total_files, 2);
+        assert_eq!(result.hard_links, 1);
+        // Both links are still indexed, with the same identity
+        assert_eq!(result.files.len(), 3);
+        let movie = result.files.iter().find(|f| f.name == "movie.mp4").unwrap();
+        let copy = result.files.iter().find(|f| f.name == "copy.mp4").unwrap();
+        assert!(movie.file_id().is_some());
+        assert_eq!(movie.file_id(), copy.file_id());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_strict_change_detection() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let path = root.join("movie.mp4");
+        fs::write(&path, b"aaaa").unwrap();
+        let mtime = fs::metadata(&path).unwrap().modified().unwrap();
+
+        let mut db = ScanDatabase::open_memory().unwrap();
+        let fast = ScanConfig::builder().add_root(root.to_path_buf()).build();
+        db.upsert_files(&scan_full(&fast).files).unwrap();
+
+        // Same size and mtime, but new content (inode change time moves)
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        fs::write(&path, b"bbbb").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+
+        let result = scan_incremental(&fast, &mut db);
+        assert_eq!(result.unchanged_files, 1);
+
+        let strict = ScanConfig::builder()
+            .add_root(root.to_path_buf())
+            .change_detection(ChangeDetection::Strict)
+            .build();
+        let result = scan_incremental(&strict, &mut db);
+        assert_eq!(result.modified_files, 1);
+        assert!(result.files[0].change_ns.is_some());
+    }
+
+    #[test]
+    fn test_dir_shortcut() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("sub/deep")).unwrap();
+        fs::write(root.join("sub/a.mp4"), b"a").unwrap();
+        fs::write(root.join("sub/deep/b.mp4"), b"b").unwrap();
+
+        let mut db = ScanDatabase::open_memory().unwrap();
+        let config = ScanConfig::builder()
+            .add_root(root.to_path_buf())
+            .dir_shortcut(true)
+            .build();
+        let first = scan_incremental(&config, &mut db);
+        assert_eq!(first.new_files, 2);
+        assert_eq!(first.shortcut_dirs, 0);
+        assert_eq!(db.load_directory_index().unwrap().len(), 3);
+
+        // Nothing changed: every directory is taken from the index
+        let second = scan_incremental(&config, &mut db);
+        assert_eq!(second.shortcut_dirs, 3);
+        assert_eq!(second.total_dirs, 3);
+        assert_eq!(second.unchanged_files, 2);
+        assert_eq!(second.deleted_files, 0);
+
+        // Adding and removing entries changes only that directory's mtime
+        fs::write(root.join("sub/deep/c.mp4"), b"c").unwrap();
+        fs::remove_file(root.join("sub/deep/b.mp4")).unwrap();
+        let third = scan_incremental(&config, &mut db);
+        assert_eq!(third.shortcut_dirs, 2);
+        assert_eq!(third.new_files, 1);
+        assert_eq!(third.unchanged_files, 1);
+        assert_eq!(third.deleted_paths.len(), 1);
+
+        // In-place edits are only seen by a full verification
+        fs::write(root.join("sub/a.mp4"), b"aa").unwrap();
+        assert_eq!(scan_incremental(&config, &mut db).modified_files, 0);
+        let verify = ScanConfig::builder()
+            .add_root(root.to_path_buf())
+            .dir_shortcut(true)
+            .dir_verify_interval_secs(0)
+            .build();
+        let result = scan_incremental(&verify, &mut db);
+        assert_eq!(result.shortcut_dirs, 0);
+        assert_eq!(result.modified_files, 1);
+
+        // Vanished directories are dropped from the table
+        fs::remove_dir_all(root.join("sub/deep")).unwrap();
+        scan_incremental(&config, &mut db);
+        assert_eq!(db.load_directory_index().unwrap().len(), 2);
+
+        // A listing change that keeps the mtime is caught by the entry count
+        let sub = root.join("sub");
+        let mtime = fs::metadata(&sub).unwrap().modified().unwrap();
+        fs::write(sub.join("d.mp4"), b"d").unwrap();
+        fs::File::open(&sub).unwrap().set_modified(mtime).unwrap();
+        let result = scan_incremental(&config, &mut db);
+        assert_eq!(result.shortcut_dirs, 1);
+        assert_eq!(result.new_files, 1);
+    }
+
+    #[test]
+    fn test_cancelled_scan_keeps_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join("a.mp4"), b"a").unwrap();
+        fs::write(root.join("b.mp4"), b"b").unwrap();
+
+        let mut db = ScanDatabase::open_memory().unwrap();
+        let config = ScanConfig::builder().add_root(root.to_path_buf()).build();
+        assert_eq!(scan_incremental(&config, &mut db).new_files, 2);
+
+        let cancel = CancelToken::new();
+        assert!(!scan_full_with_cancel(&config, &cancel).cancelled);
+        cancel.cancel();
+        assert!(cancel.clone().is_cancelled());
+
+        let result = scan_full_with_cancel(&config, &cancel);
+        assert!(result.cancelled);
+        assert!(result.files.is_empty());
+
+        // Files the cancelled scan never reached are not reported as deleted
+        let result = scan_incremental_with_cancel(&config, &mut db, &cancel);
+        assert!(result.cancelled);
+        assert!(result.deleted_paths.is_empty());
+        assert_eq!(db.file_count().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_deleted_file_restored() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join("a.mp4"), b"same content").unwrap();
+        fs::write(root.join("b.mp4"), b"other").unwrap();
+
+        let mut db = ScanDatabase::open_memory().unwrap();
+        let config = ScanConfig::builder()
+            .add_root(root.to_path_buf())
+            .compute_hash(true)
+            .build();
+        scan_incremental(&config, &mut db);
+        fs::remove_file(root.join("a.mp4")).unwrap();
+        assert_eq!(scan_incremental(&config, &mut db).deleted_files, 1);
+        assert_eq!(db.deleted_files_count().unwrap(), 1);
+
+        // The same content reappears under another name
+        fs::write(root.join("c.mp4"), b"same content").unwrap();
+        fs::write(root.join("d.mp4"), b"new content").unwrap();
+        let result = scan_incremental(&config, &mut db);
+        assert_eq!((result.new_files, result.restored_files), (1, 1));
+        let restored = result
+            .files
+            .iter()
+            .find(|f| f.status == FileStatus::Restored)
+            .unwrap();
+        assert_eq!(restored.name, "c.mp4");
+        assert_eq!(
+            restored.old_path.as_deref(),
+            Some(normalize_path(&root.join("a.mp4")).as_str())
+        );
+        assert_eq!(db.deleted_files_count().unwrap(), 0);
+        let stored = db
+            .get_file(&normalize_path(&root.join("c.mp4")))
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.status, "restored");
+    }
+
+    #[test]
+    fn test_scan_with_sink_events() {
+        use crate::progress::{DoneMessage, ErrorProgressMessage, StartMessage};
+        use std::sync::Mutex;
+
+        #[derive(Default)]
+        struct Events(Mutex<Vec<String>>);
+
+        impl Events {
+            fn push(&self, event: String) {
+                self.0.lock().unwrap().push(event);
+            }
+        }
+
+        impl ProgressSink for Events {
+            fn on_start(&self, msg: &StartMessage) {
+                self.push(format!("start {}", msg.roots.len()));
+            }
+
+            fn on_phase(&self, phase: ScanPhase) {
+                self.push(phase.as_str().to_string());
+            }
+
+            fn on_error(&self, msg: &ErrorProgressMessage) {
+                self.push(format!("error {}", msg.error_type));
+            }
+
+            fn on_done(&self, msg: &DoneMessage) {
+                self.push(format!("done {}", msg.total_files));
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join("a.mp4"), b"a").unwrap();
+        let config = ScanConfig::builder()
+            .add_root(root.to_path_buf())
+            .add_root(root.join("missing"))
+            .build();
+
+        let events = Events::default();
+        let result = scan_full_with_sink(&config, &events, None);
+        assert_eq!(result.total_files, 1);
+        let expected = [
+            "start 2",
+            "scan",
+            "error NotFound",
+            "process",
+            "done",
+            "done 1",
+        ];
+        assert_eq!(events.0.into_inner().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_scan_with_json_writer_sink() {
+        use crate::progress::JsonWriterSink;
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.mp4"), b"a").unwrap();
+        let config = ScanConfig::builder()
+            .add_root(dir.path().to_path_buf())
+            .build();
+
+        let mut output = Vec::new();
+        let mut db = ScanDatabase::open_memory().unwrap();
+        let result =
+            scan_incremental_with_sink(&config, &mut db, JsonWriterSink::new(&mut output), None);
+        assert_eq!(result.new_files, 1);
+        let lines: Vec<serde_json::Value> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.first().unwrap()["_t"], "start");
+        assert_eq!(lines.last().unwrap()["_t"], "done");
+
+        // Cancelled before it starts: nothing is stored
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let result = scan_full_with_sink(&config, JsonWriterSink::new(Vec::new()), Some(&cancel));
+        assert!(result.cancelled);
+    }
+
+    #[test]
+    fn test_hash_stops_when_cancelled() {
+        use md5::Digest;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.mp4");
+        fs::write(&path, vec![7u8; 3 * 1024 * 1024]).unwrap();
+
+        let cancel = CancelToken::new();
+        let (hash, partial) = compute_file_hash(&path, u64::MAX, Some(&cancel)).unwrap();
+        assert!(!partial);
+        let expected = format!("{:x}", md5::Md5::digest(fs::read(&path).unwrap()));
+        assert_eq!(hash, expected);
+
+        cancel.cancel();
+        assert!(compute_file_hash(&path, u64::MAX, Some(&cancel)).is_none());
+    }
 }
@@ -0,0 +1,561 @@
+//! JSON Schema for the progress and output protocol
+//!
+//! Describes every progress message (`_t`: start, p, err, done) and every
+//! record of the compact, NDJSON and JSON outputs, for `media_scanner schema`.
+//! The tests check the definitions against the serialized types so the two
+//! cannot drift apart.
+
+use serde_json::{json, Map, Value};
+
+use crate::progress::PROTOCOL_VERSION;
+
+/// Build the JSON Schema (draft 2020-12) for protocol version `PROTOCOL_VERSION`
+///
+/// Each message and record type is a named entry in `$defs`; the root schema
+/// accepts any single line of the progress stream or of the line-based outputs.
+pub fn protocol_schema() -> Value {
+    let defs = definitions();
+    let lines: Vec<Value> = LINE_TYPES
+        .iter()
+        .map(|name| json!({ "$ref": format!("#/$defs/{}", name) }))
+        .collect();
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "$id": format!("urn:media-scanner:protocol:{}", PROTOCOL_VERSION),
+        "title": "media_scanner progress and output protocol",
+        "description": "Progress messages (stderr, --progress-fd, --progress-file, JSON-RPC \
+                        progress notifications) and scan output records. The start message \
+                        carries the protocol version in `v`.",
+        "version": PROTOCOL_VERSION,
+        "anyOf": lines,
+        "$defs": defs,
+    })
+}
+
+/// Definitions that can appear as a single line of a stream
+const LINE_TYPES: &[&str] = &[
+    "start",
+    "progress",
+    "error",
+    "done",
+    "compact_summary",
+    "compact_directory",
+    "compact_deleted",
+    "summary",
+    "file",
+    "deleted",
+];
+
+fn definitions() -> Map<String, Value> {
+    let mut defs = Map::new();
+    let mut add = |name: &str, schema: Value| {
+        defs.insert(name.to_string(), schema);
+    };
+
+    add(
+        "start",
+        object(
+            "Progress: scan started (first message)",
+            &[
+                ("_t", constant("start")),
+                ("v", integer("Protocol version")),
+                ("seq", integer("Sequence number, starting at 0")),
+                ("ts", integer("Milliseconds since the reporter was created")),
+                ("roots", array(string("Root path"), "Scan root paths")),
+                (
+                    "recursive",
+                    boolean("Whether directories are scanned recursively"),
+                ),
+                ("max_depth", integer("Maximum scan depth")),
+                ("compute_hash", boolean("Whether file hashes are computed")),
+            ],
+            &[("request_id", id("JSON-RPC only: id of the scan request"))],
+        ),
+    );
+    add(
+        "progress",
+        object(
+            "Progress: periodic update",
+            &[
+                ("_t", constant("p")),
+                ("seq", integer("Sequence number (monotonically increasing)")),
+                ("ts", integer("Milliseconds since the reporter was created")),
+                ("phase", phase()),
+                ("f", integer("Files scanned")),
+                ("d", integer("Directories scanned")),
+                ("v", integer("Video files found")),
+                ("i", integer("Image files found")),
+                ("a", integer("Audio files found")),
+                ("dir", string("Directory being scanned")),
+                ("ms", integer("Elapsed milliseconds")),
+            ],
+            &[
+                ("eta_ms", integer("Estimated remaining milliseconds")),
+                ("hb", integer("Bytes read for hashing (omitted when 0)")),
+                ("request_id", id("JSON-RPC only: id of the scan request")),
+            ],
+        ),
+    );
+    add(
+        "error",
+        object(
+            "Progress: an error was encountered; the scan continues",
+            &[
+                ("_t", constant("err")),
+                ("seq", integer("Sequence number")),
+                ("ts", integer("Milliseconds since the reporter was created")),
+                ("error_type", string("Error kind (e.g. PermissionDenied)")),
+                ("message", string("Error description")),
+            ],
+            &[
+                ("path", string("Path that caused the error")),
+                ("request_id", id("JSON-RPC only: id of the scan request")),
+            ],
+        ),
+    );
+    add(
+        "done",
+        object(
+            "Progress: scan finished or was cancelled (last message)",
+            &[
+                ("_t", constant("done")),
+                ("seq", integer("Sequence number")),
+                ("ts", integer("Milliseconds since the reporter was created")),
+                ("tf", integer("Total files")),
+                ("td", integer("Total directories")),
+                ("nf", integer("New files")),
+                ("mf", integer("Modified files")),
+                ("df", integer("Deleted files")),
+                ("ec", integer("Number of errors")),
+                ("ms", integer("Scan duration in milliseconds")),
+            ],
+            &[("request_id", id("JSON-RPC only: id of the scan request"))],
+        ),
+    );
+
+    add(
+        "compact_summary",
+        object(
+            "Compact output (--compact): first line, scan statistics",
+            &[
+                ("_t", constant("s")),
+                ("tf", integer("Total files")),
+                ("td", integer("Total directories")),
+                ("nf", integer("New files")),
+                ("mf", integer("Modified files")),
+                ("uf", integer("Unchanged files")),
+                ("df", integer("Deleted files")),
+                ("vf", integer("Moved files (diff only)")),
+                ("rf", integer("Files restored from the deleted files")),
+                (
+                    "ff",
+                    integer("Files skipped by size, mtime or name filters"),
+                ),
+                ("hl", integer("Extra hard links (not counted as files)")),
+                ("sd", integer("Unchanged directories taken from the index")),
+                ("cx", boolean("Whether the scan was cancelled")),
+                ("ec", integer("Number of errors")),
+                ("ms", integer("Scan duration in milliseconds")),
+            ],
+            &[],
+        ),
+    );
+    add(
+        "compact_directory",
+        object(
+            "Compact output: one directory with its new and modified files",
+            &[
+                ("path", string("Directory path")),
+                (
+                    "files",
+                    array(json!({ "$ref": "#/$defs/compact_file" }), "Files"),
+                ),
+            ],
+            &[],
+        ),
+    );
+    add(
+        "compact_file",
+        object(
+            "Compact output: a file inside a directory record",
+            &[
+                ("n", string("File name")),
+                ("s", integer("Size in bytes")),
+                ("m", integer("Modification time (Unix seconds)")),
+                ("t", one_of(&["v", "i", "a", "u"], "Media type")),
+            ],
+            &[
+                (
+                    "st",
+                    one_of(&["m", "u", "d", "v", "c", "r"], "Status (omitted when new)"),
+                ),
+                ("h", string("MD5 hash (partial for large files)")),
+                (
+                    "o",
+                    string("Previous path of a moved file, or where a restored file was deleted"),
+                ),
+            ],
+        ),
+    );
+    add(
+        "compact_deleted",
+        object(
+            "Compact output: last line, paths deleted since the previous scan",
+            &[
+                ("_t", constant("d")),
+                ("paths", array(string("Path"), "Deleted paths")),
+            ],
+            &[],
+        ),
+    );
+
+    add("summary", summary(true));
+    add("file", file());
+    add(
+        "deleted",
+        object(
+            "NDJSON output: a path deleted since the previous scan",
+            &[("_type", constant("deleted")), ("path", string("Path"))],
+            &[],
+        ),
+    );
+    add(
+        "json_output",
+        object(
+            "JSON output (--json): the whole result as one document",
+            &[
+                ("summary", summary(false)),
+                (
+                    "directories",
+                    array(
+                        json!({ "$ref": "#/$defs/compact_directory" }),
+                        "Directories with new and modified files",
+                    ),
+                ),
+                ("deleted", array(string("Path"), "Deleted paths")),
+            ],
+            &[],
+        ),
+    );
+    defs
+}
+
+/// Summary of the NDJSON (`_type: "summary"` first line) and JSON outputs
+fn summary(line: bool) -> Value {
+    let mut required = vec![
+        ("total_files", integer("Total files")),
+        ("total_dirs", integer("Total directories")),
+        ("new_files", integer("New files")),
+        ("modified_files", integer("Modified files")),
+        ("unchanged_files", integer("Unchanged files")),
+        ("deleted_files", integer("Deleted files")),
+        ("moved_files", integer("Moved files (diff only)")),
+        (
+            "restored_files",
+            integer("Files restored from the deleted files"),
+        ),
+        (
+            "filtered_files",
+            integer("Files skipped by size, mtime or name filters"),
+        ),
+        (
+            "hard_links",
+            integer("Extra hard links (not counted as files)"),
+        ),
+        (
+            "shortcut_dirs",
+            integer("Unchanged directories taken from the index"),
+        ),
+        ("cancelled", boolean("Whether the scan was cancelled")),
+        ("error_count", integer("Number of errors")),
+        ("duration_ms", integer("Scan duration in milliseconds")),
+    ];
+    if line {
+        required.insert(0, ("_type", constant("summary")));
+        object("NDJSON output (--ndjson): first line", &required, &[])
+    } else {
+        object("Scan statistics", &required, &[])
+    }
+}
+
+/// NDJSON output: one scanned file per line (`ScannedFile`)
+fn file() -> Value {
+    object(
+        "NDJSON output: a new or modified file",
+        &[
+            ("name", string("File name")),
+            ("size", integer("Size in bytes")),
+            ("mtime", integer("Modification time (Unix seconds)")),
+            ("ctime", integer("Creation time (Unix seconds)")),
+            ("extension", string("Lowercase extension without the dot")),
+            (
+                "media_type",
+                one_of(&["video", "image", "audio", "unknown"], "Media type"),
+            ),
+        ],
+        &[
+            ("path", string("Full path")),
+            ("hash", string("MD5 hash")),
+            (
+                "is_partial_hash",
+                boolean("Whether the hash covers only the first and last 1MB"),
+            ),
+            (
+                "status",
+                one_of(
+                    &[
+                        "modified",
+                        "unchanged",
+                        "deleted",
+                        "moved",
+                        "corrupted",
+                        "restored",
+                    ],
+                    "Status (omitted when new)",
+                ),
+            ),
+            (
+                "old_path",
+                string("Previous path of a moved file, or where a restored file was deleted"),
+            ),
+            ("link_target", string("Target of a symbolic link")),
+            ("broken_link", boolean("Whether the link target is missing")),
+            ("dev", integer("Device number (Unix)")),
+            ("inode", integer("Inode number (Unix)")),
+            ("mtime_ns", integer("Modification time in nanoseconds")),
+            (
+                "change_ns",
+                integer("Inode change time in nanoseconds (Unix)"),
+            ),
+        ],
+    )
+}
+
+/// Object with the given required and optional properties and no others
+fn object(description: &str, required: &[(&str, Value)], optional: &[(&str, Value)]) -> Value {
+    let properties: Map<String, Value> = required
+        .iter()
+        .chain(optional)
+        .map(|(name, schema)| (name.to_string(), schema.clone()))
+        .collect();
+    let names: Vec<&str> = required.iter().map(|(name, _)| *name).collect();
+    json!({
+        "description": description,
+        "type": "object",
+        "properties": properties,
+        "required": names,
+        "additionalProperties": false,
+    })
+}
+
+fn constant(value: &str) -> Value {
+    json!({ "const": value })
+}
+
+fn integer(description: &str) -> Value {
+    json!({ "type": "integer", "description": description })
+}
+
+fn string(description: &str) -> Value {
+    json!({ "type": "string", "description": description })
+}
+
+fn boolean(description: &str) -> Value {
+    json!({ "type": "boolean", "description": description })
+}
+
+fn array(items: Value, description: &str) -> Value {
+    json!({ "type": "array", "items": items, "description": description })
+}
+
+fn one_of(values: &[&str], description: &str) -> Value {
+    json!({ "enum": values, "description": description })
+}
+
+fn phase() -> Value {
+    one_of(&["scan", "process", "done"], "Scan phase")
+}
+
+fn id(description: &str) -> Value {
+    json!({ "type": ["integer", "string", "null"], "description": description })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{CompactFile, FileStatus, ScanResult, ScannedDirectory, ScannedFile};
+    use crate::progress::StartMessage;
+    use crate::progress::{DoneMessage, ErrorProgressMessage, ProgressMessage, ScanPhase};
+
+    /// Check `value` against the subset of JSON Schema used by `protocol_schema`
+    fn check(schema: &Value, value: &Value, defs: &Value) -> Result<(), String> {
+        if let Some(reference) = schema["$ref"].as_str() {
+            let name = reference.trim_start_matches("#/$defs/");
+            return check(&defs[name], value, defs);
+        }
+        if let Some(expected) = schema.get("const") {
+            return (expected == value)
+                .then_some(())
+                .ok_or(format!("expected {}, got {}", expected, value));
+        }
+        if let Some(values) = schema["enum"].as_array() {
+            return values
+                .contains(value)
+                .then_some(())
+                .ok_or(format!("{} is not one of {:?}", value, values));
+        }
+        let types: Vec<&str> = match &schema["type"] {
+            Value::String(t) => vec![t.as_str()],
+            Value::Array(ts) => ts.iter().filter_map(Value::as_str).collect(),
+            _ => vec![],
+        };
+        let matches = |t: &str| match t {
+            "object" => value.is_object(),
+            "array" => value.is_array(),
+            "string" => value.is_string(),
+            "integer" => value.is_i64() || value.is_u64(),
+            "boolean" => value.is_boolean(),
+            "null" => value.is_null(),
+            _ => false,
+        };
+        if !types.iter().any(|t| matches(t)) {
+            return Err(format!("{} is not of type {:?}", value, types));
+        }
+        if let Some(object) = value.as_object() {
+            let properties = schema["properties"].as_object().unwrap();
+            for name in schema["required"].as_array().unwrap() {
+                let name = name.as_str().unwrap();
+                if !object.contains_key(name) {
+                    return Err(format!("missing required {:?}", name));
+                }
+            }
+            for (name, field) in object {
+                let property = properties
+                    .get(name)
+                    .ok_or(format!("unexpected property {:?}", name))?;
+                check(property, field, defs).map_err(|e| format!("{}: {}", name, e))?;
+            }
+        }
+        if let Some(items) = value.as_array() {
+            for item in items {
+                check(&schema["items"], item, defs)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn assert_valid(name: &str, value: Value) {
+        let schema = protocol_schema();
+        let defs = &schema["$defs"];
+        if let Err(e) = check(&defs[name], &value, defs) {
+            panic!("{} does not match the schema: {}\n{}", name, e, value);
+        }
+    }
+
+    fn to_value<T: serde::Serialize>(value: &T) -> Value {
+        serde_json::to_value(value).unwrap()
+    }
+
+    fn sample_file() -> ScannedFile {
+        let mut file = ScannedFile::new(
+            "/media/a.mp4".into(),
+            "a.mp4".to_string(),
+            10,
+            1,
+            1,
+            "mp4".to_string(),
+        )
+        .with_status(FileStatus::Modified);
+        file.hash = Some("abc".to_string());
+        file.is_partial_hash = true;
+        file.inode = Some(7);
+        file
+    }
+
+    #[test]
+    fn test_schema_document() {
+        let schema = protocol_schema();
+        assert_eq!(schema["version"], PROTOCOL_VERSION);
+        for reference in schema["anyOf"].as_array().unwrap() {
+            let name = reference["$ref"].as_str().unwrap();
+            let name = name.trim_start_matches("#/$defs/");
+            assert!(schema["$defs"].get(name).is_some(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_progress_messages_match_schema() {
+        let start = StartMessage::new(0, 0, vec!["/media".to_string()], true, 3, false);
+        assert_eq!(to_value(&start)["v"], PROTOCOL_VERSION);
+        assert_valid("start", to_value(&start));
+
+        let progress = ProgressMessage::new(
+            1,
+            5,
+            ScanPhase::Scan,
+            1,
+            2,
+            1,
+            0,
+            0,
+            "/media".to_string(),
+            5,
+            Some(10),
+        )
+        .with_hashed_bytes(10);
+        assert_valid("progress", to_value(&progress));
+
+        let error = ErrorProgressMessage::new(
+            2,
+            6,
+            "IoError".to_string(),
+            "failed".to_string(),
+            Some("/media/x".to_string()),
+        );
+        assert_valid("error", to_value(&error));
+        assert_valid(
+            "done",
+            to_value(&DoneMessage::new(3, 7, 1, 2, 1, 0, 0, 0, 7)),
+        );
+    }
+
+    #[test]
+    fn test_output_records_match_schema() {
+        let result = ScanResult {
+            files: vec![sample_file()],
+            deleted_paths: vec!["/media/b.mp4".to_string()],
+            ..Default::default()
+        };
+        let directory = ScannedDirectory {
+            path: "/media".to_string(),
+            files: result.files.iter().map(CompactFile::from_scanned).collect(),
+        };
+
+        assert_valid("compact_summary", result.compact_summary());
+        assert_valid("compact_directory", to_value(&directory));
+        assert_valid(
+            "compact_deleted",
+            json!({ "_t": "d", "paths": result.deleted_paths }),
+        );
+        let mut summary = result.summary();
+        summary["_type"] = "summary".into();
+        assert_valid("summary", summary);
+        assert_valid("file", to_value(&result.files[0]));
+        assert_valid(
+            "json_output",
+            json!({
+                "summary": result.summary(),
+                "directories": [directory],
+                "deleted": result.deleted_paths,
+            }),
+        );
+    }
+
+    #[test]
+    fn test_schema_rejects_unknown_fields() {
+        let mut start = to_value(&StartMessage::new(0, 0, vec![], true, 3, false));
+        start["extra"] = 1.into();
+        let schema = protocol_schema();
+        assert!(check(&schema["$defs"]["start"], &start, &schema["$defs"]).is_err());
+    }
+}
@@ -0,0 +1,502 @@
+//! Server mode - line-delimited JSON-RPC 2.0 over stdin/stdout
+//!
+//! Each request is one JSON object per line. Scans run on a worker thread so
+//! `query`, `stats` and `cancel` keep being answered while a scan is in
+//! progress; scan progress is sent as `progress` notifications carrying the
+//! id of the request that started the scan.
+//!
+//! Methods:
+//! - `scan` / `scanIncremental`: params are `ScanConfig` fields merged over
+//!   the server's configuration (except `db_path`, `progress_fd` and
+//!   `progress_file`, which are rejected); the result is the `ScanResult`
+//! - `query`: params are a `FileQuery`; the result is a list of files, or with
+//!   `format` set to `csv` or `tsv` the table of the `columns` as a string
+//! - `stats`: the result is `DbStats`
+//! - `cancel`: stops the running scan; the result is `{"cancelled": bool}`
+
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+use crate::config::ScanConfig;
+use crate::db::{FileQuery, ScanDatabase, ScanRecord};
+use crate::error::ScanError;
+use crate::export::{to_table, Column, TableFormat};
+use crate::models::ScanResult;
+use crate::progress::{
+    DoneMessage, ErrorProgressMessage, ProgressMessage, ProgressReporter, ProgressSink,
+    StartMessage,
+};
+use crate::scanner::{scan_full_with, scan_incremental_with, CancelToken};
+
+/// Invalid JSON was received
+pub const PARSE_ERROR: i64 = -32700;
+/// The JSON is not a valid request object
+pub const INVALID_REQUEST: i64 = -32600;
+/// The method does not exist
+pub const METHOD_NOT_FOUND: i64 = -32601;
+/// Invalid method parameters
+pub const INVALID_PARAMS: i64 = -32602;
+/// The request was valid but could not be carried out
+pub const SERVER_ERROR: i64 = -32000;
+
+/// Output shared between the request loop and the scan worker
+type SharedOutput = Arc<Mutex<Box<dyn Write + Send>>>;
+
+/// JSON-RPC error object
+#[derive(Debug, Clone, Serialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// JSON-RPC server handling one request line at a time
+pub struct Server {
+    /// Server configuration as JSON, the base for scan parameters
+    base_config: Map<String, Value>,
+    db_path: PathBuf,
+    db: ScanDatabase,
+    out: SharedOutput,
+    cancel: CancelToken,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Server {
+    /// Create a server using `config` as the base scan configuration
+    pub fn new(
+        config: &ScanConfig,
+        db_path: PathBuf,
+        out: Box<dyn Write + Send>,
+    ) -> Result<Self, ScanError> {
+        let base_config = match serde_json::to_value(config) {
+            Ok(Value::Object(map)) => map,
+            Ok(_) => Map::new(),
+            Err(e) => return Err(ScanError::config_error(None, e.to_string())),
+        };
+        let db = ScanDatabase::open(&db_path)?;
+        Ok(Self {
+            base_config,
+            db_path,
+            db,
+            out: Arc::new(Mutex::new(out)),
+            cancel: CancelToken::new(),
+            worker: None,
+        })
+    }
+
+    /// Whether a scan is currently running
+    pub fn is_scanning(&self) -> bool {
+        self.worker.as_ref().is_some_and(|w| !w.is_finished())
+    }
+
+    /// Handle one request line, writing the response (if any)
+    pub fn handle_line(&mut self, line: &str) {
+        if line.trim().is_empty() {
+            return;
+        }
+        let request: Value = match serde_json::from_str(line) {
+            Ok(value) => value,
+            Err(e) => {
+                let error = RpcError::new(PARSE_ERROR, e.to_string());
+                send(&self.out, &error_response(Value::Null, error));
+                return;
+            }
+        };
+
+        let Some(method) = request.get("method").and_then(Value::as_str) else {
+            let id = request.get("id").cloned().unwrap_or(Value::Null);
+            let error = RpcError::new(INVALID_REQUEST, "missing method");
+            send(&self.out, &error_response(id, error));
+            return;
+        };
+        let id = request.get("id").cloned();
+        let params = request.get("params").cloned().unwrap_or(Value::Null);
+
+        let result = match method {
+            "scan" => self.start_scan(id.clone(), params, false),
+            "scanIncremental" => self.start_scan(id.clone(), params, true),
+            "query" => self.query(params),
+            "stats" => self.stats(),
+            "cancel" => Ok(Some(self.cancel())),
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("unknown method {:?}", method),
+            )),
+        };
+
+        // Requests without an id are notifications and get no response
+        let Some(id) = id else {
+            return;
+        };
+        match result {
+            Ok(Some(result)) => send(
+                &self.out,
+                &json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            ),
+            // The scan worker responds when it finishes
+            Ok(None) => {}
+            Err(error) => send(&self.out, &error_response(id, error)),
+        }
+    }
+
+    /// Wait for a running scan to finish
+    pub fn wait(&mut self) {
+        if let Some(worker) = self.worker.take() {
+            if worker.join().is_err() {
+                log::error!("Scan worker panicked");
+            }
+        }
+    }
+
+    /// Start a scan on the worker thread
+    fn start_scan(
+        &mut self,
+        id: Option<Value>,
+        params: Value,
+        incremental: bool,
+    ) -> Result<Option<Value>, RpcError> {
+        if self.is_scanning() {
+            return Err(RpcError::new(SERVER_ERROR, "a scan is already running"));
+        }
+        let config = scan_config(&self.base_config, params, &self.db_path)
+            .map_err(|message| RpcError::new(INVALID_PARAMS, message))?;
+        self.wait();
+
+        self.cancel = CancelToken::new();
+        let cancel = self.cancel.clone();
+        let out = Arc::clone(&self.out);
+        let db_path = self.db_path.clone();
+
+        self.worker = Some(std::thread::spawn(move || {
+            let sink = NotificationSink {
+                out: Arc::clone(&out),
+                request_id: id.clone().unwrap_or(Value::Null),
+            };
+            let reporter = ProgressReporter::with_sink(true, config.progress_interval_ms, sink);
+
+            let result = run_scan(&config, &db_path, &reporter, &cancel, incremental);
+            if let Some(id) = id {
+                let response =
+                    match result.and_then(|r| serde_json::to_value(r).map_err(|e| e.to_string())) {
+                        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+                        Err(message) => error_response(id, RpcError::new(SERVER_ERROR, message)),
+                    };
+                send(&out, &response);
+            }
+        }));
+        Ok(None)
+    }
+
+    /// Query files; with `format` set to `csv` or `tsv` the result is the table as a string
+    fn query(&self, params: Value) -> Result<Option<Value>, RpcError> {
+        let invalid = |e: String| RpcError::new(INVALID_PARAMS, e);
+        let format: Option<TableFormat> = match params.get("format").and_then(Value::as_str) {
+            None | Some("json") => None,
+            Some(format) => Some(format.parse().map_err(invalid)?),
+        };
+        let columns = match params.get("columns") {
+            None => Column::ALL.to_vec(),
+            Some(Value::String(list)) => Column::parse_list(list).map_err(invalid)?,
+            Some(Value::Array(names)) => names
+                .iter()
+                .map(|name| name.as_str().unwrap_or_default().parse())
+                .collect::<Result<_, _>>()
+                .map_err(invalid)?,
+            Some(other) => return Err(invalid(format!("invalid columns: {}", other))),
+        };
+        let query: FileQuery = match params {
+            Value::Null => FileQuery::default(),
+            params => serde_json::from_value(params).map_err(|e| invalid(e.to_string()))?,
+        };
+        let files = self
+            .db
+            .query_files(&query)
+            .map_err(|e| RpcError::new(SERVER_ERROR, e.to_string()))?;
+        match format {
+            Some(format) => Ok(Some(Value::String(to_table(&files, format, &columns)))),
+            None => Ok(Some(json!(files))),
+        }
+    }
+
+    fn stats(&self) -> Result<Option<Value>, RpcError> {
+        let stats = self
+            .db
+            .get_stats()
+            .map_err(|e| RpcError::new(SERVER_ERROR, e.to_string()))?;
+        Ok(Some(json!(stats)))
+    }
+
+    fn cancel(&self) -> Value {
+        let running = self.is_scanning();
+        if running {
+            self.cancel.cancel();
+        }
+        json!({ "cancelled": running })
+    }
+}
+
+/// ScanConfig fields naming files the server writes, which only the server's
+/// own configuration may set
+const SERVER_ONLY_FIELDS: [&str; 3] = ["db_path", "progress_fd", "progress_file"];
+
+/// Merge request parameters (ScanConfig fields) over a base configuration,
+/// rejecting the server-only fields
+pub(crate) fn scan_config(
+    base: &Map<String, Value>,
+    params: Value,
+    db_path: &Path,
+) -> Result<ScanConfig, String> {
+    let mut merged = base.clone();
+    match params {
+        Value::Null => {}
+        Value::Object(params) => {
+            if let Some(field) = SERVER_ONLY_FIELDS.iter().find(|f| params.contains_key(**f)) {
+                return Err(format!("{} cannot be set by a request", field));
+            }
+            merged.extend(params)
+        }
+        _ => return Err("params must be an object".to_string()),
+    }
+    let mut config: ScanConfig =
+        serde_json::from_value(Value::Object(merged)).map_err(|e| e.to_string())?;
+    if config.roots.is_empty() {
+        return Err("no roots to scan".to_string());
+    }
+    // Scans always use the server's database
+    config.db_path = Some(db_path.to_path_buf());
+    Ok(config)
+}
+
+/// Run a scan against its own database connection, storing the results,
+/// adding it to the scan history and applying the deleted files retention
+pub(crate) fn run_scan(
+    config: &ScanConfig,
+    db_path: &Path,
+    reporter: &ProgressReporter,
+    cancel: &CancelToken,
+    incremental: bool,
+) -> Result<ScanResult, String> {
+    let mut db = ScanDatabase::open(db_path).map_err(|e| e.to_string())?;
+    let result = if incremental {
+        scan_incremental_with(config, &mut db, reporter, Some(cancel))
+    } else {
+        let result = scan_full_with(config, reporter, Some(cancel));
+        // Save for future incremental scans, as the scan command does
+        db.upsert_files(&result.files).map_err(|e| e.to_string())?;
+        result
+    };
+    let record = ScanRecord::from_result(&result, &config.roots, incremental);
+    db.record_scan(&record).map_err(|e| e.to_string())?;
+    db.apply_deleted_retention(config.keep_deleted_days, config.keep_deleted_runs)
+        .map_err(|e| e.to_string())?;
+    Ok(result)
+}
+
+/// Sink sending progress messages as `progress` notifications carrying the id
+/// of the request that started the scan
+struct NotificationSink {
+    out: SharedOutput,
+    request_id: Value,
+}
+
+/// `progress` notification wrapping a progress message
+#[derive(Serialize)]
+struct Notification<'a, T> {
+    jsonrpc: &'static str,
+    method: &'static str,
+    params: NotificationParams<'a, T>,
+}
+
+/// Progress message fields plus the id of the scan request
+#[derive(Serialize)]
+struct NotificationParams<'a, T> {
+    #[serde(flatten)]
+    msg: &'a T,
+    request_id: &'a Value,
+}
+
+impl NotificationSink {
+    fn notify<T: Serialize>(&self, msg: &T) {
+        let notification = Notification {
+            jsonrpc: "2.0",
+            method: "progress",
+            params: NotificationParams {
+                msg,
+                request_id: &self.request_id,
+            },
+        };
+        send(&self.out, &notification);
+    }
+}
+
+impl ProgressSink for NotificationSink {
+    fn on_start(&self, msg: &StartMessage) {
+        self.notify(msg);
+    }
+
+    fn on_progress(&self, msg: &ProgressMessage) {
+        self.notify(msg);
+    }
+
+    fn on_error(&self, msg: &ErrorProgressMessage) {
+        self.notify(msg);
+    }
+
+    fn on_done(&self, msg: &DoneMessage) {
+        self.notify(msg);
+    }
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "error": error})
+}
+
+/// Write one message as a line and flush it
+fn send(out: &SharedOutput, message: &impl Serialize) {
+    let mut out = match out.lock() {
+        Ok(out) => out,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    let written = serde_json::to_writer(&mut *out, message)
+        .map_err(std::io::Error::from)
+        .and_then(|_| writeln!(out))
+        .and_then(|_| out.flush());
+    if let Err(e) = written {
+        log::warn!("Failed to write response: {}", e);
+    }
+}
+
+/// Serve requests read from `input` until it is closed
+///
+/// A scan still running at end of input is allowed to finish.
+pub fn serve(
+    config: &ScanConfig,
+    db_path: PathBuf,
+    input: impl BufRead,
+    out: Box<dyn Write + Send>,
+) -> Result<(), ScanError> {
+    let mut server = Server::new(config, db_path, out)?;
+    for line in input.lines() {
+        server.handle_line(&line?);
+    }
+    server.wait();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Cursor;
+
+    /// Writer appending into a shared buffer
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Buffer {
+        fn messages(&self) -> Vec<Value> {
+            let data = self.0.lock().unwrap();
+            String::from_utf8_lossy(&data)
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        }
+    }
+
+    fn response(messages: &[Value], id: i64) -> &Value {
+        messages
+            .iter()
+            .find(|m| m["id"] == json!(id))
+            .unwrap_or_else(|| panic!("no response for request {}", id))
+    }
+
+    #[test]
+    fn test_serve_scan_query_and_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        let media = dir.path().join("media");
+        fs::create_dir(&media).unwrap();
+        fs::write(media.join("a.mp4"), b"video").unwrap();
+        fs::write(media.join("b.jpg"), b"image").unwrap();
+        let roots = json!([media]);
+
+        let input = [
+            json!({"jsonrpc": "2.0", "id": 1, "method": "scan", "params": {"roots": roots}}),
+            json!({"jsonrpc": "2.0", "id": 2, "method": "nope"}),
+        ]
+        .iter()
+        .map(|r| format!("{}\n", r))
+        .collect::<String>()
+            + "not json\n";
+
+        let out = Buffer::default();
+        let config = ScanConfig::default();
+        let db_path = dir.path().join("scan.db");
+        serve(
+            &config,
+            db_path.clone(),
+            Cursor::new(input),
+            Box::new(out.clone()),
+        )
+        .unwrap();
+
+        let messages = out.messages();
+        assert_eq!(response(&messages, 1)["result"]["total_files"], 2);
+        assert_eq!(response(&messages, 2)["error"]["code"], METHOD_NOT_FOUND);
+        assert!(messages
+            .iter()
+            .any(|m| m["id"].is_null() && m["error"]["code"] == PARSE_ERROR));
+        assert!(messages
+            .iter()
+            .any(|m| m["method"] == "progress" && m["params"]["request_id"] == 1));
+
+        // The finished scan is visible to query and stats; requests cannot
+        // point the server at other files to write
+        let victim = dir.path().join("victim");
+        let input = [
+            json!({"jsonrpc": "2.0", "id": 3, "method": "query", "params": {"media_type": "video"}}),
+            json!({"jsonrpc": "2.0", "id": 4, "method": "stats"}),
+            json!({"jsonrpc": "2.0", "id": 5, "method": "cancel"}),
+            json!({"jsonrpc": "2.0", "id": 6, "method": "scan", "params": {"roots": []}}),
+            json!({"jsonrpc": "2.0", "id": 7, "method": "scan",
+                   "params": {"roots": roots, "progress_file": victim}}),
+        ]
+        .iter()
+        .map(|r| format!("{}\n", r))
+        .collect::<String>();
+        let out = Buffer::default();
+        serve(&config, db_path, Cursor::new(input), Box::new(out.clone())).unwrap();
+
+        let messages = out.messages();
+        let files = response(&messages, 3)["result"].as_array().unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0]["name"], "a.mp4");
+        assert_eq!(response(&messages, 4)["result"]["total_files"], 2);
+        assert_eq!(response(&messages, 5)["result"]["cancelled"], false);
+        assert_eq!(response(&messages, 6)["error"]["code"], INVALID_PARAMS);
+        assert_eq!(response(&messages, 7)["error"]["code"], INVALID_PARAMS);
+        assert!(!victim.exists());
+    }
+}
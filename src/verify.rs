@@ -0,0 +1,309 @@
+//! Integrity verification against stored hashes
+//!
+//! Files whose size and mtime still match the index are re-hashed and
+//! compared with `files.hash`. A mismatch means the content changed without
+//! the metadata changing (bit rot, a bad sector, a tool restoring mtimes),
+//! and the file is marked `corrupted`. Every file looked at (including
+//! missing, changed and unreadable ones) updates `files.last_verified`, so
+//! repeated runs with a limit work through the archive oldest-verified first.
+
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::config::ScanConfig;
+use crate::db::ScanDatabase;
+use crate::error::ScanError;
+use crate::scanner::{compute_file_hash_with, is_unchanged, CancelToken};
+
+/// Which files are verified first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VerifyOrder {
+    /// Never verified first, then least recently verified
+    #[default]
+    Oldest,
+    /// A random sample
+    Random,
+}
+
+impl VerifyOrder {
+    /// Get string representation
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VerifyOrder::Oldest => "oldest",
+            VerifyOrder::Random => "random",
+        }
+    }
+}
+
+impl std::fmt::Display for VerifyOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl std::str::FromStr for VerifyOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "oldest" => Ok(VerifyOrder::Oldest),
+            "random" => Ok(VerifyOrder::Random),
+            _ => Err(format!(
+                "invalid verify order {:?} (expected oldest or random)",
+                s
+            )),
+        }
+    }
+}
+
+/// Options of a verification run
+#[derive(Debug, Clone, Default)]
+pub struct VerifyOptions {
+    pub order: VerifyOrder,
+    /// Verify at most this many files
+    pub limit: Option<u64>,
+    /// Read at most this many bytes per second
+    pub max_bytes_per_sec: Option<u64>,
+}
+
+/// Outcome of a verification run
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VerifyResult {
+    /// Files re-hashed
+    pub checked: u64,
+    /// Files whose hash matched
+    pub ok: u64,
+    /// Paths whose hash no longer matches (now marked `corrupted`)
+    pub corrupted: Vec<String>,
+    /// Files skipped because their size or mtime changed (left for the next scan)
+    pub changed: u64,
+    /// Files no longer on disk
+    pub missing: u64,
+    /// Bytes read
+    pub bytes: u64,
+    /// Files that could not be read
+    #[serde(skip)]
+    pub errors: Vec<ScanError>,
+    pub error_count: usize,
+    pub duration_ms: u64,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cancelled: bool,
+}
+
+/// Limits the average read rate by sleeping between reads
+pub(crate) struct Throttle {
+    bytes_per_sec: u64,
+    start: Instant,
+    bytes: u64,
+}
+
+impl Throttle {
+    pub(crate) fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec: bytes_per_sec.max(1),
+            start: Instant::now(),
+            bytes: 0,
+        }
+    }
+
+    /// Account for `n` bytes read, sleeping if ahead of the allowed rate
+    pub(crate) fn consume(&mut self, n: u64) {
+        self.bytes += n;
+        let due = Duration::from_secs_f64(self.bytes as f64 / self.bytes_per_sec as f64);
+        if let Some(wait) = due.checked_sub(self.start.elapsed()) {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+/// Re-hash stored files and compare them with their hashes
+///
+/// Only files with a hash are considered; a full hash is checked against a
+/// full hash and a partial one against a partial one. `config` supplies the
+/// change detection policy used to decide whether a file is unchanged.
+pub fn verify_files(
+    config: &ScanConfig,
+    db: &ScanDatabase,
+    options: &VerifyOptions,
+    cancel: Option<&CancelToken>,
+) -> Result<VerifyResult, ScanError> {
+    let start = Instant::now();
+    let candidates = db.verify_candidates(options.order, options.limit)?;
+    log::info!("Verifying {} files", candidates.len());
+
+    let mut result = VerifyResult::default();
+    let mut throttle = options.max_bytes_per_sec.map(Throttle::new);
+    for record in candidates {
+        if cancel.is_some_and(CancelToken::is_cancelled) {
+            result.cancelled = true;
+            break;
+        }
+        let path = PathBuf::from(&record.path);
+        let metadata = match std::fs::metadata(&path) {
+            Ok(m) => m,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                result.missing += 1;
+                db.record_verification_skipped(&record.path, now_secs())?;
+                continue;
+            }
+            Err(e) => {
+                result
+                    .errors
+                    .push(ScanError::io_error(Some(path), e.to_string()));
+                db.record_verification_skipped(&record.path, now_secs())?;
+                continue;
+            }
+        };
+        if !is_unchanged(&record, &metadata, config.change_detection) {
+            result.changed += 1;
+            db.record_verification_skipped(&record.path, now_secs())?;
+            continue;
+        }
+
+        let threshold = if record.is_partial_hash { 0 } else { u64::MAX };
+        let mut bytes = 0;
+        let hash = compute_file_hash_with(&path, threshold, cancel, |n| {
+            bytes += n as u64;
+            if let Some(ref mut throttle) = throttle {
+                throttle.consume(n as u64);
+            }
+        });
+        result.bytes += bytes;
+        let Some((hash, partial)) = hash else {
+            if cancel.is_some_and(CancelToken::is_cancelled) {
+                result.cancelled = true;
+                break;
+            }
+            result
+                .errors
+                .push(ScanError::hash_error(path, "failed to read file"));
+            db.record_verification_skipped(&record.path, now_secs())?;
+            continue;
+        };
+
+        result.checked += 1;
+        let ok = record.hash.as_deref() == Some(hash.as_str()) && partial == record.is_partial_hash;
+        if ok {
+            result.ok += 1;
+        } else {
+            log::warn!("Hash mismatch: {}", record.path);
+            result.corrupted.push(record.path.clone());
+        }
+        db.record_verification(&record.path, ok, now_secs())?;
+    }
+
+    result.error_count = result.errors.len();
+    result.duration_ms = start.elapsed().as_millis() as u64;
+    Ok(result)
+}
+
+fn now_secs() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::scan_incremental;
+    use std::fs;
+
+    #[test]
+    fn test_verify_detects_corruption() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        let good = dir.join("good.mp4");
+        let bad = dir.join("bad.mp4");
+        fs::write(&good, b"good content").unwrap();
+        fs::write(&bad, b"bad content!").unwrap();
+
+        let config = ScanConfig::builder()
+            .add_root(dir.clone())
+            .compute_hash(true)
+            .build();
+        let mut db = ScanDatabase::open_memory().unwrap();
+        scan_incremental(&config, &mut db);
+
+        // Same size, restored mtime: only the content differs
+        let mtime = fs::metadata(&bad).unwrap().modified().unwrap();
+        fs::write(&bad, b"bad CONTENT!").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&bad)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+
+        let options = VerifyOptions {
+            max_bytes_per_sec: Some(1 << 30),
+            ..Default::default()
+        };
+        let result = verify_files(&config, &db, &options, None).unwrap();
+        assert_eq!((result.checked, result.ok), (2, 1));
+        assert_eq!(result.corrupted, vec![bad.to_string_lossy().to_string()]);
+        let status = |path: &std::path::Path| {
+            db.get_file(&path.to_string_lossy())
+                .unwrap()
+                .unwrap()
+                .status
+        };
+        assert_eq!(status(&bad), "corrupted");
+        assert_eq!(status(&good), "new");
+
+        // Both files are now verified; a limit picks them oldest first
+        let options = VerifyOptions {
+            limit: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            verify_files(&config, &db, &options, None).unwrap().checked,
+            1
+        );
+
+        fs::remove_file(&good).unwrap();
+        let result = verify_files(&config, &db, &VerifyOptions::default(), None).unwrap();
+        assert_eq!((result.missing, result.checked), (1, 1));
+    }
+
+    #[test]
+    fn test_verify_limit_moves_past_missing_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.mp4"), b"first").unwrap();
+        fs::write(dir.path().join("b.mp4"), b"second").unwrap();
+        let config = ScanConfig::builder()
+            .add_root(dir.path().to_path_buf())
+            .compute_hash(true)
+            .build();
+        let mut db = ScanDatabase::open_memory().unwrap();
+        scan_incremental(&config, &mut db);
+        fs::remove_file(dir.path().join("a.mp4")).unwrap();
+
+        // The missing file is stamped, so the next run picks the other one
+        let options = VerifyOptions {
+            limit: Some(1),
+            ..Default::default()
+        };
+        let result = verify_files(&config, &db, &options, None).unwrap();
+        assert_eq!((result.missing, result.checked), (1, 0));
+        let result = verify_files(&config, &db, &options, None).unwrap();
+        assert_eq!((result.missing, result.checked), (0, 1));
+    }
+
+    #[test]
+    fn test_throttle() {
+        let mut throttle = Throttle::new(1000);
+        let start = Instant::now();
+        throttle.consume(50);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_verify_order_from_str() {
+        assert_eq!("Random".parse(), Ok(VerifyOrder::Random));
+        assert!("newest".parse::<VerifyOrder>().is_err());
+    }
+}
@@ -0,0 +1,765 @@
+//! Watch mode - keeps the database in sync using Linux inotify
+//!
+//! After an initial incremental scan, directory events are applied to the
+//! database as they happen. Created and modified files are only processed
+//! once they have been quiet for the debounce period, so files that are still
+//! being written are not indexed (or hashed) half-way through.
+
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use walkdir::WalkDir;
+
+use crate::config::ScanConfig;
+use crate::db::ScanDatabase;
+use crate::error::ScanError;
+use crate::models::{FileStatus, ScanResult, ScannedFile};
+use crate::rules::{FileFilter, PathFilter};
+use crate::scanner::{
+    is_symlink, is_unchanged, normalize_path, process_entry, scan_incremental, unix_now,
+    FileOutcome,
+};
+
+/// Default quiet period before a created or modified file is processed
+pub const DEFAULT_DEBOUNCE_MS: u64 = 2000;
+
+/// How long a MOVED_FROM waits for its MOVED_TO before it counts as a delete
+const MOVE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Size of the inotify read buffer
+const EVENT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Event emitted by the watcher, written as one NDJSON line
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "_type", rename_all = "lowercase")]
+pub enum WatchEvent {
+    /// Initial scan finished; later events are live changes
+    Ready {
+        total_files: u64,
+        watched_dirs: usize,
+        duration_ms: u64,
+    },
+    /// A file was created, modified, moved or deleted
+    Change(Box<FileChange>),
+}
+
+/// A single file change
+#[derive(Debug, Clone, Serialize)]
+pub struct FileChange {
+    pub status: FileStatus,
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_path: Option<String>,
+    /// File metadata for new and modified files
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<ScannedFile>,
+}
+
+impl WatchEvent {
+    fn change(status: FileStatus, path: String) -> Self {
+        WatchEvent::Change(Box::new(FileChange {
+            status,
+            path,
+            old_path: None,
+            file: None,
+        }))
+    }
+
+    /// Change events for the new, modified and deleted files of a scan
+    pub fn from_scan(result: &ScanResult) -> Vec<Self> {
+        let mut events: Vec<Self> = result
+            .files
+            .iter()
+            .map(|file| {
+                WatchEvent::Change(Box::new(FileChange {
+                    status: file.status,
+                    path: file.path.as_deref().map(normalize_path).unwrap_or_default(),
+                    old_path: file.old_path.clone(),
+                    file: Some(file.clone()),
+                }))
+            })
+            .collect();
+        events.extend(
+            result
+                .deleted_paths
+                .iter()
+                .map(|path| Self::change(FileStatus::Deleted, path.clone())),
+        );
+        events
+    }
+}
+
+/// Event copied out of the inotify buffer
+struct RawEvent {
+    wd: WatchDescriptor,
+    mask: EventMask,
+    cookie: u32,
+    name: Option<OsString>,
+}
+
+/// First half of a rename, waiting for its MOVED_TO
+struct PendingMove {
+    path: PathBuf,
+    is_dir: bool,
+    at: Instant,
+}
+
+/// Watches the configured roots and applies changes to a database
+pub struct Watcher {
+    config: ScanConfig,
+    inotify: Inotify,
+    debounce: Duration,
+    /// Watched directories with their depth below the root
+    dirs: HashMap<WatchDescriptor, (PathBuf, usize)>,
+    /// Include/exclude rules for each root
+    filters: Vec<(PathBuf, PathFilter)>,
+    /// Created or modified files waiting for the quiet period, by last event time
+    pending: HashMap<PathBuf, Instant>,
+    /// Renames waiting for their destination, by cookie
+    moves: HashMap<u32, PendingMove>,
+}
+
+impl Watcher {
+    /// Start watching every directory below the configured roots
+    pub fn new(config: &ScanConfig, debounce: Duration) -> Result<Self, ScanError> {
+        // Validate the file filters up front; they are rebuilt when applied
+        FileFilter::new(config, unix_now())?;
+
+        let mut filters = Vec::new();
+        for root in &config.roots {
+            if !root.exists() {
+                return Err(ScanError::not_found(root.clone()));
+            }
+            filters.push((root.clone(), PathFilter::new(config, root)?));
+        }
+
+        let mut watcher = Self {
+            config: config.clone(),
+            inotify: Inotify::init()?,
+            debounce,
+            dirs: HashMap::new(),
+            filters,
+            pending: HashMap::new(),
+            moves: HashMap::new(),
+        };
+        for root in &config.roots {
+            watcher.watch_tree(root, 0);
+        }
+        Ok(watcher)
+    }
+
+    /// Number of directories being watched
+    pub fn watched_dirs(&self) -> usize {
+        self.dirs.len()
+    }
+
+    /// Print change events as NDJSON until writing them or reading inotify fails
+    pub fn run(&mut self, db: &mut ScanDatabase, out: &mut dyn Write) -> Result<(), ScanError> {
+        loop {
+            for event in self.poll(db, Duration::from_secs(1))? {
+                write_event(out, &event)?;
+            }
+            out.flush()?;
+        }
+    }
+
+    /// Wait up to `timeout` for events and apply everything that is due
+    ///
+    /// A change that cannot be applied to the database is logged and skipped,
+    /// so one failing event does not stop the watcher.
+    pub fn poll(
+        &mut self,
+        db: &mut ScanDatabase,
+        timeout: Duration,
+    ) -> Result<Vec<WatchEvent>, ScanError> {
+        let wait = self
+            .next_deadline()
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+            .map_or(timeout, |until| until.min(timeout));
+
+        let mut events = Vec::new();
+        if self.wait_readable(wait)? {
+            for raw in self.read_events()? {
+                match self.handle(db, raw) {
+                    Ok(changes) => events.extend(changes),
+                    Err(e) => log::error!("Failed to apply change: {}", e),
+                }
+            }
+        }
+        events.extend(self.expire_moves(db));
+        match self.flush_pending(db) {
+            Ok(changes) => events.extend(changes),
+            Err(e) => log::error!("Failed to index changed files: {}", e),
+        }
+        Ok(events)
+    }
+
+    /// Earliest time a pending file or rename becomes due
+    fn next_deadline(&self) -> Option<Instant> {
+        let files = self.pending.values().map(|at| *at + self.debounce);
+        let moves = self.moves.values().map(|m| m.at + MOVE_TIMEOUT);
+        files.chain(moves).min()
+    }
+
+    /// Wait for the inotify descriptor to become readable
+    fn wait_readable(&self, timeout: Duration) -> Result<bool, ScanError> {
+        let mut fds = [libc::pollfd {
+            fd: self.inotify.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        }];
+        let timeout_ms = timeout.as_millis().min(i32::MAX as u128) as libc::c_int;
+        // SAFETY: `fds` is a valid array of one pollfd for the duration of the call
+        let ready = unsafe { libc::poll(fds.as_mut_ptr(), 1, timeout_ms) };
+        if ready < 0 {
+            let error = std::io::Error::last_os_error();
+            if error.kind() == std::io::ErrorKind::Interrupted {
+                return Ok(false);
+            }
+            return Err(error.into());
+        }
+        Ok(ready > 0)
+    }
+
+    /// Read all queued events without blocking
+    fn read_events(&mut self) -> Result<Vec<RawEvent>, ScanError> {
+        let mut buffer = vec![0u8; EVENT_BUFFER_SIZE];
+        let mut raw = Vec::new();
+        loop {
+            let events = match self.inotify.read_events(&mut buffer) {
+                Ok(events) => events,
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e.into()),
+            };
+            raw.extend(events.map(|event| RawEvent {
+                wd: event.wd,
+                mask: event.mask,
+                cookie: event.cookie,
+                name: event.name.map(|n| n.to_os_string()),
+            }));
+        }
+        Ok(raw)
+    }
+
+    /// Apply a single inotify event
+    fn handle(
+        &mut self,
+        db: &mut ScanDatabase,
+        raw: RawEvent,
+    ) -> Result<Vec<WatchEvent>, ScanError> {
+        if raw.mask.contains(EventMask::Q_OVERFLOW) {
+            log::warn!("inotify queue overflowed, rescanning");
+            // Directories created while events were lost are not watched yet
+            for root in self.config.roots.clone() {
+                self.watch_tree(&root, 0);
+            }
+            return Ok(WatchEvent::from_scan(&scan_incremental(&self.config, db)));
+        }
+        if raw.mask.contains(EventMask::IGNORED) {
+            self.dirs.remove(&raw.wd);
+            return Ok(Vec::new());
+        }
+        let (Some((dir, depth)), Some(name)) = (self.dirs.get(&raw.wd).cloned(), raw.name) else {
+            return Ok(Vec::new());
+        };
+        let path = dir.join(name);
+        let now = Instant::now();
+
+        if raw.mask.contains(EventMask::ISDIR) {
+            if raw.mask.contains(EventMask::MOVED_FROM) {
+                self.moves.insert(
+                    raw.cookie,
+                    PendingMove {
+                        path,
+                        is_dir: true,
+                        at: now,
+                    },
+                );
+            } else if raw.mask.contains(EventMask::MOVED_TO) {
+                match self.moves.remove(&raw.cookie) {
+                    Some(from) if from.is_dir && self.accepts_dir(&path) => {
+                        return self.move_dir(db, &from.path, &path);
+                    }
+                    Some(from) => {
+                        let events = self.remove_dir(db, &from.path)?;
+                        self.add_dir(&path, depth + 1);
+                        return Ok(events);
+                    }
+                    None => self.add_dir(&path, depth + 1),
+                }
+            } else if raw.mask.contains(EventMask::CREATE) {
+                self.add_dir(&path, depth + 1);
+            } else if raw.mask.contains(EventMask::DELETE) {
+                return self.remove_dir(db, &path);
+            }
+            return Ok(Vec::new());
+        }
+
+        if raw.mask.contains(EventMask::DELETE) {
+            self.pending.remove(&path);
+            return self.remove_file(db, &path);
+        }
+        if raw.mask.contains(EventMask::MOVED_FROM) {
+            self.pending.remove(&path);
+            self.moves.insert(
+                raw.cookie,
+                PendingMove {
+                    path,
+                    is_dir: false,
+                    at: now,
+                },
+            );
+            return Ok(Vec::new());
+        }
+        if raw.mask.contains(EventMask::MOVED_TO) {
+            if let Some(from) = self.moves.remove(&raw.cookie) {
+                let old_path = normalize_path(&from.path);
+                if self.accepts_file(&path) && db.get_file(&old_path)?.is_some() {
+                    let new_path = normalize_path(&path);
+                    self.pending.remove(&path);
+                    // Renaming over an indexed file (`mv -f`, save-via-rename) replaces it
+                    let mut events = Vec::new();
+                    if new_path != old_path && db.get_file(&new_path)?.is_some() {
+                        events.push(WatchEvent::change(FileStatus::Deleted, new_path.clone()));
+                    }
+                    db.rename_file(&old_path, &new_path)?;
+                    events.push(WatchEvent::Change(Box::new(FileChange {
+                        status: FileStatus::Moved,
+                        path: new_path,
+                        old_path: Some(old_path),
+                        file: None,
+                    })));
+                    return Ok(events);
+                }
+                // Renamed from an unindexed name (e.g. `.part`) or out of the rules
+                let events = self.remove_file(db, &from.path)?;
+                if self.accepts_file(&path) {
+                    self.pending.insert(path, now);
+                }
+                return Ok(events);
+            }
+        }
+        if self.accepts_file(&path) {
+            self.pending.insert(path, now);
+        }
+        Ok(Vec::new())
+    }
+
+    /// Treat renames whose destination never arrived as deletes
+    fn expire_moves(&mut self, db: &mut ScanDatabase) -> Vec<WatchEvent> {
+        let now = Instant::now();
+        let expired: Vec<u32> = self
+            .moves
+            .iter()
+            .filter(|(_, m)| now.duration_since(m.at) >= MOVE_TIMEOUT)
+            .map(|(cookie, _)| *cookie)
+            .collect();
+
+        let mut events = Vec::new();
+        for cookie in expired {
+            if let Some(from) = self.moves.remove(&cookie) {
+                let removed = if from.is_dir {
+                    self.remove_dir(db, &from.path)
+                } else {
+                    self.remove_file(db, &from.path)
+                };
+                match removed {
+                    Ok(changes) => events.extend(changes),
+                    Err(e) => log::error!("Failed to remove {:?}: {}", from.path, e),
+                }
+            }
+        }
+        events
+    }
+
+    /// Index files that have been quiet for the debounce period
+    fn flush_pending(&mut self, db: &mut ScanDatabase) -> Result<Vec<WatchEvent>, ScanError> {
+        let now = Instant::now();
+        let due: Vec<PathBuf> = self
+            .pending
+            .iter()
+            .filter(|(_, at)| now.duration_since(**at) >= self.debounce)
+            .map(|(path, _)| path.clone())
+            .collect();
+        if due.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Relative mtime limits are resolved against the current time
+        let filter = FileFilter::new(&self.config, unix_now())?;
+        let mut files = Vec::new();
+        for path in due {
+            self.pending.remove(&path);
+            let Ok(metadata) = std::fs::metadata(&path) else {
+                continue;
+            };
+            let record = db.get_file(&normalize_path(&path))?;
+            if record
+                .as_ref()
+                .is_some_and(|r| is_unchanged(r, &metadata, self.config.change_detection))
+            {
+                continue;
+            }
+            if let FileOutcome::Scanned(file, _) =
+                process_entry(&path, is_symlink(&path), &self.config, &filter, None)
+            {
+                let status = if record.is_some() {
+                    FileStatus::Modified
+                } else {
+                    FileStatus::New
+                };
+                files.push(file.with_status(status));
+            }
+        }
+
+        db.upsert_files(&files)?;
+        Ok(files
+            .into_iter()
+            .map(|file| {
+                WatchEvent::Change(Box::new(FileChange {
+                    status: file.status,
+                    path: file.path.as_deref().map(normalize_path).unwrap_or_default(),
+                    old_path: None,
+                    file: Some(file),
+                }))
+            })
+            .collect())
+    }
+
+    /// Watch a new directory tree and queue the files already inside it
+    fn add_dir(&mut self, path: &Path, depth: usize) {
+        if depth > self.config.effective_max_depth() || !self.accepts_dir(path) {
+            return;
+        }
+        let now = Instant::now();
+        for file in self.watch_tree(path, depth) {
+            self.pending.insert(file, now);
+        }
+    }
+
+    /// Add watches below `dir` (at `depth` below its root), returning the files found
+    fn watch_tree(&mut self, dir: &Path, depth: usize) -> Vec<PathBuf> {
+        let max_depth = self.config.effective_max_depth();
+        let mut dirs = Vec::new();
+        let mut files = Vec::new();
+
+        let walker = WalkDir::new(dir)
+            .max_depth(max_depth - depth)
+            .follow_links(self.config.follow_symlinks)
+            .same_file_system(self.config.one_file_system)
+            .into_iter()
+            .filter_entry(|entry| {
+                if entry.depth() == 0 {
+                    return true;
+                }
+                if entry.file_type().is_dir() {
+                    self.accepts_dir(entry.path())
+                } else {
+                    self.accepts_file(entry.path())
+                }
+            });
+        for entry in walker.filter_map(|e| e.ok()) {
+            let entry_depth = depth + entry.depth();
+            if entry.file_type().is_dir() {
+                if entry_depth < max_depth {
+                    dirs.push((entry.into_path(), entry_depth));
+                }
+            } else {
+                files.push(entry.into_path());
+            }
+        }
+
+        let mut watches = self.inotify.watches();
+        let mask = WatchMask::CREATE
+            | WatchMask::MODIFY
+            | WatchMask::CLOSE_WRITE
+            | WatchMask::DELETE
+            | WatchMask::MOVED_FROM
+            | WatchMask::MOVED_TO;
+        for (path, depth) in dirs {
+            match watches.add(&path, mask) {
+                Ok(wd) => {
+                    self.dirs.insert(wd, (path, depth));
+                }
+                Err(e) if e.raw_os_error() == Some(libc::ENOSPC) => {
+                    log::warn!(
+                        "Cannot watch {:?}: inotify watch limit reached (see fs.inotify.max_user_watches)",
+                        path
+                    );
+                }
+                Err(e) => log::debug!("Cannot watch {:?}: {}", path, e),
+            }
+        }
+        files
+    }
+
+    /// Apply a directory rename within the watched tree
+    fn move_dir(
+        &mut self,
+        db: &mut ScanDatabase,
+        from: &Path,
+        to: &Path,
+    ) -> Result<Vec<WatchEvent>, ScanError> {
+        for (path, _) in self.dirs.values_mut() {
+            if let Ok(rest) = path.strip_prefix(from) {
+                *path = to.join(rest);
+            }
+        }
+
+        let old_dir = normalize_path(from);
+        let new_dir = normalize_path(to);
+        let old_paths = db.paths_under(&old_dir)?;
+        // Files still indexed at the destination were replaced by the rename
+        let replaced = db.paths_under(&new_dir)?;
+        db.rename_dir(&old_dir, &new_dir)?;
+        let deleted = replaced
+            .into_iter()
+            .map(|path| WatchEvent::change(FileStatus::Deleted, path));
+        let moved = old_paths.into_iter().map(|old_path| {
+            let path = format!("{}{}", new_dir, &old_path[old_dir.len()..]);
+            WatchEvent::Change(Box::new(FileChange {
+                status: FileStatus::Moved,
+                path,
+                old_path: Some(old_path),
+                file: None,
+            }))
+        });
+        Ok(deleted.chain(moved).collect())
+    }
+
+    /// Stop watching a removed directory and delete its files from the index
+    fn remove_dir(
+        &mut self,
+        db: &mut ScanDatabase,
+        path: &Path,
+    ) -> Result<Vec<WatchEvent>, ScanError> {
+        let removed: Vec<WatchDescriptor> = self
+            .dirs
+            .iter()
+            .filter(|(_, (dir, _))| dir.starts_with(path))
+            .map(|(wd, _)| wd.clone())
+            .collect();
+        let mut watches = self.inotify.watches();
+        for wd in removed {
+            self.dirs.remove(&wd);
+            // Fails harmlessly when the kernel already dropped the watch
+            let _ = watches.remove(wd);
+        }
+        self.pending.retain(|pending, _| !pending.starts_with(path));
+
+        let paths = db.paths_under(&normalize_path(path))?;
+        db.move_to_deleted(&paths)?;
+        Ok(paths
+            .into_iter()
+            .map(|path| WatchEvent::change(FileStatus::Deleted, path))
+            .collect())
+    }
+
+    /// Delete a removed file from the index
+    fn remove_file(
+        &mut self,
+        db: &mut ScanDatabase,
+        path: &Path,
+    ) -> Result<Vec<WatchEvent>, ScanError> {
+        let path = normalize_path(path);
+        if db.get_file(&path)?.is_none() {
+            return Ok(Vec::new());
+        }
+        db.move_to_deleted(std::slice::from_ref(&path))?;
+        Ok(vec![WatchEvent::change(FileStatus::Deleted, path)])
+    }
+
+    /// Rules of the root containing `path`
+    fn filter_for(&self, path: &Path) -> Option<&PathFilter> {
+        self.filters
+            .iter()
+            .filter(|(root, _)| path.starts_with(root))
+            .max_by_key(|(root, _)| root.as_os_str().len())
+            .map(|(_, filter)| filter)
+    }
+
+    /// Check if a directory below a root should be watched
+    fn accepts_dir(&self, path: &Path) -> bool {
+        let ignored = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|name| self.config.should_ignore_dir(name));
+        !ignored
+            && self
+                .filter_for(path)
+                .is_some_and(|filter| !filter.is_excluded(path, true))
+    }
+
+    /// Check if a file below a root passes the name and path rules
+    fn accepts_file(&self, path: &Path) -> bool {
+        let ignored = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|name| self.config.should_ignore_file(name));
+        !ignored
+            && self
+                .filter_for(path)
+                .is_some_and(|filter| !filter.is_excluded(path, false) && filter.is_included(path))
+    }
+}
+
+/// Write one event as an NDJSON line
+fn write_event(out: &mut dyn Write, event: &WatchEvent) -> Result<(), ScanError> {
+    let line = serde_json::to_string(event)
+        .map_err(|e| ScanError::io_error(None, format!("Failed to encode event: {}", e)))?;
+    writeln!(out, "{}", line)?;
+    Ok(())
+}
+
+/// Run an initial incremental scan, then apply live changes until an error occurs
+///
+/// Watches are set up before the scan, so changes made while it runs are not lost.
+pub fn watch(
+    config: &ScanConfig,
+    db: &mut ScanDatabase,
+    debounce: Duration,
+    out: &mut dyn Write,
+) -> Result<(), ScanError> {
+    let mut watcher = Watcher::new(config, debounce)?;
+    let result = scan_incremental(config, db);
+    for event in WatchEvent::from_scan(&result) {
+        write_event(out, &event)?;
+    }
+    write_event(
+        out,
+        &WatchEvent::Ready {
+            total_files: result.total_files,
+            watched_dirs: watcher.watched_dirs(),
+            duration_ms: result.duration_ms,
+        },
+    )?;
+    out.flush()?;
+
+    watcher.run(db, out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Poll until at least `count` events arrived (or a few seconds passed)
+    fn collect(watcher: &mut Watcher, db: &mut ScanDatabase, count: usize) -> Vec<WatchEvent> {
+        let mut events = Vec::new();
+        let start = Instant::now();
+        while events.len() < count && start.elapsed() < Duration::from_secs(5) {
+            events.extend(watcher.poll(db, Duration::from_millis(50)).unwrap());
+        }
+        events
+    }
+
+    fn changes(events: &[WatchEvent]) -> Vec<(FileStatus, String)> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                WatchEvent::Change(change) => Some((change.status, change.path.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_watch_applies_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir(root.join("shows")).unwrap();
+        let config = ScanConfig::builder().add_root(root.to_path_buf()).build();
+        let mut db = ScanDatabase::open_memory().unwrap();
+        let mut watcher = Watcher::new(&config, Duration::from_millis(100)).unwrap();
+        let path = |name: &str| normalize_path(&root.join(name));
+
+        // Created files are indexed once written; other extensions are ignored
+        fs::write(root.join("shows/ep1.mkv"), b"data").unwrap();
+        fs::write(root.join("shows/notes.txt"), b"text").unwrap();
+        let events = collect(&mut watcher, &mut db, 1);
+        assert_eq!(
+            changes(&events),
+            vec![(FileStatus::New, path("shows/ep1.mkv"))]
+        );
+        assert!(db.get_file(&path("shows/ep1.mkv")).unwrap().is_some());
+
+        // Renames are recorded as moves
+        fs::rename(root.join("shows/ep1.mkv"), root.join("shows/ep01.mkv")).unwrap();
+        let events = collect(&mut watcher, &mut db, 1);
+        assert_eq!(
+            changes(&events),
+            vec![(FileStatus::Moved, path("shows/ep01.mkv"))]
+        );
+
+        // New directories are watched, and directory renames move their files
+        fs::create_dir(root.join("new")).unwrap();
+        watcher.poll(&mut db, Duration::from_millis(50)).unwrap();
+        fs::write(root.join("new/clip.mp4"), b"clip").unwrap();
+        collect(&mut watcher, &mut db, 1);
+        fs::rename(root.join("new"), root.join("clips")).unwrap();
+        let events = collect(&mut watcher, &mut db, 1);
+        assert_eq!(
+            changes(&events),
+            vec![(FileStatus::Moved, path("clips/clip.mp4"))]
+        );
+
+        // Deleted files are moved to deleted_files
+        fs::remove_file(root.join("clips/clip.mp4")).unwrap();
+        let events = collect(&mut watcher, &mut db, 1);
+        assert_eq!(
+            changes(&events),
+            vec![(FileStatus::Deleted, path("clips/clip.mp4"))]
+        );
+        assert!(db.get_file(&path("clips/clip.mp4")).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_watch_rename_over_indexed_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join("old.mkv"), b"old").unwrap();
+        fs::write(root.join("new.mkv"), b"new").unwrap();
+        let config = ScanConfig::builder().add_root(root.to_path_buf()).build();
+        let mut db = ScanDatabase::open_memory().unwrap();
+        scan_incremental(&config, &mut db);
+        let mut watcher = Watcher::new(&config, Duration::from_millis(100)).unwrap();
+        let path = |name: &str| normalize_path(&root.join(name));
+
+        // `mv -f new.mkv old.mkv` replaces the indexed target
+        fs::rename(root.join("new.mkv"), root.join("old.mkv")).unwrap();
+        let events = collect(&mut watcher, &mut db, 2);
+        assert_eq!(
+            changes(&events),
+            vec![
+                (FileStatus::Deleted, path("old.mkv")),
+                (FileStatus::Moved, path("old.mkv")),
+            ]
+        );
+        assert_eq!(db.get_file(&path("old.mkv")).unwrap().unwrap().size, 3);
+        assert!(db.get_file(&path("new.mkv")).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_watch_debounces_partial_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let config = ScanConfig::builder().add_root(root.to_path_buf()).build();
+        let mut db = ScanDatabase::open_memory().unwrap();
+        let mut watcher = Watcher::new(&config, Duration::from_millis(300)).unwrap();
+
+        // Downloads written under a temporary name, then renamed into place
+        fs::write(root.join("movie.mp4.part"), b"partial").unwrap();
+        let events = watcher.poll(&mut db, Duration::from_millis(50)).unwrap();
+        assert!(events.is_empty());
+        fs::rename(root.join("movie.mp4.part"), root.join("movie.mp4")).unwrap();
+
+        let events = collect(&mut watcher, &mut db, 1);
+        let expected = normalize_path(&root.join("movie.mp4"));
+        assert_eq!(changes(&events), vec![(FileStatus::New, expected)]);
+    }
+}